@@ -0,0 +1,78 @@
+//! Rename files in a temporary directory to upper case, collecting results by hooks.
+//!
+//! cargo run --example collect_results
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use clap::Parser;
+use moove::*;
+
+/// Hooks collecting results of operations, skipping hidden files.
+#[derive(Debug, Default)]
+struct Collector {
+    results: Mutex<Vec<(String, Option<String>)>>,
+}
+
+impl Hooks for Collector {
+    fn on_plan(&self, operations: &[Operation]) {
+        println!("{} operations planned", operations.len());
+    }
+
+    fn on_before(&self, o: &Operation) -> HookDecision {
+        if o.src
+            .path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            HookDecision::Skip
+        } else {
+            HookDecision::Proceed
+        }
+    }
+
+    fn on_after(&self, o: &Operation, result: &Result<()>) {
+        if let Ok(mut results) = self.results.lock() {
+            results.push((
+                o.dst.text.to_owned(),
+                result.as_ref().err().map(|err| err.to_string()),
+            ));
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let dir = std::env::temp_dir().join("moove").join("example");
+    std::fs::create_dir_all(&dir)?;
+    for name in ["a.txt", "b.txt", ".hidden"] {
+        std::fs::write(dir.join(name), name)?;
+    }
+    let collector = Arc::new(Collector::default());
+    let mut args = CommandLine::parse_from(["moove", "--with-hidden", "--no-lock"]);
+    args.paths = vec![dir.to_string_lossy().to_string()];
+    args.reporter = Some(Arc::new(QuietReporter));
+    args.hooks = Some(collector.clone());
+    let sources = sources_from(&args)?;
+    let lines: Vec<String> = sources
+        .iter()
+        .map(|src| {
+            let name = src.path.file_name().unwrap_or_default().to_string_lossy();
+            src.path
+                .with_file_name(name.to_uppercase())
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let plan = plan_from_lines(&sources, &lines, &args)?;
+    let report = execute_operations(&plan.operations, &args, None)?;
+    for (dst, error) in collector.results.lock().unwrap().iter() {
+        match error {
+            Some(error) => println!("Failed: {} {}", dst, error),
+            None => println!("Done: {}", dst),
+        }
+    }
+    println!("{}", report);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
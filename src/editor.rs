@@ -0,0 +1,90 @@
+//! Editors of the text of sources, injected by `CommandLine::text_editor`.
+
+use anyhow::Result;
+
+use crate::{
+    open_editor, prompt_collision, prompt_redo, prompt_resolution, shell_split, CommandLine,
+    MooveError, Operation, Resolution,
+};
+
+/// Editor of the text listing sources, which returns the edited text.
+///
+/// Closures of `Fn(&str) -> Result<String>` are editors as they are,
+/// e.g. to transform the text deterministically in tests.
+/// Failing with `Failure::Aborted` in the context aborts like an editor exiting with error.
+///
+/// Unless `--oops`, invalid operations are resolved by the editor,
+/// which edits the text with errors again by default.
+pub trait Editor: Send + Sync {
+    fn edit(&self, text: &str) -> Result<String>;
+
+    /// How to resolve the invalid operation, which is reported already.
+    fn resolve(
+        &self,
+        _args: &CommandLine,
+        _o: &Operation,
+        _error: &anyhow::Error,
+    ) -> Result<Resolution> {
+        Ok(Resolution::Edit)
+    }
+
+    /// Whether to edit again for the error not of any line, which is reported already,
+    /// or to abort otherwise.
+    fn redo(&self, _args: &CommandLine, _error: &anyhow::Error) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+impl std::fmt::Debug for dyn Editor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Editor")
+    }
+}
+
+impl<F> Editor for F
+where
+    F: Fn(&str) -> Result<String> + Send + Sync,
+{
+    fn edit(&self, text: &str) -> Result<String> {
+        self(text)
+    }
+}
+
+/// Editor spawned as a process, `--editor` or VISUAL or EDITOR.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalEditor {
+    pub editor: Option<String>,
+    pub editor_args: Vec<String>,
+}
+
+impl ExternalEditor {
+    pub fn from_args(args: &CommandLine) -> ExternalEditor {
+        ExternalEditor {
+            editor: args.editor.clone(),
+            editor_args: shell_split(args.editor_args.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+/// Resolutions are asked to the user on the terminal.
+impl Editor for ExternalEditor {
+    fn edit(&self, text: &str) -> Result<String> {
+        open_editor(text, self.editor.as_deref(), &self.editor_args)
+    }
+
+    fn resolve(
+        &self,
+        args: &CommandLine,
+        o: &Operation,
+        error: &anyhow::Error,
+    ) -> Result<Resolution> {
+        match error.downcast_ref::<MooveError>() {
+            Some(MooveError::DestinationExists(_)) => prompt_collision(args, !o.dst.path.is_dir()),
+            _ => prompt_resolution(args),
+        }
+    }
+
+    fn redo(&self, args: &CommandLine, _error: &anyhow::Error) -> Result<bool> {
+        prompt_redo(args)
+    }
+}
@@ -0,0 +1,512 @@
+//! Errors classified by their causes.
+
+use std::path::PathBuf;
+
+use colored::*;
+
+/// Invalid sources or operations, and failures of the filesystem with their causes.
+#[derive(Debug)]
+pub enum MooveError {
+    RootSource(PathBuf),
+    EmptyDirectory(PathBuf),
+    DuplicateSource(PathBuf),
+    /// Source is the same file as other source through a different path, e.g. a symbolic or hard link.
+    AliasedSource {
+        path: PathBuf,
+        other: PathBuf,
+    },
+    LineMismatch {
+        expected: usize,
+        got: usize,
+    },
+    MissingFileName {
+        dst: String,
+        src: String,
+    },
+    DestinationInsideSource {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    DuplicateDestination(PathBuf),
+    /// Destination differs from other destination only in case or Unicode normalization,
+    /// colliding on filesystems ignoring the difference.
+    EquivalentDestination {
+        dst: PathBuf,
+        other: PathBuf,
+    },
+    /// Destination contains other destination.
+    NestedDestination(PathBuf),
+    DestinationExists(PathBuf),
+    /// Ancestor of destination is moved away by other operation.
+    VacatedAncestor {
+        dst: PathBuf,
+        src: PathBuf,
+    },
+    /// Destination is a listed source whose line is unchanged.
+    UnmovedSource(PathBuf),
+    /// Destination is a symbolic link, even if it is broken.
+    DestinationLinkExists(PathBuf),
+    /// Name in destination is invalid on Windows, with the reason like the offending character.
+    InvalidWindowsName {
+        dst: PathBuf,
+        name: String,
+        reason: String,
+    },
+    /// Name in destination is longer than the limit of the filesystem or `--max-name-length`.
+    TooLongName {
+        dst: PathBuf,
+        name: String,
+        length: usize,
+        max: usize,
+    },
+    /// Absolute path of destination is longer than the limit of the platform.
+    TooLongPath {
+        dst: PathBuf,
+        length: usize,
+        max: usize,
+    },
+    /// Ancestor of destination is a file.
+    FileAncestor(PathBuf),
+    /// Directory to contain destination is not writable.
+    PermissionDenied(PathBuf),
+    /// Parent directory of destination is missing with `--no-create-dir`.
+    MissingParent(PathBuf),
+    /// Destination does not match the source after the operation, checked by `--verify`.
+    VerificationFailed(PathBuf),
+    /// File name cannot be truncated without its extension.
+    TooLongExtension {
+        name: String,
+        max: usize,
+    },
+    /// No source has the prefix to strip.
+    MissingPrefix(String),
+    /// Operation did not finish within `--timeout`.
+    Timeout {
+        src: PathBuf,
+        timeout: std::time::Duration,
+    },
+    /// Removal cannot be inverted, e.g. to roll back the inverse of copy.
+    IrreversibleRemoval(PathBuf),
+    /// Options contradict each other, e.g. given by the library rather than clap.
+    ConflictingFlags(String),
+    /// Source cannot be accessed, e.g. missing or without permission.
+    SourceAccess {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Path cannot be made absolute.
+    Normalize {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Path is not valid UTF-8 on a platform requiring it.
+    NonUtf8Path(PathBuf),
+    CurrentDirectory(std::io::Error),
+    CreateDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Source cannot be moved or copied into the directory.
+    Transfer {
+        src: PathBuf,
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        source: std::io::Error,
+    },
+    Remove {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    ReadXattrs {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    WriteXattr {
+        name: std::ffi::OsString,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl std::fmt::Display for MooveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MooveError::RootSource(path) => write!(
+                f,
+                "Source should not be the root directory. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::EmptyDirectory(path) => write!(
+                f,
+                "Directory is empty. {}\n\
+                 Use --directory for the directory itself.",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::DuplicateSource(path) => write!(
+                f,
+                "Duplicated source. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::AliasedSource { path, other } => write!(
+                f,
+                "Duplicated source through a different path. {} is the same as {}",
+                path.to_string_lossy().yellow().underline(),
+                other.to_string_lossy().yellow().underline()
+            ),
+            MooveError::LineMismatch { expected, got } => write!(
+                f,
+                "Number of lines {} does not match the original one {}",
+                got.to_string().yellow(),
+                expected.to_string().yellow()
+            ),
+            MooveError::MissingFileName { dst, src } => write!(
+                f,
+                "Missing file name. {} for {}",
+                dst.yellow().underline(),
+                src.underline()
+            ),
+            MooveError::DestinationInsideSource { src, dst } => write!(
+                f,
+                "Destination should not be inside the source. {} for {}",
+                dst.to_string_lossy().yellow().underline(),
+                src.to_string_lossy().underline()
+            ),
+            MooveError::DuplicateDestination(path) => write!(
+                f,
+                "Duplicated destination. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::EquivalentDestination { dst, other } => write!(
+                f,
+                "Destination is the same as other destination on the filesystem. {} and {}\n\
+                 Use --case-sensitive if the filesystem is case-sensitive.",
+                dst.to_string_lossy().yellow().underline(),
+                other.to_string_lossy().yellow().underline()
+            ),
+            MooveError::NestedDestination(path) => write!(
+                f,
+                "Destination should not be included in other destination. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::DestinationExists(path) => write!(
+                f,
+                "Destination exists. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::VacatedAncestor { dst, src } => write!(
+                f,
+                "Destination should not be inside a directory moved away. {} inside {}",
+                dst.to_string_lossy().yellow().underline(),
+                src.to_string_lossy().underline()
+            ),
+            MooveError::UnmovedSource(path) => write!(
+                f,
+                "Destination is another listed file that is not being moved. {}\n\
+                 Edit its line too or choose a different name.",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::DestinationLinkExists(path) => write!(
+                f,
+                "Destination exists as a symbolic link. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::InvalidWindowsName { dst, name, reason } => write!(
+                f,
+                "Name is invalid on Windows with {}. {} in {}",
+                reason.yellow(),
+                name.yellow().underline(),
+                dst.to_string_lossy().underline()
+            ),
+            MooveError::TooLongName {
+                dst,
+                name,
+                length,
+                max,
+            } => write!(
+                f,
+                "Name is too long, {} of at most {} {}. {} in {}",
+                length.to_string().yellow(),
+                max.to_string().yellow(),
+                crate::NAME_LENGTH_UNIT,
+                name.yellow().underline(),
+                dst.to_string_lossy().underline()
+            ),
+            MooveError::TooLongPath { dst, length, max } => write!(
+                f,
+                "Path is too long, {} of at most {} {}. {}",
+                length.to_string().yellow(),
+                max.to_string().yellow(),
+                crate::NAME_LENGTH_UNIT,
+                dst.to_string_lossy().yellow().underline()
+            ),
+            MooveError::FileAncestor(path) => write!(
+                f,
+                "Ancestor of destination should not be a file.\n\
+                 Destination: {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::PermissionDenied(path) => write!(
+                f,
+                "Directory of destination is not writable. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::MissingParent(path) => write!(
+                f,
+                "Directory of destination does not exist. {}\n\
+                 Remove --no-create-dir to create it.",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::VerificationFailed(path) => write!(
+                f,
+                "Destination does not match the source after the operation. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::TooLongExtension { name, max } => write!(
+                f,
+                "Extension is too long to truncate the file name to {} bytes. {}",
+                max.to_string().yellow(),
+                name.yellow().underline()
+            ),
+            MooveError::MissingPrefix(prefix) => write!(
+                f,
+                "No source has the prefix. {}\n\
+                 Use --ignore-missing-prefix to ignore it.",
+                prefix.yellow().underline()
+            ),
+            MooveError::Timeout { src, timeout } => write!(
+                f,
+                "Operation timed out after {}. {}",
+                humantime::format_duration(*timeout).to_string().yellow(),
+                src.to_string_lossy().yellow().underline()
+            ),
+            MooveError::IrreversibleRemoval(path) => write!(
+                f,
+                "Removal cannot be undone. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::ConflictingFlags(flags) => {
+                write!(f, "Options cannot be used together. {}", flags.yellow())
+            }
+            MooveError::SourceAccess { path, .. } => write!(
+                f,
+                "Failed to access {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::Normalize { path, .. } => write!(
+                f,
+                "Failed to normalize path. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::NonUtf8Path(path) => write!(
+                f,
+                "Failed to convert path to UTF-8. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::CurrentDirectory(_) => write!(f, "Failed to get current directory."),
+            MooveError::CreateDirectory { path, .. } => write!(
+                f,
+                "Failed to create directory. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::Transfer { src, dir, .. } => write!(
+                f,
+                "Failed to move {} to {}",
+                src.to_string_lossy().yellow().underline(),
+                dir.to_string_lossy().yellow().underline()
+            ),
+            MooveError::Rename { from, to, .. } => write!(
+                f,
+                "Failed to rename {} to {}",
+                from.to_string_lossy().yellow().underline(),
+                to.to_string_lossy().yellow().underline()
+            ),
+            MooveError::Remove { path, .. } => write!(
+                f,
+                "Failed to remove {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::ReadXattrs { path, .. } => write!(
+                f,
+                "Failed to read extended attributes. {}",
+                path.to_string_lossy().yellow().underline()
+            ),
+            MooveError::WriteXattr { name, path, .. } => write!(
+                f,
+                "Failed to write extended attribute {} of {}",
+                name.to_string_lossy().yellow(),
+                path.to_string_lossy().yellow().underline()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MooveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MooveError::SourceAccess { source, .. }
+            | MooveError::Normalize { source, .. }
+            | MooveError::CurrentDirectory(source)
+            | MooveError::CreateDirectory { source, .. }
+            | MooveError::Transfer { source, .. }
+            | MooveError::Rename { source, .. }
+            | MooveError::Remove { source, .. }
+            | MooveError::ReadXattrs { source, .. }
+            | MooveError::WriteXattr { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Class of failure, which determines the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Invalid or colliding operations
+    Validation,
+    /// Filesystem error during operations
+    Filesystem,
+    /// Aborted by user
+    Aborted,
+    /// Invalid operations found by `--check`
+    Checked,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Validation => write!(f, "Invalid operations."),
+            Failure::Filesystem => write!(f, "Failed to operate."),
+            Failure::Aborted => write!(f, "Aborted."),
+            Failure::Checked => write!(f, "Invalid operations found."),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exit code for the error, described in `--help`.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    if let Some(rollback) = err.downcast_ref::<RollbackError>() {
+        return if rollback.restored { 3 } else { 4 };
+    }
+    if err.downcast_ref::<LockError>().is_some() {
+        return 5;
+    }
+    if err.downcast_ref::<InterruptedError>().is_some() {
+        return 130;
+    }
+    match err.downcast_ref::<Failure>() {
+        Some(Failure::Validation) | None => 2,
+        Some(Failure::Filesystem) => 3,
+        Some(Failure::Aborted) => 4,
+        Some(Failure::Checked) => 1,
+    }
+}
+
+/// Lock of the directory is held by another invocation.
+#[derive(Debug)]
+pub struct LockError {
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Another moove is running in the same directory. Retry later or specify --wait-lock. {}",
+            self.path.to_string_lossy().yellow().underline()
+        )
+    }
+}
+
+impl std::error::Error for LockError {}
+
+/// Result of rolling back operations by `--atomic`.
+#[derive(Debug)]
+pub struct RollbackError {
+    /// Whether all completed operations were undone.
+    pub restored: bool,
+    /// State file of operations which failed to be undone.
+    pub recovery_file: Option<PathBuf>,
+}
+
+impl std::fmt::Display for RollbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.restored {
+            return write!(f, "Rolled back all completed operations.");
+        }
+        write!(f, "Failed to roll back some operations.")?;
+        if let Some(recovery_file) = &self.recovery_file {
+            write!(
+                f,
+                "\nRetry them by --resume --state-file {}",
+                recovery_file.to_string_lossy().yellow().underline()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RollbackError {}
+
+/// Error of the edited text.
+#[derive(Debug)]
+pub struct LineError {
+    /// Index of the line, or `None` if the error is not of a line
+    pub line: Option<usize>,
+    pub error: anyhow::Error,
+}
+
+/// Errors of the edited text found by `plan_from_lines`.
+#[derive(Debug)]
+pub struct InvalidLines(pub Vec<LineError>);
+
+impl std::fmt::Display for InvalidLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid lines.")?;
+        for e in self.0.iter() {
+            match e.line {
+                Some(i) => write!(f, "\nLine {}: {}", (i + 1).to_string().yellow(), e.error)?,
+                None => write!(f, "\n{}", e.error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidLines {}
+
+/// Operations stopped by SIGINT or SIGTERM.
+#[derive(Debug)]
+pub struct InterruptedError {
+    /// Number of completed operations
+    pub completed: usize,
+    /// Number of operations not executed
+    pub pending: usize,
+    /// State file to resume pending operations
+    pub state_file: Option<PathBuf>,
+}
+
+impl std::fmt::Display for InterruptedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Interrupted. {} completed, {} pending.",
+            self.completed.to_string().yellow(),
+            self.pending.to_string().yellow()
+        )?;
+        if let Some(state_file) = &self.state_file {
+            write!(
+                f,
+                "\nResume them by --resume --state-file {}",
+                state_file.to_string_lossy().yellow().underline()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InterruptedError {}
@@ -0,0 +1,46 @@
+//! Callbacks around operations, injected by `CommandLine::hooks`.
+
+use crate::Operation;
+
+/// What to do with an operation about to be executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookDecision {
+    Proceed,
+    /// Skip this operation as declined, continuing with the rest.
+    Skip,
+    /// Stop before this operation, skipping the rest as declined.
+    Abort,
+}
+
+/// Progress of copying or moving a directory, or a file across devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes copied so far in the operation
+    pub copied_bytes: u64,
+    /// Bytes to copy in the operation
+    pub total_bytes: u64,
+    /// Name of the file being copied, which may be empty if unknown
+    pub file_name: String,
+    pub file_copied_bytes: u64,
+    pub file_total_bytes: u64,
+}
+
+/// Receiver of operations before and after their execution, e.g. for library users with GUI.
+///
+/// All methods do nothing by default.
+pub trait Hooks: std::fmt::Debug + Send + Sync {
+    /// Operations to be executed in order, called once before any of them.
+    fn on_plan(&self, _operations: &[Operation]) {}
+
+    /// Operation about to be executed, which may be vetoed.
+    fn on_before(&self, _o: &Operation) -> HookDecision {
+        HookDecision::Proceed
+    }
+
+    /// Progress of the operation while files are copied by chunks,
+    /// not called if it is done by renaming.
+    fn on_progress(&self, _o: &Operation, _progress: &Progress) {}
+
+    /// Operation executed with its result, including failed ones.
+    fn on_after(&self, _o: &Operation, _result: &anyhow::Result<()>) {}
+}
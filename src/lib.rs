@@ -1,6 +1,7 @@
 use std::fs::Metadata;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -8,9 +9,30 @@ use colored::*;
 use fs_extra::dir::CopyOptions;
 use normpath::PathExt;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Parser, Default)]
-#[command(version)]
+pub mod editor;
+pub use editor::*;
+pub mod error;
+pub use error::*;
+pub mod hooks;
+pub use hooks::*;
+pub mod reporter;
+pub use reporter::*;
+
+#[derive(Debug, Clone, Parser, Default)]
+#[command(
+    version,
+    args_override_self = true,
+    after_help = "Exit codes:
+  0  Success, or all operations are valid with --check
+  1  Nothing to do, only with --fail-on-nothing, or invalid operations with --check
+  2  Invalid or colliding operations
+  3  Filesystem error during operations
+  4  Aborted by user, including the editor exiting with error, or failed to roll back with --atomic
+  5  Another moove is running in the same directory
+  130  Interrupted by SIGINT or SIGTERM"
+)]
 pub struct CommandLine {
     /// Paths or wildcard patterns to move
     #[arg(value_hint = clap::ValueHint::AnyPath)]
@@ -18,692 +40,5106 @@ pub struct CommandLine {
     /// Verbose output
     #[arg(short, long)]
     pub verbose: bool,
-    /// Sort in natural order
+    /// Sort in natural order, same as --sort-by name
     #[arg(short, long)]
     pub sort: bool,
+    /// Sort by the key, ties are broken by natural order of names
+    #[arg(long, value_enum, value_name = "KEY")]
+    pub sort_by: Option<SortKey>,
+    /// Reverse the order of sorting, or of children in each directory without sorting
+    #[arg(long)]
+    pub reverse: bool,
+    /// Shuffle sources randomly, e.g. to sample some of many files
+    #[arg(long, alias = "random")]
+    pub shuffle: bool,
+    /// Seed of --shuffle to reproduce the order
+    #[arg(long, value_name = "N", requires = "shuffle")]
+    pub seed: Option<u64>,
+    /// Group sources by extension in the text to edit, each group headed by a comment
+    #[arg(long)]
+    pub group_by_extension: bool,
     /// Treat as absolute paths
     #[arg(short, long)]
     pub absolute: bool,
     /// Directories themselves, not their contents
     #[arg(short, long)]
     pub directory: bool,
+    /// Descend into subdirectories, including them as sources if --directory is specified
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// Maximum depth of subdirectories to descend into
+    #[arg(long, value_name = "DEPTH", requires = "recursive")]
+    pub max_depth: Option<usize>,
+    /// Move sources into the directory without editing, e.g. "--flatten=dir"
+    #[arg(long, value_name = "DIR", visible_alias = "dest-dir")]
+    pub flatten: Option<PathBuf>,
+    /// Move files into directories named after their extensions without editing, e.g. "JPG/a.jpg"
+    #[arg(long, conflicts_with = "flatten")]
+    pub organize: bool,
+    /// Remove the prefix from file names without editing
+    #[arg(long, value_name = "PREFIX")]
+    pub strip_prefix: Option<String>,
+    /// Prepend the prefix to file names without editing
+    #[arg(long, value_name = "PREFIX")]
+    pub add_prefix: Option<String>,
+    /// Remove the suffix from file stems before extensions without editing
+    #[arg(long, value_name = "SUFFIX")]
+    pub strip_suffix: Option<String>,
+    /// Append the suffix to file stems before extensions without editing, e.g. "-v2"
+    #[arg(long, value_name = "SUFFIX")]
+    pub add_suffix: Option<String>,
+    /// Convert file names to lowercase without editing
+    #[arg(long = "lowercase", conflicts_with = "to_uppercase")]
+    pub to_lowercase: bool,
+    /// Convert file names to uppercase without editing
+    #[arg(long = "uppercase")]
+    pub to_uppercase: bool,
+    /// Replace characters unsafe on any filesystem in destination file names
+    #[arg(long)]
+    pub sanitize: bool,
+    /// Character to replace unsafe characters with
+    #[arg(
+        long,
+        value_name = "CHAR",
+        default_value_t = '_',
+        requires = "sanitize"
+    )]
+    pub sanitize_char: char,
+    /// Truncate stems of destination file names to be at most N bytes with extensions
+    #[arg(long, value_name = "N")]
+    pub truncate: Option<usize>,
+    /// Prepend modification dates to file names without editing, e.g. "20240102-a.jpg"
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "%Y%m%d"
+    )]
+    pub date_prefix: Option<String>,
+    /// Date used for --date-prefix if the modification date is unavailable
+    #[arg(long, value_enum, default_value_t = DateFallback::Now, requires = "date_prefix")]
+    pub date_fallback: DateFallback,
+    /// Do not fail even if no source has the prefix of --strip-prefix
+    #[arg(long, requires = "strip_prefix")]
+    pub ignore_missing_prefix: bool,
+    /// What to do if destinations without editing conflict
+    #[arg(long, value_enum, default_value_t = Conflict::Abort)]
+    pub conflict: Conflict,
     /// Include hidden files
     #[arg(short, long)]
     pub with_hidden: bool,
-    /// Exclude regular expression pattern
-    #[arg(short, long, value_name = "PATTERN")]
-    pub exclude_pattern: Option<Regex>,
+    /// Exclude regular expression pattern (can be repeated)
+    #[arg(short, long = "exclude-pattern", value_name = "PATTERN", action = clap::ArgAction::Append)]
+    pub exclude_patterns: Vec<Regex>,
+    /// Include only files with the extension, case-insensitively (can be repeated)
+    #[arg(long = "extension", value_name = "EXT", action = clap::ArgAction::Append)]
+    pub extensions: Vec<String>,
+    /// Include only sources of the type
+    #[arg(long = "type", value_enum, value_name = "TYPE")]
+    pub file_type: Option<FileType>,
+    /// Filter symbolic links by types of their targets, still operating the links themselves
+    #[arg(long)]
+    pub follow_symlinks: bool,
     /// Copy without moving
     #[arg(short, long)]
     pub copy: bool,
+    /// Preserve extended attributes of files copied, also across devices, on Linux and macOS
+    #[arg(long)]
+    pub xattrs: bool,
+    /// Fail instead of creating missing parent directories of destinations
+    #[arg(long)]
+    pub no_create_dir: bool,
+    /// Move by copying and removing even on the same filesystem, instead of renaming
+    #[arg(long)]
+    pub force_copy: bool,
+    /// Copy targets of symbolic links instead of the links themselves
+    #[arg(long)]
+    pub dereference: bool,
     /// Dry-run
     #[arg(short = 'u', long)]
     pub dry_run: bool,
     /// Abort in case of collision (prompt as default)
     #[arg(short, long)]
     pub oops: bool,
+    /// Compare destinations case-sensitively also on Windows and macOS, e.g. on case-sensitive volumes
+    #[arg(long)]
+    pub case_sensitive: bool,
+    /// Reject destinations invalid on Windows also on other platforms, e.g. for files shared with Windows
+    #[arg(long)]
+    pub windows_safe: bool,
+    /// Maximum length of file names in destinations, tighter than that of the platform, e.g. for FAT32
+    #[arg(long, value_name = "N")]
+    pub max_name_length: Option<usize>,
+    /// Fail an operation taking longer than the duration, e.g. 30s or 5m, not with --atomic
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = humantime::parse_duration,
+        conflicts_with = "atomic"
+    )]
+    pub timeout: Option<std::time::Duration>,
+    /// Retry operations failing transiently, e.g. busy or interrupted
+    #[arg(long)]
+    pub retry: bool,
+    /// Delay before retrying an operation
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "1s", requires = "retry")]
+    pub retry_delay: std::time::Duration,
+    /// Maximum number of retries for an operation
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "retry")]
+    pub retry_max: u32,
+    /// OS error code to retry in addition to the default ones (can be repeated)
+    #[arg(long = "retry-errno", value_name = "CODE", action = clap::ArgAction::Append, requires = "retry")]
+    pub retry_errnos: Vec<i32>,
+    /// Verify destinations after operations, comparing checksums of copied files
+    #[arg(long)]
+    pub verify: bool,
+    /// Do not highlight changed parts of paths in the output
+    #[arg(long)]
+    pub no_diff_colors: bool,
+    /// Do not report throughput of copying with --verbose
+    #[arg(long)]
+    pub no_progress: bool,
+    /// Size of the buffer to copy files across devices and directories
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024, value_parser = clap::value_parser!(u64).range(1..))]
+    pub buffer_size: u64,
+    /// Preview the text to edit before opening the editor
+    #[arg(long)]
+    pub preview: bool,
+    /// Only validate operations without executing them, exiting with 1 if invalid
+    #[arg(long)]
+    pub check: bool,
+    /// Execute operations without confirming the plan
+    #[arg(short, long)]
+    pub yes: bool,
+    /// Confirm each operation before it runs
+    #[arg(short, long, conflicts_with = "quiet")]
+    pub interactive: bool,
+    /// Print a table of statistics after operations
+    #[arg(long)]
+    pub stat: bool,
     /// No output to stdout/strerr even if error
     #[arg(short, long)]
     pub quiet: bool,
+    /// Exit with 1 if there is nothing to do
+    #[arg(long)]
+    pub fail_on_nothing: bool,
+    /// Undo completed operations if any operation fails
+    #[arg(short = 'A', long)]
+    pub atomic: bool,
+    /// Break circular operations like "a → b" and "b → a" by moving one of them to a temporary name first
+    #[arg(long)]
+    pub cycle_stage: bool,
+    /// Record progress of operations to resume them after interruption
+    #[arg(long, value_name = "PATH", env = "MOOVE_STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+    /// Resume interrupted operations recorded in the state file
+    #[arg(long, requires = "state_file")]
+    pub resume: bool,
+    /// Remove the state file after successful completion (default)
+    #[arg(long, overrides_with = "keep_state")]
+    pub cleanup_state: bool,
+    /// Keep the state file after successful completion
+    #[arg(long, overrides_with = "cleanup_state")]
+    pub keep_state: bool,
+    /// Wait for another moove running in the same directory to finish
+    #[arg(long, overrides_with = "fail_on_lock")]
+    pub wait_lock: bool,
+    /// Abort if another moove is running in the same directory (default)
+    #[arg(long, overrides_with = "wait_lock")]
+    pub fail_on_lock: bool,
+    /// Show verbose and dry-run output through PAGER
+    #[arg(long, overrides_with = "no_pager")]
+    pub pager: bool,
+    /// Show output without pager (default)
+    #[arg(long, overrides_with = "pager")]
+    pub no_pager: bool,
+    /// Run without locking the current directory
+    #[arg(long)]
+    pub no_lock: bool,
+    /// Append executed operations to the log file
+    #[arg(long, value_name = "PATH", env = "MOOVE_LOG")]
+    pub log_file: Option<PathBuf>,
+    /// Format of lines in the log file
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = LogFormat::Tsv, requires = "log_file")]
+    pub log_format: LogFormat,
+    /// Rename the log file with ".1" appended once it exceeds the size, to start a new one
+    #[arg(long, value_name = "BYTES", requires = "log_file")]
+    pub log_rotate_size: Option<u64>,
+    /// Write a shell script of operations instead of executing them, `-` for stdout
+    #[arg(long, value_name = "FILE")]
+    pub output_script: Option<PathBuf>,
+    /// Print operations as CSV of kind, source and destination instead of executing them
+    #[arg(long, conflicts_with_all = ["tsv", "output_script", "print0"])]
+    pub csv: bool,
+    /// Print operations as TSV without quotes instead of executing them
+    #[arg(long, conflicts_with_all = ["output_script", "print0"])]
+    pub tsv: bool,
+    /// Editor to edit the text instead of VISUAL or EDITOR
+    #[arg(long, value_name = "PATH", env = "MOOVE_EDITOR")]
+    pub editor: Option<String>,
+    /// Extra arguments for the editor, split like a shell
+    #[arg(
+        long,
+        value_name = "ARGS",
+        env = "MOOVE_EDITOR_ARGS",
+        allow_hyphen_values = true
+    )]
+    pub editor_args: Option<String>,
+    /// Format of the text to edit
+    #[arg(long, value_enum, default_value_t = Format::Lines)]
+    pub format: Format,
+    /// Leave sources alone whose lines are deleted, editing in the two-column format
+    #[arg(long)]
+    pub allow_removal: bool,
+    /// Show size and modification date of files as trailing comments
+    #[arg(long)]
+    pub annotate: bool,
+    /// Prefill destinations with sequential numbers, e.g. "{n:02} - {name}"
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "{n:02} - {name}"
+    )]
+    pub number: Option<String>,
+    /// Number files only, not directories
+    #[arg(long, requires = "number")]
+    pub number_files_only: bool,
+    /// Prefill destinations with a counter appended to file names, e.g. "photo-001.jpg"
+    #[arg(long, conflicts_with = "number")]
+    pub sequence: bool,
+    /// First value of the counter
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "sequence")]
+    pub counter_start: usize,
+    /// Increment of the counter
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "sequence")]
+    pub counter_step: usize,
+    /// Number of digits of the counter padded with zeros
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "sequence")]
+    pub counter_pad: usize,
+    /// Reset the counter for each directory
+    #[arg(long, requires = "sequence")]
+    pub sequence_per_dir: bool,
+    /// Ignore MOOVE_OPTIONS environment variable
+    #[arg(long)]
+    pub no_env: bool,
+    /// Paths from stdin are separated by null characters instead of new lines
+    #[arg(short = '0', long = "null")]
+    pub null_separated: bool,
+    /// Read paths or wildcard patterns from the file, one per line, or stdin if it is "-"
+    #[arg(long, value_name = "FILE")]
+    pub from_file: Option<PathBuf>,
+    /// Paths to move as they are, without wildcard patterns expanded
+    #[arg(skip)]
+    pub literal_paths: Vec<PathBuf>,
+    /// Print sources and destinations separated by null characters, with messages to stderr
+    #[arg(long)]
+    pub print0: bool,
+    /// Receiver of messages instead of the terminal, e.g. for library users
+    #[arg(skip)]
+    pub reporter: Option<std::sync::Arc<dyn Reporter>>,
+    /// Editor of the text instead of spawning a process, e.g. for library users and tests
+    #[arg(skip)]
+    pub text_editor: Option<std::sync::Arc<dyn Editor>>,
+    /// Callbacks around operations, e.g. for library users
+    #[arg(skip)]
+    pub hooks: Option<std::sync::Arc<dyn Hooks>>,
 }
 
-#[derive(Debug)]
+impl CommandLine {
+    /// Whether file names are renamed by options without editing.
+    pub fn renames_without_editing(&self) -> bool {
+        self.strip_prefix.is_some()
+            || self.add_prefix.is_some()
+            || self.strip_suffix.is_some()
+            || self.add_suffix.is_some()
+            || self.to_lowercase
+            || self.to_uppercase
+            || self.date_prefix.is_some()
+    }
+
+    /// Format of the text to edit, which is two-column to match lines with sources if lines can be deleted.
+    pub fn effective_format(&self) -> Format {
+        if self.allow_removal {
+            Format::TwoColumn
+        } else {
+            self.format
+        }
+    }
+
+    /// Whether stdout is occupied by machine-readable output.
+    pub fn stdout_occupied(&self) -> bool {
+        self.print0
+            || self.output_script.as_deref() == Some(Path::new("-"))
+            || self.table_delimiter().is_some()
+    }
+
+    /// Delimiter of fields to print operations with `--csv` or `--tsv`.
+    pub fn table_delimiter(&self) -> Option<char> {
+        if self.csv {
+            Some(',')
+        } else if self.tsv {
+            Some('\t')
+        } else {
+            None
+        }
+    }
+
+    /// Receiver of messages, which is the terminal one unless injected.
+    pub fn reporter(&self) -> std::sync::Arc<dyn Reporter> {
+        match &self.reporter {
+            Some(reporter) => reporter.clone(),
+            None => self.terminal_reporter(),
+        }
+    }
+
+    /// Editor of the text, which spawns `--editor` or VISUAL or EDITOR unless injected.
+    pub fn text_editor(&self) -> std::sync::Arc<dyn Editor> {
+        match &self.text_editor {
+            Some(editor) => editor.clone(),
+            None => std::sync::Arc::new(ExternalEditor::from_args(self)),
+        }
+    }
+
+    /// Reporter printing to the terminal, or discarding messages with `--quiet`.
+    pub fn terminal_reporter(&self) -> std::sync::Arc<dyn Reporter> {
+        if self.quiet {
+            std::sync::Arc::new(QuietReporter)
+        } else {
+            std::sync::Arc::new(TerminalReporter {
+                verbose: self.verbose,
+                stdout_occupied: self.stdout_occupied(),
+            })
+        }
+    }
+
+    /// Whether to ask how to resolve invalid operations instead of aborting.
+    pub fn asks_resolution(&self) -> bool {
+        !self.oops && !self.check
+    }
+
+    /// Whether to confirm the plan before executing operations.
+    pub fn confirms_plan(&self) -> bool {
+        use std::io::IsTerminal;
+        !self.yes
+            && !self.dry_run
+            && !self.quiet
+            && !self.interactive
+            && std::io::stdout().is_terminal()
+    }
+
+    /// Check contradictory options, which clap rejects only when parsing the command line.
+    pub fn validate(&self) -> Result<(), MooveError> {
+        let conflicts = [
+            (
+                self.to_lowercase && self.to_uppercase,
+                "--lowercase --uppercase",
+            ),
+            (
+                self.organize && self.flatten.is_some(),
+                "--organize --flatten",
+            ),
+            (
+                (self.organize || self.flatten.is_some()) && self.renames_without_editing(),
+                "--flatten --organize with renaming options",
+            ),
+            (
+                self.sequence && self.number.is_some(),
+                "--sequence --number",
+            ),
+            (self.interactive && self.quiet, "--interactive --quiet"),
+            (
+                self.cleanup_state && self.keep_state,
+                "--cleanup-state --keep-state",
+            ),
+            (
+                self.wait_lock && self.fail_on_lock,
+                "--wait-lock --fail-on-lock",
+            ),
+            (self.pager && self.no_pager, "--pager --no-pager"),
+            (
+                self.resume && self.state_file.is_none(),
+                "--resume without --state-file",
+            ),
+            (
+                self.print0 && self.output_script.as_deref() == Some(Path::new("-")),
+                "--print0 --output-script -",
+            ),
+            (self.timeout.is_some() && self.atomic, "--timeout --atomic"),
+            (self.csv && self.tsv, "--csv --tsv"),
+            (
+                self.table_delimiter().is_some() && (self.print0 || self.output_script.is_some()),
+                "--csv --tsv --print0 --output-script",
+            ),
+            (
+                self.shuffle && (self.sort || self.sort_by.is_some()),
+                "--shuffle --sort",
+            ),
+        ];
+        match conflicts.iter().find(|(conflicting, _)| *conflicting) {
+            Some((_, flags)) => Err(MooveError::ConflictingFlags(flags.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether to show output of operations through the pager.
+    pub fn paged(&self) -> bool {
+        use std::io::IsTerminal;
+        self.pager
+            && !self.quiet
+            && !self.interactive
+            && (self.verbose || self.dry_run)
+            && !self.stdout_occupied()
+            && std::env::var_os("NO_PAGER").is_none()
+            && std::io::stdout().is_terminal()
+    }
+
+    /// Whether to lock the current directory, only when executing operations.
+    pub fn locks(&self) -> bool {
+        !self.no_lock && !self.dry_run && !self.check
+    }
+
+    /// Rules of names of destinations, those of the platform unless `--case-sensitive` or `--windows-safe`.
+    pub fn name_rules(&self) -> NameRules {
+        let native = NameRules::native();
+        NameRules {
+            fold_case: native.fold_case && !self.case_sensitive,
+            fold_unicode: native.fold_unicode,
+            windows: native.windows || self.windows_safe,
+            max_name_length: match (native.max_name_length, self.max_name_length) {
+                (Some(native), Some(max)) => Some(native.min(max)),
+                (native, max) => native.or(max),
+            },
+            ..native
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SortKey {
+    /// Natural order of names
+    #[value(alias = "natural")]
+    Name,
+    /// Modification time, oldest first
+    Mtime,
+    /// Size, smallest first
+    Size,
+    /// Extension
+    #[value(alias = "extension")]
+    Ext,
+    /// Depth of directories, shallowest first
+    Depth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum FileType {
+    /// Regular file
+    #[value(name = "f", alias = "file")]
+    File,
+    /// Directory
+    #[value(name = "d", alias = "directory")]
+    Directory,
+    /// Symbolic link
+    #[value(name = "l", alias = "symlink")]
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// A destination for each line in the order of sources
+    #[default]
+    Lines,
+    /// A source and its destination separated by a tab for each line
+    TwoColumn,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Timestamp, kind, source, destination and result separated by tabs
+    #[default]
+    Tsv,
+    /// JSON object with fields ts, kind, src, dst and result
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Conflict {
+    /// Abort all operations
+    #[default]
+    Abort,
+    /// Skip the source
+    Skip,
+    /// Append a number to the file name like "name (1).ext"
+    Suffix,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum DateFallback {
+    /// Current date
+    #[default]
+    Now,
+    /// Leave the file name without date
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    /// Edit the whole text again
+    Edit,
+    /// Skip the conflicting operation
+    Skip,
+    /// Skip the conflicting operation and the following ones without asking
+    Continue,
+    /// Abort without any operation
+    Abort,
+    /// Remove the existing destination before the operation
+    Overwrite,
+    /// Append a number to the file name of the destination like "name (1).ext"
+    Rename,
+}
+
+/// Answer to the preview of the text to edit.
+#[derive(Debug, Clone)]
+pub enum Preview {
+    /// Open the editor
+    Continue,
+    /// Exclude sources matching the pattern and preview again
+    Exclude(Regex),
+    /// Abort without any operation
+    Abort,
+}
+
+/// Operations planned from edited text.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub operations: Vec<Operation>,
+    /// Number of sources whose lines are deleted with `--allow-removal`
+    pub deleted: usize,
+    /// Operations skipped at the prompt because of their conflicts
+    pub skipped: Vec<Operation>,
+    /// Edited text to edit again, if planned by editing
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Operation {
     pub kind: OperationKind,
     pub src: Source,
     pub dst: Destination,
 }
 
-#[derive(Debug)]
+impl Operation {
+    /// Operation to undo this one after it is executed.
+    ///
+    /// The inverse of copy is removal of the copy, and removal cannot be undone.
+    /// The source of the inverse is read from the destination, hence it should already exist.
+    pub fn inverse(&self) -> Result<Operation, MooveError> {
+        let (kind, dst) = match self.kind {
+            OperationKind::Move | OperationKind::Rename => (
+                self.kind.clone(),
+                Destination {
+                    text: self.src.text.to_owned(),
+                    path: self.src.path.to_owned(),
+                    abs: self.src.abs.to_owned(),
+                },
+            ),
+            OperationKind::Copy => (OperationKind::Remove, self.dst.to_owned()),
+            OperationKind::Remove => {
+                return Err(MooveError::IrreversibleRemoval(self.src.path.to_owned()))
+            }
+        };
+        Ok(Operation {
+            kind,
+            src: Source {
+                text: self.dst.text.to_owned(),
+                ..Source::from_path(&self.dst.path)?
+            },
+            dst,
+        })
+    }
+
+    /// Whether the path is moved away or removed by this operation.
+    pub fn vacates(&self, path: &Path) -> bool {
+        !matches!(self.kind, OperationKind::Copy)
+            && (self.src.path.is_identical(path) || self.src.abs.is_identical(path))
+    }
+
+    /// Whether the destination is moved away or removed by this operation.
+    pub fn vacates_destination(&self, dst: &Destination) -> bool {
+        self.vacates(&dst.path) || self.vacates(&dst.abs)
+    }
+
+    /// Whether the source and the nearest existing ancestor of the destination are on different devices.
+    ///
+    /// It is unknown and false if their devices cannot be identified.
+    pub fn crosses_filesystem(&self) -> bool {
+        let device = |path: &Path| path.file_id().map(|(device, _)| device);
+        let dst_device = self.dst.abs.ancestors().skip(1).find_map(device);
+        matches!((device(&self.src.path), dst_device), (Some(src), Some(dst)) if src != dst)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OperationKind {
     Move,
+    /// Move in the same directory
+    Rename,
     Copy,
     Remove,
 }
 
-#[derive(Debug, Clone)]
+impl OperationKind {
+    /// Name in state and log files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OperationKind::Move => "move",
+            OperationKind::Rename => "rename",
+            OperationKind::Copy => "copy",
+            OperationKind::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Source {
     pub text: String,
     pub path: PathBuf,
     pub abs: PathBuf,
-    pub meta: Metadata,
+    pub meta: SourceMeta,
+    /// Device and inode number on Unix, volume serial number and file index on Windows.
+    pub inode: Option<(u64, u64)>,
 }
 
-#[derive(Debug, Clone)]
+impl Source {
+    /// Source of the path itself, not of the target even if it is a symbolic link.
+    pub fn from_path(path: &Path) -> Result<Source, MooveError> {
+        if cfg!(not(target_family = "unix")) && path.to_str().is_none() {
+            return Err(MooveError::NonUtf8Path(path.to_owned()));
+        }
+        let text = escape_path(path).trim_end_matches(SEPARATORS).to_string();
+        let meta = symlink_metadata_of(path)?;
+        Ok(Source {
+            text,
+            path: path.to_path_buf(),
+            abs: path.absolute()?.into_path_buf(),
+            meta: SourceMeta::from(&meta),
+            inode: path.file_id(),
+        })
+    }
+
+    /// Whether the text is escaped by `escape_bytes` since the path is invalid as UTF-8.
+    pub fn is_escaped(&self) -> bool {
+        self.path.to_str().is_none()
+    }
+}
+
+/// Part of metadata of a source needed for operations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceMeta {
+    pub size: u64,
+    pub is_file: bool,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub mtime: Option<SystemTime>,
+}
+
+impl From<&Metadata> for SourceMeta {
+    fn from(meta: &Metadata) -> SourceMeta {
+        SourceMeta {
+            size: meta.len(),
+            is_file: meta.is_file(),
+            is_dir: meta.is_dir(),
+            is_symlink: meta.is_symlink(),
+            mtime: meta.modified().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Destination {
     pub text: String,
     pub path: PathBuf,
+    /// Normalized absolute path to compare destinations with each other.
+    pub abs: PathBuf,
+}
+
+impl Destination {
+    pub fn new(text: String, path: PathBuf) -> Result<Destination> {
+        let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+        let abs = resolve_destination(&path, &current_dir);
+        Ok(Destination { text, path, abs })
+    }
+
+    /// Proper ancestors of both the given and the absolute paths.
+    pub fn ancestors(&self) -> impl Iterator<Item = &Path> {
+        self.path
+            .ancestors()
+            .skip(1)
+            .chain(self.abs.ancestors().skip(1))
+    }
+}
+
+/// Rules of names on the filesystem, by which destinations are compared and validated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NameRules {
+    /// Letter case is ignored, e.g. `Readme.md` and `README.md` on Windows and macOS
+    pub fold_case: bool,
+    /// Unicode normalization is ignored, e.g. `é` composed or decomposed on macOS
+    pub fold_unicode: bool,
+    /// Names invalid on Windows are rejected
+    pub windows: bool,
+    /// Maximum length of file names, measured by `name_length`
+    pub max_name_length: Option<usize>,
+    /// Maximum length of absolute paths, measured by `name_length`
+    pub max_path_length: Option<usize>,
+}
+
+impl NameRules {
+    /// Rules assumed for the filesystem of the platform.
+    pub fn native() -> NameRules {
+        NameRules {
+            fold_case: cfg!(any(target_os = "windows", target_os = "macos")),
+            fold_unicode: cfg!(target_os = "macos"),
+            windows: cfg!(target_os = "windows"),
+            max_name_length: Some(255),
+            // Long paths are supported on Windows by the extended-length form.
+            max_path_length: if cfg!(target_os = "windows") {
+                None
+            } else if cfg!(target_os = "macos") {
+                Some(1024)
+            } else {
+                Some(4096)
+            },
+        }
+    }
+
+    /// Whether any difference of names is ignored.
+    pub fn folds(&self) -> bool {
+        self.fold_case || self.fold_unicode
+    }
+
+    /// Text of the path with the ignored differences removed, in NFC and lowercase.
+    pub fn fold(&self, path: &Path) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        let mut text = path.to_string_lossy().to_string();
+        if self.fold_unicode {
+            text = text.nfc().collect();
+        }
+        if self.fold_case {
+            text = text.to_lowercase();
+        }
+        text
+    }
+}
+
+/// Absolute path of the destination with `.` and `..` resolved lexically,
+/// and its parent resolved as sources are if it exists.
+///
+/// Leading `..` are resolved against the current directory, never dropped,
+/// while `..` beyond the root stays at the root.
+pub fn resolve_destination(path: &Path, current_dir: &Path) -> PathBuf {
+    use std::path::Component;
+    let mut abs = PathBuf::new();
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                abs.pop();
+            }
+            component => abs.push(component),
+        }
+    }
+    if let (Some(parent), Some(name)) = (abs.parent(), abs.file_name()) {
+        if let Ok(parent) = parent.absolute() {
+            abs = parent.join(name).into_path_buf();
+        }
+    }
+    // Same form as sources, which are verbatim if they are long.
+    if is_long_path(&abs) {
+        verbatim_path(&abs)
+    } else {
+        abs
+    }
+}
+
+/// Operations recorded in the state file and the number of completed ones.
+#[derive(Debug)]
+pub struct State {
+    pub entries: Vec<StateEntry>,
+    pub completed: usize,
+}
+
+#[derive(Debug)]
+pub struct StateEntry {
+    pub kind: OperationKind,
+    pub src: String,
+    pub dst: String,
 }
 
 static SEPARATORS: &[char] = &['/', '\\'];
 
+/// Beginning of a trailing comment, which is ignored with the rest of the line.
+static ANNOTATION_MARKER: &str = "\t# ";
+
+/// Beginning of a comment of an error, written into the text to edit again.
+static ERROR_MARKER: &str = "# ERROR";
+
+/// Lock file in the current directory to prevent concurrent invocations.
+pub static LOCK_FILE_NAME: &str = ".moove.lock";
+
+/// Metadata of the path itself, not of the target even if it is a symbolic link.
+fn symlink_metadata_of(path: &Path) -> Result<Metadata, MooveError> {
+    path.symlink_metadata()
+        .map_err(|source| MooveError::SourceAccess {
+            path: path.to_owned(),
+            source,
+        })
+}
+
 trait PathUtilExt {
     /// NOTE Can be replaced with `std::path::absolute` in the future.
-    fn absolute(&self) -> Result<normpath::BasePathBuf>;
-    fn is_hidden(&self) -> Result<bool>;
+    fn absolute(&self) -> Result<normpath::BasePathBuf, MooveError>;
+    /// Whether the path is hidden by itself, even if it is a symbolic link to a hidden file.
+    fn is_hidden(&self, meta: &std::fs::Metadata) -> bool;
     fn is_identical(&self, other: &Path) -> bool;
+    fn file_id(&self) -> Option<(u64, u64)>;
 }
 
 impl PathUtilExt for Path {
     fn is_identical(&self, other: &Path) -> bool {
         if cfg!(target_family = "windows") {
-            self == other
-                || self.as_os_str().to_ascii_lowercase() == other.as_os_str().to_ascii_lowercase()
+            self == other || self.as_os_str().eq_ignore_ascii_case(other.as_os_str())
         } else {
             self == other
         }
     }
 
     #[cfg(target_family = "windows")]
-    fn absolute(&self) -> Result<normpath::BasePathBuf> {
-        self.normalize_virtually().with_context(|| {
-            format!(
-                "Failed to normalize path. {}",
-                self.to_string_lossy().yellow().underline()
-            )
-        })
+    fn absolute(&self) -> Result<normpath::BasePathBuf, MooveError> {
+        let failed = |source| MooveError::Normalize {
+            path: self.to_owned(),
+            source,
+        };
+        // Verbatim paths are taken literally, hence normalized without the prefix.
+        let abs = strip_verbatim(self).normalize_virtually().map_err(failed)?;
+        if !is_long_path(abs.as_path()) {
+            return Ok(abs);
+        }
+        normpath::BasePathBuf::new(verbatim_path(abs.as_path())).map_err(failed)
     }
 
     #[cfg(target_family = "unix")]
-    fn absolute(&self) -> Result<normpath::BasePathBuf> {
-        self.normalize().with_context(|| {
-            format!(
-                "Failed to normalize path. {}",
-                self.to_string_lossy().yellow().underline()
-            )
+    fn absolute(&self) -> Result<normpath::BasePathBuf, MooveError> {
+        // Broken symbolic links cannot be canonicalized, but their parents can.
+        if let (Err(_), Ok(_), Some(name)) = (
+            self.canonicalize(),
+            self.symlink_metadata(),
+            self.file_name(),
+        ) {
+            let parent = match self.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            return Ok(parent.absolute()?.join(name));
+        }
+        self.normalize().map_err(|source| MooveError::Normalize {
+            path: self.to_owned(),
+            source,
         })
     }
 
     #[cfg(target_family = "windows")]
-    fn is_hidden(&self) -> Result<bool> {
+    fn file_id(&self) -> Option<(u64, u64)> {
         use std::os::windows::prelude::*;
-        let metadata = std::fs::metadata(self).with_context(|| {
-            format!(
-                "Failed to read metadata of {}",
-                self.to_string_lossy().yellow().underline()
-            )
-        })?;
-        Ok((metadata.file_attributes() & 0x2) > 0)
+        use windows_sys::Win32::Storage::FileSystem::{
+            GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+            FILE_FLAG_OPEN_REPARSE_POINT,
+        };
+        // Open the link itself, not its target, without any access right.
+        let file = std::fs::OpenOptions::new()
+            .access_mode(0)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+            .open(self)
+            .ok()?;
+        let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+        if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+            return None;
+        }
+        Some((
+            info.dwVolumeSerialNumber as u64,
+            ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64,
+        ))
     }
 
     #[cfg(target_family = "unix")]
-    fn is_hidden(&self) -> Result<bool> {
-        Ok(self
-            .file_name()
-            .with_context(|| {
-                format!(
-                    "Failed to get file name {}",
-                    self.to_string_lossy().yellow().underline()
-                )
-            })?
-            .to_string_lossy()
-            .starts_with('.'))
+    fn file_id(&self) -> Option<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = self.symlink_metadata().ok()?;
+        Some((metadata.dev(), metadata.ino()))
     }
-}
 
-pub fn try_main(args: &CommandLine) -> Result<usize> {
-    let sources = &sources_from(args)?;
-    let operations = &operations_from(sources, args)?;
-    let mut processed = 0;
-    for o in operations.iter() {
-        execute_operation(o, args)?;
-        if args.dry_run {
-            continue;
-        }
-        processed += 1;
+    #[cfg(target_family = "windows")]
+    fn is_hidden(&self, meta: &std::fs::Metadata) -> bool {
+        use std::os::windows::prelude::*;
+        // FILE_ATTRIBUTE_HIDDEN of the link itself, given by `symlink_metadata`
+        (meta.file_attributes() & 0x2) > 0
+    }
+
+    #[cfg(target_family = "unix")]
+    fn is_hidden(&self, _meta: &std::fs::Metadata) -> bool {
+        self.file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
     }
-    Ok(processed)
 }
 
-pub fn sources_from(args: &CommandLine) -> Result<Vec<Source>> {
-    let mut sources: Vec<Source> = Vec::new();
-    let paths = list_files(&args.paths)?;
-    for p in paths.iter().map(|p| p.trim_end_matches(SEPARATORS)) {
-        let path = &PathBuf::from(if cfg!(target_family = "windows") {
-            p.replace('/', "\\")
-        } else {
-            p.to_string()
-        });
-        let stat = &path.symlink_metadata().with_context(|| {
-            format!(
-                "Failed to access {}",
-                path.to_string_lossy().yellow().underline()
-            )
+pub fn try_main(args: &CommandLine) -> Result<Report> {
+    try_main_with(args, args.text_editor())
+}
+
+/// Run the whole flow with the editor of the text, including editing again on errors.
+pub fn try_main_with(args: &CommandLine, editor: std::sync::Arc<dyn Editor>) -> Result<Report> {
+    let args = &CommandLine {
+        text_editor: Some(editor),
+        ..args.clone()
+    };
+    args.validate().context(Failure::Validation)?;
+    let _lock = if args.locks() {
+        let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+        Some(acquire_lock(&current_dir, args)?)
+    } else {
+        None
+    };
+    let mut log = match &args.log_file {
+        Some(path)
+            if !args.dry_run
+                && args.output_script.is_none()
+                && args.table_delimiter().is_none() =>
+        {
+            Some(OperationLog::open_with(
+                path,
+                args.log_format,
+                args.log_rotate_size,
+            )?)
+        }
+        _ => None,
+    };
+    let mut excluded = 0;
+    let sources = &if args.resume {
+        Vec::new()
+    } else {
+        let (sources, filtered) = sources_with_excluded(args)?;
+        let listed = sources.len();
+        let sources = preview_sources(sources, args)?;
+        excluded = filtered + listed - sources.len();
+        sources
+    };
+    let plan = if args.resume {
+        let state_file = args
+            .state_file
+            .as_ref()
+            .context("State file is required to resume.")?;
+        resume_operations(&load_state(state_file)?, args).map(|operations| Plan {
+            operations,
+            ..Plan::default()
+        })
+    } else {
+        plan_operations(sources, args)
+    }
+    .and_then(|mut plan| {
+        reorder_operations_with(&mut plan.operations, args.cycle_stage)
+            .context(Failure::Validation)?;
+        Ok(plan)
+    });
+    if let Ok(plan) = &plan {
+        for o in plan.operations.iter().filter(|o| is_long_path(&o.dst.abs)) {
+            args.reporter().warn(&format!(
+                "{} {}",
+                "Destination is longer than MAX_PATH, which some programs cannot handle.",
+                o.dst.text.yellow().underline()
+            ));
+        }
+    }
+    if args.check {
+        let plan = plan.map_err(|err| match err.downcast_ref::<Failure>() {
+            Some(Failure::Validation) => err.context(Failure::Checked),
+            _ => err,
         })?;
-        if stat.is_file() || stat.is_symlink() || args.directory {
-            put_source(&mut sources, path, args)?;
-        } else {
-            let mut children = Vec::new();
-            for entry in std::fs::read_dir(path).with_context(|| {
-                format!(
-                    "Failed to list files of directory. {}",
-                    path.to_string_lossy().yellow().underline()
-                )
-            })? {
-                children.push(entry?.path());
-            }
-            children.sort_unstable_by(|a, b| {
-                natord::compare(&a.to_string_lossy(), &b.to_string_lossy())
-            });
-            for child in children {
-                put_source(&mut sources, &child, args)?;
+        args.reporter().info(&format!(
+            "{} {}",
+            "Valid:".green().bold(),
+            match plan.operations.len() {
+                1 => "1 operation".to_owned(),
+                n => format!("{} operations", n),
             }
-            if sources.is_empty() {
-                anyhow::bail!(
-                    "Directory is empty. {}\n\
-                    Use --directory for the directory itself.",
-                    path.to_string_lossy().yellow().underline()
-                );
+        ));
+        return Ok(Report::default());
+    }
+    let plan = &mut plan?;
+    if let Some(path) = &args.output_script {
+        output_script(path, &plan.operations)?;
+        return Ok(Report::default());
+    }
+    if let Some(delimiter) = args.table_delimiter() {
+        render_csv(&plan.operations, delimiter, &mut std::io::stdout().lock())
+            .context("Failed to print operations.")?;
+        return Ok(Report::default());
+    }
+    if args.confirms_plan() && !plan.operations.is_empty() {
+        let mut answers = open_terminal()?;
+        loop {
+            write_plan(&mut std::io::stdout().lock(), &plan.operations)?;
+            match prompt_proceed_from(args, plan.text.is_some(), &mut answers)? {
+                Resolution::Continue => break,
+                Resolution::Edit => {
+                    // NOTE Can be unwrapped safely, editing is offered only with the text.
+                    let text = plan.text.take().unwrap();
+                    *plan = edit_operations(sources, args, text)?;
+                    reorder_operations_with(&mut plan.operations, args.cycle_stage)
+                        .context(Failure::Validation)?;
+                    if plan.operations.is_empty() {
+                        break;
+                    }
+                }
+                _ => return Err(Failure::Aborted.into()),
             }
         }
     }
-    if args.sort {
-        sources.sort_unstable_by(|a, b| natord::compare(&a.text, &b.text));
+    let mut report = execute_operations(&plan.operations, args, log.as_mut())?;
+    report.skipped = plan.deleted;
+    report.excluded = excluded;
+    report.skipped_operations = std::mem::take(&mut plan.skipped);
+    Ok(report)
+}
+
+/// Write a shell script of operations to the file, or stdout if it is `-`.
+pub fn output_script(path: &Path, operations: &[Operation]) -> Result<()> {
+    let context = || {
+        format!(
+            "Failed to write script. {}",
+            path.to_string_lossy().yellow().underline()
+        )
+    };
+    if path == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+        write_script(&mut stdout, operations).with_context(context)
+    } else {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path).with_context(context)?);
+        write_script(&mut file, operations)
+            .and_then(|_| file.flush())
+            .with_context(context)
     }
-    Ok(sources)
 }
 
-pub fn list_files(args: &[String]) -> Result<Vec<String>> {
-    use glob::glob;
-    let mut paths = Vec::new();
-    for arg in args.iter() {
-        let mut globbed = Vec::new();
-        for path in
-            glob(arg).with_context(|| format!("Invalid pattern {}", arg.yellow().underline()))?
-        {
-            globbed
-                .push(path.with_context(|| format!("Failed to glob {}", arg.yellow().underline()))?)
+/// Write a header `kind,src,dst` and a row per operation, with an empty destination for removal.
+///
+/// Fields containing the delimiter, double quotes or line breaks are quoted as RFC 4180,
+/// unless the delimiter is a tab, which never quotes like TSV but escapes tabs, line breaks
+/// and backslashes with backslashes like `\t`.
+pub fn render_csv(ops: &[Operation], delimiter: char, writer: &mut dyn Write) -> Result<()> {
+    let field = |text: &str| {
+        if delimiter == '\t' {
+            escape_state_field(text).replace('\r', "\\r")
+        } else if text.contains([delimiter, '"', '\r', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_owned()
         }
-        if globbed.is_empty() {
-            anyhow::bail!("Failed to access {}", arg);
+    };
+    let mut row = |fields: [&str; 3]| {
+        let fields: Vec<String> = fields.iter().map(|f| field(f)).collect();
+        writeln!(writer, "{}", fields.join(&delimiter.to_string()))
+    };
+    row(["kind", "src", "dst"])?;
+    for o in ops.iter() {
+        let dst = match o.kind {
+            OperationKind::Remove => "",
+            _ => &o.dst.text,
+        };
+        row([o.kind.name(), &o.src.text, dst])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write operations as a POSIX shell script of `mv`, `cp` and `rm`.
+pub fn write_script(writer: &mut impl Write, operations: &[Operation]) -> std::io::Result<()> {
+    writeln!(writer, "#!/bin/sh")?;
+    writeln!(writer, "set -e")?;
+    let mut created = std::collections::HashSet::new();
+    for o in operations.iter() {
+        let (src, dst) = (shell_quote(&o.src.text), shell_quote(&o.dst.text));
+        if !matches!(o.kind, OperationKind::Remove) {
+            let dst_text = o.dst.text.trim_end_matches(SEPARATORS);
+            if let Some((parent, _)) = dst_text.rsplit_once(SEPARATORS) {
+                if !parent.is_empty() && created.insert(parent.to_owned()) {
+                    writeln!(writer, "mkdir -p -- {}", shell_quote(parent))?;
+                }
+            }
+        }
+        match o.kind {
+            OperationKind::Move | OperationKind::Rename => {
+                writeln!(writer, "mv -- {} {}", src, dst)?
+            }
+            OperationKind::Copy => writeln!(writer, "cp -R -- {} {}", src, dst)?,
+            OperationKind::Remove => writeln!(writer, "rm -rf -- {}", src)?,
         }
-        globbed.sort_unstable();
-        paths.append(
-            &mut globbed
-                .iter()
-                .map(|g| g.to_string_lossy().to_string())
-                .collect(),
-        );
     }
-    Ok(paths)
+    Ok(())
 }
 
-pub fn put_source(sources: &mut Vec<Source>, path: &Path, args: &CommandLine) -> Result<()> {
-    let abs = path.absolute()?;
-    let abs = abs.as_path();
-    if abs.parent().is_none() {
-        anyhow::bail!(
-            "Source should not be the root directory. {}",
-            path.to_string_lossy().yellow().underline()
-        );
+/// Quote a string for POSIX shells, leaving it as it is if it needs no quotes.
+pub fn shell_quote(s: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        return s.to_owned();
     }
-    if !args.with_hidden && abs.is_hidden()? {
-        return Ok(());
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Lock held during the run, released and removed when dropped.
+pub struct LockGuard {
+    file: fslock::LockFile,
+    path: PathBuf,
+}
+
+/// Path of the lock file held by `LockGuard`, for the application exiting without dropping it.
+static HELD_LOCK: std::sync::Mutex<Option<PathBuf>> = std::sync::Mutex::new(None);
+
+/// Remove the lock file held by this process, e.g. before exiting by a signal.
+pub fn remove_held_lock() {
+    if let Some(path) = HELD_LOCK.lock().ok().and_then(|mut held| held.take()) {
+        let _ = std::fs::remove_file(path);
     }
-    let new_path = if args.absolute { abs } else { path };
-    let new_path_text = new_path
-        .to_str()
-        .with_context(|| {
-            format!(
-                "Failed to convert path to UTF-8. {}",
-                path.to_string_lossy().to_string().yellow().underline()
-            )
-        })?
-        .trim_end_matches(SEPARATORS)
-        .to_string();
-    if let Some(pattern) = &args.exclude_pattern {
-        if pattern.is_match(&new_path_text) {
-            return Ok(());
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Ok(mut held) = HELD_LOCK.lock() {
+            held.take();
         }
-    }
-    let new_src = Source {
-        text: new_path_text,
-        path: new_path.to_path_buf(),
-        abs: abs.to_path_buf(),
-        meta: new_path.symlink_metadata().with_context(|| {
-            format!(
-                "Failed to access {}",
-                new_path.to_string_lossy().yellow().underline()
-            )
-        })?,
-    };
-    for src in sources.iter() {
-        if src.abs.is_identical(&new_src.abs) {
-            anyhow::bail!(
-                "Duplicated source. {}",
-                new_src.abs.to_string_lossy().yellow().underline()
-            );
+        if self.file.owns_lock() {
+            // Removed before unlocking, so that waiting invocations notice it and lock a new file.
+            // It fails on Windows while others have it open, leaving it to the last one.
+            let _ = std::fs::remove_file(&self.path);
+            let _ = self.file.unlock();
         }
     }
-    sources.push(new_src);
-    Ok(())
 }
 
-pub fn operations_from(sources: &Vec<Source>, args: &CommandLine) -> Result<Vec<Operation>> {
-    let mut operations = Vec::new();
-    let mut text = sources
-        .iter()
-        .map(|src| {
-            let mut line = src.text.to_owned();
-            if src.path.is_dir()
-                && !src.path.is_symlink()
-                && !line.ends_with(std::path::MAIN_SEPARATOR)
-            {
-                line.push(std::path::MAIN_SEPARATOR);
+/// Lock the directory, waiting for another invocation if `--wait-lock` is specified.
+pub fn acquire_lock(dir: &Path, args: &CommandLine) -> Result<LockGuard> {
+    let path = dir.join(LOCK_FILE_NAME);
+    let context = || {
+        format!(
+            "Failed to lock. {}",
+            path.to_string_lossy().yellow().underline()
+        )
+    };
+    let mut waiting = false;
+    loop {
+        let opened = path.file_id();
+        let mut file = fslock::LockFile::open(&path).with_context(context)?;
+        if !file.try_lock().with_context(context)? {
+            if !args.wait_lock {
+                return Err(LockError { path }.into());
             }
-            line
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-    'redo: loop {
-        text = edit::edit(&text)?;
-        let lines = text
-            .split('\n')
-            .filter_map(|line| {
-                let line = line.trim();
-                if line.is_empty() {
-                    return None;
-                }
-                Some(line.trim_end_matches(SEPARATORS))
-            })
-            .collect::<Vec<_>>();
-        if lines.len() != sources.len() {
-            let message = format!(
-                "Number of lines {} does not match the original one {}",
-                lines.len().to_string().yellow(),
-                sources.len().to_string().yellow()
-            );
-            if !args.oops {
-                println!("{}", message);
-                if prompt_redo()? {
-                    continue 'redo;
-                }
-                break 'redo;
+            if !waiting {
+                args.reporter().warn(&format!(
+                    "{}",
+                    "Waiting for another moove running in the same directory.".dimmed()
+                ));
             }
-            anyhow::bail!(message);
+            waiting = true;
+            file.lock().with_context(context)?;
         }
-        for (src, line) in sources.iter().zip(lines.iter()) {
-            let line = line.to_owned();
-            let (kind, line) = if line.starts_with("//") {
-                (OperationKind::Remove, src.text.as_str())
-            } else if args.copy {
-                (OperationKind::Copy, line)
-            } else {
-                (OperationKind::Move, line)
-            };
-            let line = if cfg!(target_family = "windows") {
-                line.replace('/', "\\")
-            } else {
-                line.to_string()
-            };
-            let dst_path = PathBuf::from(&line);
-            let removing = matches!(kind, OperationKind::Remove);
-            if !removing && (dst_path == src.path || dst_path == src.abs) {
-                continue;
+        // The file may have been removed or replaced by the previous holder while waiting.
+        if opened.is_some() && path.file_id() == opened {
+            if let Ok(mut held) = HELD_LOCK.lock() {
+                *held = Some(path.to_owned());
             }
-            let new_operation = Operation {
-                kind,
-                src: src.to_owned(),
-                dst: Destination {
-                    text: line.to_owned(),
-                    path: dst_path.to_owned(),
-                },
-            };
-            if !removing {
-                if let Err(message) = is_operational(&operations, &new_operation) {
-                    if !args.oops {
-                        println!("{}", message);
-                        if prompt_redo()? {
-                            continue 'redo;
-                        }
-                        break 'redo;
-                    }
-                    anyhow::bail!(message);
-                }
-            }
-            operations.push(new_operation);
+            return Ok(LockGuard { file, path });
         }
-        break;
+        file.unlock().with_context(context)?;
     }
-    Ok(operations)
 }
 
-pub fn prompt_redo() -> Result<bool> {
-    loop {
-        print!(
-            "{}{} or {}{}? > ",
-            "E".bold().underline(),
-            "dit".bold(),
-            "A".bold().underline(),
-            "bort".bold()
-        );
-        std::io::stdout().flush()?;
-        let mut ans = String::new();
-        std::io::stdin().read_line(&mut ans)?;
-        let ans = ans.trim().to_ascii_lowercase();
-        if Regex::new(r"^a(bort)?$")?.is_match(&ans) {
-            return Ok(false);
-        }
-        if ans.is_empty() || Regex::new(r"^e(dit)?$")?.is_match(&ans) {
-            return Ok(true);
-        }
+/// Execute operations recording progress to the state file,
+/// and undo completed ones in case of failure if `--atomic` is specified.
+pub fn execute_operations(
+    operations: &[Operation],
+    args: &CommandLine,
+    log: Option<&mut OperationLog>,
+) -> Result<Report> {
+    if args.interactive && !args.dry_run {
+        execute_operations_with(operations, args, log, &mut open_terminal()?)
+    } else {
+        execute_operations_with(operations, args, log, &mut std::io::empty())
     }
 }
 
-pub fn is_operational(operations: &[Operation], new_operation: &Operation) -> Result<()> {
-    let src = &new_operation.src;
-    let dst = &new_operation.dst;
-    if dst.text.ends_with(std::path::MAIN_SEPARATOR)
-        && (src.meta.is_file() || src.meta.is_symlink())
-    {
-        anyhow::bail!(
-            "Missing file name. {} for {}",
-            dst.text.yellow().underline(),
-            src.text.underline()
-        )
+/// Set by the signal handler of the application to stop executing operations after the current one.
+pub static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Whether an operation is being executed and recorded, during which the signal handler of
+/// the application should set `INTERRUPTED` rather than exit.
+pub static OPERATING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// `OPERATING` set while it lives.
+struct OperatingGuard;
+
+impl OperatingGuard {
+    fn new() -> OperatingGuard {
+        OPERATING.store(true, std::sync::atomic::Ordering::SeqCst);
+        OperatingGuard
     }
-    if operations
-        .iter()
-        .any(|o| o.dst.path.is_identical(&dst.path))
-    {
-        anyhow::bail!("Duplicated destination. {}", dst.text.yellow().underline());
+}
+
+impl Drop for OperatingGuard {
+    fn drop(&mut self) {
+        OPERATING.store(false, std::sync::atomic::Ordering::SeqCst);
     }
-    if operations
+}
+
+/// Open the terminal to read answers, since stdin might be consumed for paths.
+fn open_terminal() -> Result<std::io::BufReader<std::fs::File>> {
+    let tty = std::fs::File::open(if cfg!(target_family = "windows") {
+        "CONIN$"
+    } else {
+        "/dev/tty"
+    })
+    .context("Failed to open the terminal to confirm operations.")?;
+    Ok(std::io::BufReader::new(tty))
+}
+
+/// Write operations with aligned arrows followed by their counts.
+pub fn write_plan(writer: &mut impl Write, operations: &[Operation]) -> std::io::Result<()> {
+    let count =
+        |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let width = operations
         .iter()
-        .any(|o| o.dst.path.ancestors().any(|a| a.is_identical(&dst.path)))
-    {
-        anyhow::bail!(
-            "Destination should not be included in other destination. {}",
-            dst.text.yellow().underline()
-        );
-    }
-    if dst.path.exists() {
-        anyhow::bail!("Destination exists. {}", dst.text.yellow().underline())
-    }
-    if dst.path.ancestors().any(|a| {
-        if !a.exists() {
-            false
-        } else if a.is_file() {
-            true
-        } else if a.is_symlink() {
-            if let Ok(p) = a.read_link() {
-                p.is_file()
-            } else {
-                false
+        .map(|o| o.src.text.chars().count())
+        .max()
+        .unwrap_or_default();
+    let (mut moves, mut copies, mut removals) = (0, 0, 0);
+    let mut created = std::collections::HashSet::new();
+    for o in operations.iter() {
+        let kind = match o.kind {
+            OperationKind::Move => "Move",
+            OperationKind::Rename => "Rename",
+            OperationKind::Copy => "Copy",
+            OperationKind::Remove => "Remove",
+        };
+        match o.kind {
+            OperationKind::Move | OperationKind::Rename => moves += 1,
+            OperationKind::Copy => copies += 1,
+            OperationKind::Remove => {
+                removals += 1;
+                writeln!(writer, "{:6} {}", kind, o.src.text)?;
+                continue;
             }
-        } else {
-            false
         }
-    }) {
-        anyhow::bail!(
-            "Ancestor of destination should not be a file.\n\
-             Destination: {}",
-            dst.text.yellow().underline()
+        writeln!(
+            writer,
+            "{:6} {:width$} → {}",
+            kind,
+            o.src.text,
+            o.dst.text,
+            width = width
+        )?;
+        created.extend(
+            o.dst
+                .path
+                .ancestors()
+                .skip(1)
+                .take_while(|a| !a.as_os_str().is_empty() && !a.exists()),
         );
     }
-    Ok(())
+    let mut items = Vec::new();
+    if moves > 0 {
+        items.push(count(moves, "move", "moves"));
+    }
+    if copies > 0 {
+        items.push(count(copies, "copy", "copies"));
+    }
+    if removals > 0 {
+        items.push(count(removals, "removal", "removals"));
+    }
+    if !created.is_empty() {
+        items.push(format!(
+            "{} will be created",
+            count(created.len(), "directory", "directories")
+        ));
+    }
+    writeln!(writer, "{}", items.join(", "))
 }
 
-pub fn execute_operation(o: &Operation, args: &CommandLine) -> Result<()> {
-    match o.kind {
-        OperationKind::Move => {
-            if !args.quiet && (args.verbose || args.dry_run) {
-                println!(
-                    "{} {}{}{}",
-                    "Move".dimmed(),
-                    o.src.text.dimmed().underline(),
-                    " → ".dimmed(),
-                    o.dst.text.dimmed().underline()
-                );
+/// Ask whether to proceed with the plan, or to edit it again if editable.
+pub fn prompt_proceed_from(
+    args: &CommandLine,
+    editable: bool,
+    reader: &mut impl std::io::BufRead,
+) -> Result<Resolution> {
+    let prompt = if editable {
+        "Proceed? [y/N/e] > "
+    } else {
+        "Proceed? [y/N] > "
+    };
+    prompt_from(args, reader, prompt, |ans| match ans {
+        "y" | "yes" => Some(Resolution::Continue),
+        "" | "n" | "no" => Some(Resolution::Abort),
+        "e" | "edit" if editable => Some(Resolution::Edit),
+        _ => None,
+    })
+}
+
+/// `execute_operations` reading answers to confirm operations from the reader with `--interactive`.
+pub fn execute_operations_with(
+    operations: &[Operation],
+    args: &CommandLine,
+    mut log: Option<&mut OperationLog>,
+    answers: &mut impl std::io::BufRead,
+) -> Result<Report> {
+    let started = std::time::Instant::now();
+    let state_file = if args.dry_run {
+        None
+    } else {
+        args.state_file.as_deref()
+    };
+    let mut state = match state_file {
+        Some(path) => Some(StateFile::create(path, operations, 0)?),
+        None => None,
+    };
+    let mut records = args
+        .print0
+        .then(|| std::io::BufWriter::new(std::io::stdout().lock()));
+    let mut pager = if args.paged() {
+        Some(PagerWriter::spawn().context("Failed to launch the pager.")?)
+    } else {
+        None
+    };
+    let mut report = Report {
+        dry_run: args.dry_run,
+        ..Report::default()
+    };
+    let mut confirms = args.interactive && !args.dry_run;
+    let mut quit = false;
+    // Only executed ones, not declined, to be rolled back.
+    let mut executed = Vec::new();
+    if let Some(hooks) = &args.hooks {
+        hooks.on_plan(operations);
+    }
+    for (i, o) in operations.iter().enumerate() {
+        if confirms {
+            match prompt_confirmation(args, o, answers)? {
+                Confirmation::Yes => {}
+                Confirmation::All => confirms = false,
+                Confirmation::No => {
+                    report.declined += 1;
+                    if let Some(state) = state.as_mut() {
+                        state.complete(i + 1)?;
+                    }
+                    continue;
+                }
+                Confirmation::Quit => {
+                    report.declined += operations.len() - i;
+                    args.reporter().info(&format!(
+                        "{} {}",
+                        "Info:".bright_cyan(),
+                        "Quit, skipping the rest.".dimmed()
+                    ));
+                    // The rest is kept in the state file to be resumed.
+                    if let Some(state_file) = state_file {
+                        args.reporter().info(&format!(
+                            "{} --resume --state-file {}",
+                            "Resume them by".dimmed(),
+                            state_file.to_string_lossy().yellow().underline()
+                        ));
+                    }
+                    quit = true;
+                    break;
+                }
             }
-            if args.dry_run {
-                return Ok(());
+        }
+        match args.hooks.as_ref().map(|hooks| hooks.on_before(o)) {
+            None | Some(HookDecision::Proceed) => {}
+            Some(HookDecision::Skip) => {
+                report.declined += 1;
+                if let Some(state) = state.as_mut() {
+                    state.complete(i + 1)?;
+                }
+                continue;
             }
-            execute_move_or_copy(o, args)?;
-            if !args.quiet {
-                println!(
-                    "{} → {}",
-                    o.src.text.green().underline(),
-                    o.dst.text.green().underline()
-                );
+            Some(HookDecision::Abort) => {
+                report.declined += operations.len() - i;
+                quit = true;
+                break;
             }
         }
-        OperationKind::Copy => {
-            if !args.quiet && (args.verbose || args.dry_run) {
-                println!(
-                    "{} {}{}{}",
-                    "Copy".dimmed(),
-                    o.src.text.dimmed().underline(),
-                    " → ".dimmed(),
-                    o.dst.text.dimmed().underline()
-                );
+        let _operating = OperatingGuard::new();
+        let missing_dirs = match o.kind {
+            OperationKind::Remove => 0,
+            _ => o
+                .dst
+                .path
+                .ancestors()
+                .skip(1)
+                .take_while(|a| !a.as_os_str().is_empty() && !a.exists())
+                .count(),
+        };
+        let result = match args.timeout {
+            Some(timeout) if !args.dry_run => execute_operation_within(o, args, timeout),
+            _ => execute_operation_to(o, args, pager.as_mut()),
+        };
+        if let Some(log) = log.as_deref_mut() {
+            log.record(o, &result)?;
+        }
+        if let Some(hooks) = &args.hooks {
+            hooks.on_after(o, &result);
+        }
+        if let (Ok(_), Some(records)) = (&result, records.as_mut()) {
+            write_record(records, o).context("Failed to write records.")?;
+        }
+        if let Err(err) = result {
+            let err = err.context(Failure::Filesystem);
+            if let Some(records) = records.as_mut() {
+                records.flush().context("Failed to write records.")?;
             }
-            if args.dry_run {
-                return Ok(());
+            if !args.atomic || args.dry_run {
+                return Err(err);
             }
-            execute_move_or_copy(o, args)?;
-            if !args.quiet {
-                println!(
-                    "{} → {}",
-                    o.src.text.green().underline(),
-                    o.dst.text.green().underline()
-                );
+            let rollback = rollback_operations(&executed, args, log)?;
+            if rollback.restored {
+                if let Some(state) = state.as_mut() {
+                    state.complete(0)?;
+                }
             }
+            return Err(err.context(rollback));
         }
-        OperationKind::Remove => {
-            if !args.quiet && (args.verbose || args.dry_run) {
-                println!("{} {}", "Remove".dimmed(), o.src.text.dimmed().underline());
+        if args.dry_run {
+            continue;
+        }
+        report.add(o);
+        report.created_dirs += missing_dirs;
+        executed.push(o.to_owned());
+        if let Some(state) = state.as_mut() {
+            state.complete(i + 1)?;
+        }
+        if INTERRUPTED.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            if let Some(records) = records.as_mut() {
+                records.flush().context("Failed to write records.")?;
             }
-            if args.dry_run {
-                return Ok(());
+            return Err(InterruptedError {
+                completed: i + 1 - report.declined,
+                pending: operations.len() - i - 1,
+                state_file: state_file.map(Path::to_path_buf),
             }
-            execute_remove(o, args)?;
-            if !args.quiet {
-                println!("Removed {}", o.src.text.green().underline());
+            .into());
+        }
+    }
+    if let Some(records) = records.as_mut() {
+        records.flush().context("Failed to write records.")?;
+    }
+    if let Some(state_file) = state_file {
+        if !args.keep_state && !quit {
+            std::fs::remove_file(state_file).with_context(|| {
+                format!(
+                    "Failed to remove state file. {}",
+                    state_file.to_string_lossy().yellow().underline()
+                )
+            })?;
+        }
+    }
+    report.elapsed = started.elapsed();
+    report.stats.duration = report.elapsed;
+    Ok(report)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Confirmation {
+    Yes,
+    No,
+    /// Yes to all remaining operations
+    All,
+    /// Quit, skipping the remaining operations
+    Quit,
+}
+
+/// Ask whether to execute the operation, which quits at the end of input.
+pub fn prompt_confirmation(
+    args: &CommandLine,
+    o: &Operation,
+    reader: &mut impl std::io::BufRead,
+) -> Result<Confirmation> {
+    let prompt = format!(
+        "{} {}{}{}? [y,n,a,q] > ",
+        match o.kind {
+            OperationKind::Move => "Move",
+            OperationKind::Rename => "Rename",
+            OperationKind::Copy => "Copy",
+            OperationKind::Remove => "Remove",
+        },
+        o.src.text.underline(),
+        if matches!(o.kind, OperationKind::Remove) {
+            ""
+        } else {
+            " → "
+        },
+        if matches!(o.kind, OperationKind::Remove) {
+            ""
+        } else {
+            &o.dst.text
+        }
+        .underline()
+    );
+    loop {
+        if args.stdout_occupied() {
+            eprint!("{}", prompt);
+            std::io::stderr().flush()?;
+        } else {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+        }
+        let mut ans = String::new();
+        if reader.read_line(&mut ans)? == 0 {
+            return Ok(Confirmation::Quit);
+        }
+        match ans.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Ok(Confirmation::Yes),
+            "n" | "no" => return Ok(Confirmation::No),
+            "a" | "all" => return Ok(Confirmation::All),
+            "q" | "quit" => return Ok(Confirmation::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// Report of a run with statistics of executed operations.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    /// Executed operations in the order of execution
+    pub operations: Vec<Operation>,
+    pub files: usize,
+    pub dirs: usize,
+    /// Total size of files, including the contents of copied directories
+    pub bytes: u64,
+    pub created_dirs: usize,
+    /// Sources whose lines are deleted with `--allow-removal`
+    pub skipped: usize,
+    /// Operations skipped at the prompt because of their conflicts
+    pub skipped_operations: Vec<Operation>,
+    /// Operations declined with `--interactive` or by hooks
+    pub declined: usize,
+    /// Sources excluded by filters or at the preview
+    pub excluded: usize,
+    pub dry_run: bool,
+    pub elapsed: std::time::Duration,
+    pub stats: OperationStats,
+}
+
+impl Report {
+    /// Number of executed operations.
+    pub fn processed(&self) -> usize {
+        self.files + self.dirs
+    }
+
+    fn add(&mut self, o: &Operation) {
+        self.operations.push(o.to_owned());
+        let bytes = if o.src.meta.is_dir {
+            self.dirs += 1;
+            if matches!(o.kind, OperationKind::Copy) {
+                fs_extra::dir::get_size(&o.src.path).unwrap_or(0)
+            } else {
+                0
             }
+        } else {
+            self.files += 1;
+            o.src.meta.size
+        };
+        self.bytes += bytes;
+        self.stats.count += 1;
+        if matches!(o.kind, OperationKind::Copy) {
+            self.stats.bytes += bytes;
         }
-    };
-    Ok(())
+    }
 }
 
-pub fn execute_move_or_copy(operation: &Operation, args: &CommandLine) -> Result<()> {
-    let Operation { kind, src, dst, .. } = operation;
-    let moving = matches!(kind, OperationKind::Move);
-    let dst_parent = create_dir(dst, args)?;
-    if should_relocate(&src.path, &dst_parent) {
-        if !args.quiet && args.verbose {
-            println!(
-                "{} {} {}",
-                if moving { "Moving" } else { "Copying" }.dimmed(),
-                src.abs.to_string_lossy().dimmed().underline(),
-                dst_parent.to_string_lossy().dimmed().underline()
-            );
+/// Statistics of executed operations printed by `--stat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OperationStats {
+    pub count: usize,
+    /// Total size of copied files, excluding moved ones
+    pub bytes: u64,
+    pub duration: std::time::Duration,
+}
+
+impl OperationStats {
+    /// Table of the statistics with average throughput of copying.
+    pub fn display(&self) -> String {
+        let duration = self.duration.as_secs_f64();
+        let throughput = if duration > 0.0 {
+            human_size((self.bytes as f64 / duration) as u64) + "/s"
+        } else {
+            "-".to_string()
+        };
+        [
+            ("Processed", self.count.to_string()),
+            ("Copied", human_size(self.bytes)),
+            ("Elapsed", format!("{:.3}s", duration)),
+            ("Throughput", throughput),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{:<12}{}", name.dimmed(), value.bold()))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let count =
+            |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        let mut items = Vec::new();
+        if self.files > 0 || self.dirs == 0 {
+            items.push(count(self.files, "file", "files"));
         }
-        if moving {
-            fs_extra::move_items(&[&src.path], &dst_parent, &CopyOptions::default()).with_context(
-                || {
-                    format!(
-                        "Failed to move {} to {}",
-                        src.text.yellow().underline(),
-                        dst_parent.to_string_lossy().yellow().underline()
-                    )
-                },
+        if self.dirs > 0 {
+            items.push(count(self.dirs, "directory", "directories"));
+        }
+        write!(
+            f,
+            "Processed {} ({}) in {:.1}s",
+            items.join(" and "),
+            human_size(self.bytes),
+            self.elapsed.as_secs_f64()
+        )?;
+        if self.created_dirs > 0 {
+            write!(
+                f,
+                ", created {}",
+                count(self.created_dirs, "directory", "directories")
             )?;
-        } else {
-            fs_extra::copy_items(&[&src.path], &dst_parent, &CopyOptions::default()).with_context(
-                || {
-                    format!(
-                        "Failed to move {} to {}",
-                        src.text.yellow().underline(),
-                        dst_parent.to_string_lossy().yellow().underline()
-                    )
-                },
+        }
+        if self.skipped > 0 {
+            write!(
+                f,
+                ", skipped {} of deleted lines",
+                count(self.skipped, "source", "sources")
             )?;
         }
-    }
-    // Rename if its file name need to be changed.
-    // NOTE Can be unwrapped safely, `src` and `dst` cannot be root nor `..`.
-    let src_basename = src.path.file_name().unwrap();
-    let dst_basename = dst.path.file_name().unwrap();
-    if src_basename != dst_basename {
-        let from = &dst_parent.join(src_basename);
-        let to = &dst_parent.join(dst_basename);
-        if !args.quiet && args.verbose {
-            println!(
-                "{} {}{}{}",
-                "Renaming".dimmed(),
-                from.to_string_lossy().dimmed().underline(),
-                " → ".dimmed(),
-                to.to_string_lossy().dimmed().underline()
-            );
+        if self.declined > 0 {
+            write!(
+                f,
+                ", skipped {}",
+                count(self.declined, "declined operation", "declined operations")
+            )?;
         }
-        // Destination is never over-written, ensured when the operation was made.
-        std::fs::rename(from, to).with_context(|| {
-            format!(
-                "Failed to rename {} to {}",
-                from.to_string_lossy().yellow().underline(),
-                to.to_string_lossy().yellow().underline()
-            )
-        })?;
+        if !self.skipped_operations.is_empty() {
+            write!(
+                f,
+                ", skipped {}",
+                count(
+                    self.skipped_operations.len(),
+                    "conflicting operation",
+                    "conflicting operations"
+                )
+            )?;
+        }
+        Ok(())
     }
-    Ok(())
 }
 
-/// Create parent directory if missing.
-pub fn create_dir(dst: &Destination, args: &CommandLine) -> Result<PathBuf> {
-    let current_dir = std::env::current_dir().context("Failed to get current directory.")?;
-    let dst_parent = if dst.text.contains(std::path::MAIN_SEPARATOR) {
-        dst.path.parent().unwrap()
+/// Undo completed operations in reverse order.
+///
+/// Operations which failed to be undone are saved to a recovery file,
+/// which can be retried by `--resume`.
+pub fn rollback_operations(
+    completed: &[Operation],
+    args: &CommandLine,
+    mut log: Option<&mut OperationLog>,
+) -> Result<RollbackError> {
+    let mut failed = Vec::new();
+    let mut irreversible = 0;
+    for o in completed.iter().rev() {
+        let result = o
+            .inverse()
+            .map_err(anyhow::Error::from)
+            .and_then(|inverse| {
+                let result = execute_operation(&inverse, args);
+                if let Some(log) = log.as_deref_mut() {
+                    log.record(&inverse, &result)?;
+                }
+                result
+            });
+        match result {
+            Ok(_) => args.reporter().info(&format!(
+                "{} {} → {}",
+                "Rolled back".yellow(),
+                o.src.text.yellow().underline(),
+                o.dst.text.yellow().underline()
+            )),
+            Err(err) => {
+                args.reporter().error(&format!(
+                    "{} {} → {}\n{:?}",
+                    "Failed to roll back".bright_red(),
+                    o.src.text.yellow().underline(),
+                    o.dst.text.yellow().underline(),
+                    err
+                ));
+                match o.inverse() {
+                    Ok(inverse) => failed.push(inverse),
+                    Err(_) => irreversible += 1,
+                }
+            }
+        }
+    }
+    let recovery_file = if failed.is_empty() {
+        None
     } else {
-        &current_dir
+        let path = std::env::temp_dir().join(format!("moove-recovery-{}", std::process::id()));
+        save_state(&path, &failed, 0)?;
+        Some(path)
     };
-    if !dst_parent.exists() {
-        if !args.quiet && args.verbose {
-            println!(
-                "{} {}",
-                "Creating directory".dimmed(),
-                dst_parent.to_string_lossy().dimmed().underline()
-            );
+    Ok(RollbackError {
+        restored: failed.is_empty() && irreversible == 0,
+        recovery_file,
+    })
+}
+
+/// Log of executed operations appended to `--log-file`.
+pub struct OperationLog {
+    path: PathBuf,
+    file: std::fs::File,
+    current_dir: PathBuf,
+    format: LogFormat,
+    rotate_size: Option<u64>,
+}
+
+/// Line of the log file in the JSON Lines format.
+#[derive(Debug, Serialize)]
+struct LogRecord<'a> {
+    ts: String,
+    kind: &'a str,
+    src: String,
+    dst: String,
+    result: String,
+}
+
+impl OperationLog {
+    pub fn open(path: &Path) -> Result<OperationLog> {
+        OperationLog::open_with(path, LogFormat::Tsv, None)
+    }
+
+    /// Open the log file of the format, rotated once it exceeds `rotate_size` bytes.
+    pub fn open_with(
+        path: &Path,
+        format: LogFormat,
+        rotate_size: Option<u64>,
+    ) -> Result<OperationLog> {
+        let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+        Ok(OperationLog {
+            path: path.to_owned(),
+            file: OperationLog::append(path)?,
+            current_dir,
+            format,
+            rotate_size,
+        })
+    }
+
+    fn append(path: &Path) -> Result<std::fs::File> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                format!(
+                    "Failed to open log file. {}",
+                    path.to_string_lossy().yellow().underline()
+                )
+            })
+    }
+
+    /// Rename the log file with `.1` appended, replacing the previous one, if it exceeds the size.
+    fn rotate(&mut self) -> Result<()> {
+        let Some(max) = self.rotate_size else {
+            return Ok(());
+        };
+        let size = self.file.metadata().map(|m| m.len()).unwrap_or(0);
+        if size <= max {
+            return Ok(());
         }
-        std::fs::create_dir_all(dst_parent).with_context(|| {
+        let mut rotated = self.path.to_owned().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(&self.path, &rotated).with_context(|| {
             format!(
-                "Failed to create directory. {}",
-                dst_parent.to_string_lossy().yellow().underline()
+                "Failed to rotate log file. {}",
+                self.path.to_string_lossy().yellow().underline()
             )
         })?;
+        self.file = OperationLog::append(&self.path)?;
+        Ok(())
+    }
+
+    /// Append a line of timestamp, kind, absolute paths and result of the operation.
+    pub fn record(&mut self, o: &Operation, result: &Result<()>) -> Result<()> {
+        self.rotate()?;
+        let result = match result {
+            Ok(_) => "ok".to_owned(),
+            Err(err) => strip_colors(&format!("error: {:#}", err)).replace(['\t', '\n'], " "),
+        };
+        let (src, dst) = (
+            escape_path(&o.src.abs),
+            escape_path(&self.current_dir.join(&o.dst.path)),
+        );
+        match self.format {
+            LogFormat::Tsv => writeln!(
+                self.file,
+                "{}\t{}\t{}\t{}\t{}",
+                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                o.kind.name(),
+                escape_state_field(&src),
+                escape_state_field(&dst),
+                result
+            ),
+            LogFormat::Jsonl => {
+                let record = LogRecord {
+                    ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                    kind: o.kind.name(),
+                    src,
+                    dst,
+                    result,
+                };
+                writeln!(self.file, "{}", serde_json::to_string(&record)?)
+            }
+        }
+        .and_then(|_| self.file.flush())
+        .context("Failed to write log file.")
     }
-    Ok(dst_parent.to_path_buf())
 }
 
-pub fn should_relocate(src: &Path, dst_parent: &Path) -> bool {
-    // NOTE `Path.parent()` returns `Some("")` in case of simple relative path.
-    if let Some(src_parent) = src.parent() {
-        !src_parent.as_os_str().is_empty() && src_parent != dst_parent
-    } else {
-        false
+/// Read paths or wildcard patterns separated by new lines.
+pub fn read_paths(reader: impl std::io::BufRead) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read paths.")?;
+        paths.push(line.trim_end_matches('\r').to_owned());
     }
+    Ok(paths)
 }
 
-pub fn execute_remove(operation: &Operation, _args: &CommandLine) -> Result<()> {
-    let path = &operation.src.abs;
-    if path.is_dir() {
-        std::fs::remove_dir_all(path).with_context(|| {
-            format!(
-                "Failed to remove {}",
-                path.to_string_lossy().yellow().underline()
-            )
-        })?;
+/// Read paths or wildcard patterns from the file, or stdin if it is `-`,
+/// skipping blank lines and comments starting with `#`.
+pub fn read_paths_from_file(path: &Path) -> Result<Vec<String>> {
+    let paths = if path == Path::new("-") {
+        read_paths(std::io::stdin().lock())?
     } else {
-        std::fs::remove_file(path).with_context(|| {
+        let file = std::fs::File::open(path).with_context(|| {
             format!(
-                "Failed to remove {}",
+                "Failed to open {}",
                 path.to_string_lossy().yellow().underline()
             )
         })?;
+        read_paths(std::io::BufReader::new(file))?
+    };
+    Ok(paths
+        .into_iter()
+        .filter(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .collect())
+}
+
+/// Read literal paths separated by null characters, which may contain any character but null.
+pub fn read_null_separated_paths(reader: impl std::io::BufRead) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for record in reader.split(b'\0') {
+        let record = record.context("Failed to read paths.")?;
+        #[cfg(target_family = "unix")]
+        let path = {
+            use std::os::unix::ffi::OsStringExt;
+            PathBuf::from(std::ffi::OsString::from_vec(record))
+        };
+        #[cfg(not(target_family = "unix"))]
+        let path =
+            PathBuf::from(String::from_utf8(record).context("Failed to convert path to UTF-8.")?);
+        paths.push(path);
     }
-    Ok(())
+    Ok(paths)
 }
 
-#[cfg(test)]
-mod lib {
-    use std::path::PathBuf;
+pub fn sources_from(args: &CommandLine) -> Result<Vec<Source>> {
+    Ok(sources_with_excluded(args)?.0)
+}
 
-    use anyhow::{Context, Result};
-    use colored::*;
-    use normpath::PathExt;
+/// `sources_from` with the number of sources excluded by filters like `--exclude-pattern`,
+/// including hidden ones and counting a directory not descended into as one.
+pub fn sources_with_excluded(args: &CommandLine) -> Result<(Vec<Source>, usize)> {
+    let mut sources: Vec<Source> = Vec::new();
+    let mut excluded = 0;
+    // Paths with their roots given literally by arguments
+    let mut paths = Vec::new();
+    let mut matched = std::collections::HashSet::new();
+    for pattern in args.paths.iter() {
+        let root = pattern_root(pattern);
+        for p in list_files_except(std::slice::from_ref(pattern), &mut matched)? {
+            // Keep the root directory as it is.
+            let p = match p.trim_end_matches(SEPARATORS) {
+                "" => p.as_str(),
+                trimmed => trimmed,
+            };
+            let path = PathBuf::from(if cfg!(target_family = "windows") {
+                p.replace('/', "\\")
+            } else {
+                p.to_string()
+            });
+            paths.push((path, root.to_owned()));
+        }
+    }
+    paths.extend(
+        args.literal_paths
+            .iter()
+            .map(|p| (p.to_owned(), p.to_owned())),
+    );
+    // The lock file created before listing is not a source, whether it is matched or given.
+    let lock = if args.locks() {
+        let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+        current_dir.join(LOCK_FILE_NAME).file_id()
+    } else {
+        None
+    };
+    for (path, root) in paths.iter() {
+        // Contents of the root directory are not listed either.
+        if path.absolute()?.as_path().parent().is_none() {
+            return Err(MooveError::RootSource(path.to_owned()).into());
+        }
+        let stat = &symlink_metadata_of(path)?;
+        let listed = !(stat.is_file() || stat.is_symlink() || (args.directory && !args.recursive));
+        if !listed {
+            excluded += usize::from(!put_source_under(&mut sources, path, root, args)?);
+        } else if args.recursive {
+            excluded += collect_recursive(path, 1, args.max_depth, args, &mut sources)?;
+        } else {
+            for child in children_of(path, args)? {
+                excluded += usize::from(!put_source(&mut sources, &child, args)?);
+            }
+        }
+        if lock.is_some() {
+            sources.retain(|s| s.inode != lock);
+        }
+        if listed && sources.is_empty() {
+            return Err(MooveError::EmptyDirectory(path.to_owned()).into());
+        }
+    }
+    let sort_key = match args.sort_by {
+        Some(key) => Some(key),
+        None if args.sort => Some(SortKey::Name),
+        None => None,
+    };
+    if let Some(key) = sort_key {
+        sources.sort_by(|a, b| {
+            let ordering = compare_sources(a, b, key);
+            if args.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+    if args.shuffle {
+        use rand::{seq::SliceRandom, SeedableRng};
+        let mut rng = match args.seed {
+            Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+            None => rand::rngs::StdRng::from_entropy(),
+        };
+        sources.shuffle(&mut rng);
+    }
+    if args.group_by_extension {
+        sources.sort_by_key(extension_of);
+    }
+    Ok((sources, excluded))
+}
 
-    use super::*;
+/// Children of the directory in natural order.
+fn children_of(dir: &Path, args: &CommandLine) -> Result<Vec<PathBuf>> {
+    let mut children = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| {
+        format!(
+            "Failed to list files of directory. {}",
+            dir.to_string_lossy().yellow().underline()
+        )
+    })? {
+        children.push(entry?.path());
+    }
+    children.sort_unstable_by(|a, b| {
+        let ordering = natord::compare(&a.to_string_lossy(), &b.to_string_lossy());
+        if args.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(children)
+}
 
-    /// Create temporary files before starting tests and removed by RAII.
-    struct Setup {
-        sandbox: PathBuf,
-        args: CommandLine,
+/// Collect descendants of the directory as sources, `depth` is that of its children,
+/// returning the number of excluded ones.
+///
+/// Hidden and excluded directories are not descended into.
+pub fn collect_recursive(
+    root: &Path,
+    depth: usize,
+    max_depth: Option<usize>,
+    args: &CommandLine,
+    sources: &mut Vec<Source>,
+) -> Result<usize> {
+    let mut excluded = 0;
+    for child in children_of(root, args)? {
+        let stat = symlink_metadata_of(&child)?;
+        if !stat.is_dir() {
+            excluded += usize::from(!put_source(sources, &child, args)?);
+            continue;
+        }
+        let text = child.to_string_lossy();
+        if (!args.with_hidden && child.is_hidden(&stat))
+            || args.exclude_patterns.iter().any(|p| p.is_match(&text))
+        {
+            excluded += 1;
+            continue;
+        }
+        if args.directory {
+            excluded += usize::from(!put_source(sources, &child, args)?);
+        }
+        if max_depth.map_or(true, |max| depth < max) {
+            excluded += collect_recursive(&child, depth + 1, max_depth, args, sources)?;
+        }
     }
+    Ok(excluded)
+}
 
-    impl Setup {
-        ///
-        /// Create following tree.
-        ///
-        /// ```ignore
-        /// {sandbox}/
-        ///   1/
-        ///   ├─1.txt
-        ///   ├─11/
-        ///   │  └─11.txt
-        ///   ├─12/
-        ///   │  └─12.txt
-        ///   2/
-        ///   ├─2.txt
-        ///   ├─21/
-        ///   │  ├─21.txt
-        ///   │  └─211/
-        ///   │      └─211.txt
-        ///   └─22
-        ///       └─22.txt
-        /// ```
-        fn init(key: &str) -> Result<Setup> {
-            let sandbox = &std::env::temp_dir().join("moove").join("test").join(key);
-            std::fs::create_dir_all(sandbox)?;
-            let dirs: Vec<PathBuf> = vec!["1", "1/11", "1/12", "2", "2/21", "2/21/211", "2/22"]
-                .iter()
-                .map(|d| {
-                    sandbox.join(PathBuf::from(if cfg!(target_family = "windows") {
-                        d.replace('/', "\\")
-                    } else {
-                        d.to_string()
-                    }))
+/// Extension of the source in lowercase.
+pub fn extension_of(src: &Source) -> Option<String> {
+    src.path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+pub fn compare_sources(a: &Source, b: &Source, key: SortKey) -> std::cmp::Ordering {
+    match key {
+        SortKey::Name => std::cmp::Ordering::Equal,
+        SortKey::Mtime => a.meta.mtime.cmp(&b.meta.mtime),
+        SortKey::Size => a.meta.size.cmp(&b.meta.size),
+        SortKey::Ext => extension_of(a).cmp(&extension_of(b)),
+        SortKey::Depth => a
+            .path
+            .components()
+            .count()
+            .cmp(&b.path.components().count()),
+    }
+    .then_with(|| natord::compare(&a.text, &b.text))
+}
+
+/// Paths matching the patterns, each listed once even if matched by several patterns.
+///
+/// Paths given literally are listed as many times as given, to be rejected as duplicates.
+pub fn list_files(args: &[String]) -> Result<Vec<String>> {
+    list_files_except(args, &mut std::collections::HashSet::new())
+}
+
+/// `list_files` except paths already in `matched`, adding listed ones to it.
+pub fn list_files_except(
+    args: &[String],
+    matched: &mut std::collections::HashSet<PathBuf>,
+) -> Result<Vec<String>> {
+    use glob::glob;
+    let mut paths = Vec::new();
+    for arg in args.iter() {
+        let mut globbed = Vec::new();
+        let pattern = if cfg!(target_family = "windows") {
+            windows_pattern(arg)
+        } else {
+            arg.to_owned()
+        };
+        for pattern in expand_braces(&pattern) {
+            for path in glob(&pattern)
+                .with_context(|| format!("Invalid pattern {}", arg.yellow().underline()))?
+            {
+                globbed.push(
+                    path.with_context(|| format!("Failed to glob {}", arg.yellow().underline()))?,
+                )
+            }
+        }
+        // Globbing does not match broken symbolic links by their literal paths.
+        if globbed.is_empty() && Path::new(arg).symlink_metadata().is_ok() {
+            globbed.push(PathBuf::from(arg));
+        }
+        if globbed.is_empty() {
+            anyhow::bail!("Failed to access {}", arg);
+        }
+        globbed.sort_unstable();
+        let literal = !arg.contains(['*', '?', '[', '{']);
+        for g in globbed {
+            // Canonicalize the parent only, not to resolve the path itself if it is a symbolic link.
+            let parent = match g.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let key = match (parent.canonicalize(), g.file_name()) {
+                (Ok(parent), Some(name)) => parent.join(name),
+                _ => g.to_owned(),
+            };
+            if matched.insert(key) || literal {
+                paths.push(g.to_string_lossy().to_string());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Patterns expanded from braces like a shell, e.g. `img{01,02}.jpg` into `img01.jpg` and `img02.jpg`.
+///
+/// Braces can be nested, and `{`, `}` and `,` can be escaped by a backslash.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    let mut expanded = vec![String::new()];
+    while i < chars.len() {
+        expanded = product(expanded, expand_sequence(&chars, &mut i));
+        // Closing braces and commas out of braces are literal.
+        if let Some(c) = chars.get(i) {
+            expanded = product(expanded, vec![c.to_string()]);
+            i += 1;
+        }
+    }
+    expanded
+}
+
+/// Patterns expanded from the sequence until the end, `,` or `}`.
+fn expand_sequence(chars: &[char], i: &mut usize) -> Vec<String> {
+    let mut expanded = vec![String::new()];
+    while let Some(&c) = chars.get(*i) {
+        match c {
+            '\\' if matches!(chars.get(*i + 1), Some('{' | '}' | ',' | '\\')) => {
+                expanded = product(expanded, vec![chars[*i + 1].to_string()]);
+                *i += 2;
+            }
+            ',' | '}' => break,
+            '{' => {
+                let start = *i;
+                *i += 1;
+                let mut alternatives = Vec::new();
+                let mut commas = 0;
+                let closed = loop {
+                    alternatives.extend(expand_sequence(chars, i));
+                    match chars.get(*i) {
+                        Some(',') => {
+                            commas += 1;
+                            *i += 1;
+                        }
+                        Some('}') => {
+                            *i += 1;
+                            break true;
+                        }
+                        _ => break false,
+                    }
+                };
+                if closed && commas > 0 {
+                    expanded = product(expanded, alternatives);
+                } else {
+                    // Not a list of alternatives, e.g. `{a}` or an unclosed brace.
+                    *i = start + 1;
+                    expanded = product(expanded, vec!["{".to_owned()]);
+                }
+            }
+            c => {
+                expanded = product(expanded, vec![c.to_string()]);
+                *i += 1;
+            }
+        }
+    }
+    expanded
+}
+
+/// Concatenations of every pair of the heads and the tails.
+fn product(heads: Vec<String>, tails: Vec<String>) -> Vec<String> {
+    heads
+        .iter()
+        .flat_map(|head| tails.iter().map(move |tail| format!("{}{}", head, tail)))
+        .collect()
+}
+
+/// Pattern with separators of backslashes replaced by slashes,
+/// where a backslash before a bracket escapes it like `\[`.
+/// `*` and `?` are never escaped, since they cannot be in file names on Windows.
+pub fn windows_pattern(pattern: &str) -> String {
+    let mut normalized = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&m @ ('[' | ']'))) => {
+                chars.next();
+                normalized.push_str(&format!("[{}]", m));
+            }
+            ('\\', _) => normalized.push('/'),
+            (c, _) => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Whether the path is hidden by itself or by its ancestors below the root, not counting the root.
+fn is_hidden_under(path: &Path, root: &Path) -> Result<bool, MooveError> {
+    let below = match path.strip_prefix(root) {
+        Ok(below) if below.as_os_str().is_empty() => return Ok(false),
+        Ok(below) => below,
+        Err(_) => Path::new(""),
+    };
+    let hidden_ancestor = cfg!(target_family = "unix")
+        && below
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .any(|c| matches!(c, std::path::Component::Normal(name) if name.to_string_lossy().starts_with('.')));
+    // Symbolic links are checked by themselves, not by their targets.
+    let meta = symlink_metadata_of(path)?;
+    Ok(hidden_ancestor || path.is_hidden(&meta))
+}
+
+/// Leading components of the pattern without wildcards, which is the pattern itself if literal.
+pub fn pattern_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| {
+            !c.as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{'])
+        })
+        .collect()
+}
+
+pub fn put_source(
+    sources: &mut Vec<Source>,
+    path: &Path,
+    args: &CommandLine,
+) -> Result<bool, MooveError> {
+    put_source_under(sources, path, path.parent().unwrap_or(Path::new("")), args)
+}
+
+/// `put_source` for the path under the root given by an argument,
+/// which is hidden if any component below the root is hidden.
+///
+/// It returns whether the source is put, which is not if excluded by filters.
+pub fn put_source_under(
+    sources: &mut Vec<Source>,
+    path: &Path,
+    root: &Path,
+    args: &CommandLine,
+) -> Result<bool, MooveError> {
+    let abs = path.absolute()?;
+    let abs = abs.as_path();
+    if abs.parent().is_none() {
+        return Err(MooveError::RootSource(path.to_owned()));
+    }
+    if !args.with_hidden && is_hidden_under(path, root)? {
+        return Ok(false);
+    }
+    if let Some(file_type) = args.file_type {
+        if !is_of_type(path, file_type, args.follow_symlinks)? {
+            return Ok(false);
+        }
+    }
+    if !args.extensions.is_empty() {
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+        if !args
+            .extensions
+            .iter()
+            .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+        {
+            return Ok(false);
+        }
+    }
+    let new_path = if args.absolute { abs } else { path };
+    let new_src = Source::from_path(new_path)?;
+    if args
+        .exclude_patterns
+        .iter()
+        .any(|pattern| pattern.is_match(&new_src.text))
+    {
+        return Ok(false);
+    }
+    // The same file may be reached through symbolic links or hard links by different paths.
+    for src in sources.iter() {
+        if src.abs.is_identical(&new_src.abs)
+            || (new_src.inode.is_some() && src.inode == new_src.inode)
+        {
+            if src.text == new_src.text {
+                return Err(MooveError::DuplicateSource(new_src.abs));
+            }
+            return Err(MooveError::AliasedSource {
+                path: new_src.path,
+                other: src.path.to_owned(),
+            });
+        }
+    }
+    sources.push(new_src);
+    Ok(true)
+}
+
+/// Whether the path is of the type, by the target if it is a symbolic link to be followed.
+///
+/// Broken symbolic links are of their own type even if followed.
+fn is_of_type(path: &Path, file_type: FileType, follow_symlinks: bool) -> Result<bool, MooveError> {
+    let meta = match path.metadata() {
+        Ok(meta) if follow_symlinks => meta,
+        _ => symlink_metadata_of(path)?,
+    };
+    Ok(match file_type {
+        FileType::File => meta.is_file(),
+        FileType::Directory => meta.is_dir(),
+        FileType::Symlink => meta.is_symlink(),
+    })
+}
+
+/// Sources after previewing the text to edit with `--preview`, excluding patterns on demand.
+///
+/// It does nothing if stdin is not a terminal or the text is not edited.
+pub fn preview_sources(mut sources: Vec<Source>, args: &CommandLine) -> Result<Vec<Source>> {
+    use std::io::IsTerminal;
+    if !args.preview
+        || !std::io::stdin().is_terminal()
+        || args.flatten.is_some()
+        || args.organize
+        || args.renames_without_editing()
+    {
+        return Ok(sources);
+    }
+    loop {
+        let text = text_from(&sources, args);
+        let paged = std::env::var_os("NO_PAGER").is_none() && std::io::stdout().is_terminal();
+        match paged.then(PagerWriter::spawn) {
+            Some(Ok(mut pager)) => {
+                // Failure to write means the pager has been closed by user.
+                let _ = pager.write_all(text.as_bytes());
+            }
+            _ => args.reporter().info(text.trim_end()),
+        }
+        match prompt_preview_from(args, &mut std::io::stdin().lock())? {
+            Preview::Continue => return Ok(sources),
+            Preview::Exclude(pattern) => sources.retain(|src| !pattern.is_match(&src.text)),
+            Preview::Abort => return Err(Failure::Aborted.into()),
+        }
+    }
+}
+
+/// Ask whether to edit the previewed text, which aborts at the end of input.
+pub fn prompt_preview_from(
+    args: &CommandLine,
+    reader: &mut impl std::io::BufRead,
+) -> Result<Preview> {
+    let prompt = format!(
+        "{}{} them, e{}{} a pattern or {}{}? > ",
+        "E".bold().underline(),
+        "dit".bold(),
+        "x".bold().underline(),
+        "clude".bold(),
+        "A".bold().underline(),
+        "bort".bold()
+    );
+    loop {
+        let answer = prompt_from(args, reader, &prompt, |ans| match ans {
+            "" | "e" | "edit" => Some(Resolution::Edit),
+            "x" | "exclude" => Some(Resolution::Skip),
+            "a" | "abort" => Some(Resolution::Abort),
+            _ => None,
+        })?;
+        match answer {
+            Resolution::Edit => return Ok(Preview::Continue),
+            Resolution::Skip => {}
+            _ => return Ok(Preview::Abort),
+        }
+        if args.stdout_occupied() {
+            eprint!("Pattern to exclude > ");
+            std::io::stderr().flush()?;
+        } else {
+            print!("Pattern to exclude > ");
+            std::io::stdout().flush()?;
+        }
+        let mut pattern = String::new();
+        if reader.read_line(&mut pattern)? == 0 {
+            return Ok(Preview::Abort);
+        }
+        match Regex::new(pattern.trim_end_matches(['\r', '\n'])) {
+            Ok(pattern) => return Ok(Preview::Exclude(pattern)),
+            Err(err) => args.reporter().error(&err.to_string()),
+        }
+    }
+}
+
+pub fn operations_from(sources: &[Source], args: &CommandLine) -> Result<Vec<Operation>> {
+    plan_operations(sources, args).map(|plan| plan.operations)
+}
+
+/// Operations with sources left alone because their lines are deleted or conflict.
+pub fn plan_operations(sources: &[Source], args: &CommandLine) -> Result<Plan> {
+    let planned = |operations| Plan {
+        operations,
+        ..Plan::default()
+    };
+    if let Some(dir) = &args.flatten {
+        return Ok(planned(flatten_operations(sources, dir, args)?));
+    }
+    if args.organize {
+        return Ok(planned(organize_operations(sources, args)?));
+    }
+    if args.renames_without_editing() {
+        return Ok(planned(rename_operations(sources, args)?));
+    }
+    edit_operations(sources, args, text_from(sources, args))
+}
+
+/// Operations planned by editing the text.
+pub fn edit_operations(sources: &[Source], args: &CommandLine, mut text: String) -> Result<Plan> {
+    let editor = args.text_editor();
+    loop {
+        text = strip_errors(&editor.edit(&text)?);
+        let mut skips_conflicts = false;
+        let planned = plan_from_lines_with(sources, &lines_from(&text), args, |o, message| {
+            if !args.asks_resolution() {
+                return Ok(Resolution::Edit);
+            }
+            args.reporter().error(&message.to_string());
+            if skips_conflicts {
+                return Ok(Resolution::Skip);
+            }
+            let resolution = editor.resolve(args, o, message)?;
+            skips_conflicts |= resolution == Resolution::Continue;
+            Ok(resolution)
+        });
+        let errors = match planned {
+            Ok(plan) => {
+                return Ok(Plan {
+                    text: Some(text),
+                    ..plan
                 })
-                .collect();
-            for dir in dirs.iter() {
-                println!("{} {}", "Creating".dimmed(), dir.to_string_lossy().dimmed());
-                std::fs::create_dir_all(dir)?;
             }
-            let files: Vec<PathBuf> = dirs
+            Err(err) => match err.downcast::<InvalidLines>() {
+                Ok(InvalidLines(errors)) => errors,
+                Err(err) => return Err(err),
+            },
+        };
+        if !args.asks_resolution() {
+            // NOTE Can be unwrapped safely, there is at least one error.
+            let first = errors.into_iter().next().unwrap();
+            return Err(first.error.context(Failure::Validation));
+        }
+        // Errors not of any line are not asked yet, unlike the ones chosen to edit.
+        if errors.iter().all(|e| e.line.is_none()) {
+            args.reporter().error(&errors[0].error.to_string());
+            if !editor.redo(args, &errors[0].error)? {
+                return Err(Failure::Aborted.into());
+            }
+        }
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.error.to_string()))
+            .collect();
+        text = text_with_errors(&text, &errors);
+    }
+}
+
+/// Operations planned from the lines of the edited text without any prompt nor output,
+/// failing with `InvalidLines` to list all errors.
+pub fn plan_from_lines(sources: &[Source], lines: &[&str], args: &CommandLine) -> Result<Plan> {
+    plan_from_lines_with(sources, lines, args, |_, _| Ok(Resolution::Edit))
+}
+
+/// Operations planned from the lines, resolving each invalid operation by the callback.
+///
+/// Once an operation is resolved by `Resolution::Edit`, the following invalid ones are
+/// collected without calling back, to fail with `InvalidLines` of all of them.
+pub fn plan_from_lines_with(
+    sources: &[Source],
+    lines: &[&str],
+    args: &CommandLine,
+    mut resolve: impl FnMut(&Operation, &anyhow::Error) -> Result<Resolution>,
+) -> Result<Plan> {
+    let invalid = |error: anyhow::Error| {
+        anyhow::Error::new(InvalidLines(vec![LineError { line: None, error }]))
+    };
+    let pairs: Vec<(&Source, String)> = match args.effective_format() {
+        Format::Lines => {
+            if lines.len() != sources.len() {
+                return Err(invalid(
+                    MooveError::LineMismatch {
+                        expected: sources.len(),
+                        got: lines.len(),
+                    }
+                    .into(),
+                ));
+            }
+            sources
                 .iter()
-                .map(|dir| dir.join(dir.file_name().unwrap()).with_extension("txt"))
-                .collect();
-            for file in files.iter() {
-                println!(
-                    "{} {}",
-                    "Creating".dimmed(),
-                    file.to_string_lossy().dimmed()
-                );
-                std::fs::File::create(file)?;
+                .zip(lines.iter().map(|line| line.to_string()))
+                .collect()
+        }
+        Format::TwoColumn => pairs_from(sources, lines).map_err(invalid)?,
+    };
+    let mut candidates = Vec::new();
+    for &(src, ref line) in pairs.iter() {
+        let (kind, line) = if line.starts_with("//") {
+            (OperationKind::Remove, src.text.as_str())
+        } else if args.copy {
+            (OperationKind::Copy, line.as_str())
+        } else {
+            (OperationKind::Move, line.as_str())
+        };
+        let line = if cfg!(target_family = "windows") {
+            line.replace('/', "\\")
+        } else {
+            line.to_string()
+        };
+        // Only lines of escaped sources are decoded, keeping `%` typed in other lines as it is.
+        let mut dst_path = if src.is_escaped() {
+            unescape_path(&line)
+        } else {
+            PathBuf::from(&line)
+        };
+        let mut line = line;
+        let removing = matches!(kind, OperationKind::Remove);
+        if !removing {
+            if let Some(adjusted) =
+                adjust_destination(&dst_path, args).context(Failure::Validation)?
+            {
+                line = escape_path(&adjusted)
+                    + if line.ends_with(SEPARATORS) {
+                        std::path::MAIN_SEPARATOR_STR
+                    } else {
+                        ""
+                    };
+                dst_path = adjusted;
             }
-            let setup = Setup {
-                sandbox: sandbox.to_owned(),
-                args: CommandLine {
-                    verbose: true,
-                    ..CommandLine::default()
-                },
+        }
+        if !removing && is_unchanged(src, &dst_path, args.name_rules()) {
+            continue;
+        }
+        let kind = match kind {
+            OperationKind::Move => move_kind(src, &dst_path),
+            kind => kind,
+        };
+        candidates.push(Operation {
+            kind,
+            src: src.to_owned(),
+            dst: Destination::new(line.to_owned(), dst_path.to_owned())?,
+        });
+    }
+    reorder_operations_with(&mut candidates, args.cycle_stage).map_err(invalid)?;
+    let mut operations = Vec::new();
+    let mut skipped = Vec::new();
+    // Errors with indices of their lines to write into the text to edit again
+    let mut errors = Vec::new();
+    'candidates: for mut new_operation in candidates {
+        // Validate again after the collision is resolved.
+        while !matches!(new_operation.kind, OperationKind::Remove) {
+            let Err(error) = is_unmoved_source(sources, &operations, &new_operation)
+                .and_then(|_| is_operational_with(&operations, &new_operation, args.name_rules()))
+            else {
+                break;
             };
-            Ok(setup)
+            let error = anyhow::Error::new(error);
+            let line = pairs
+                .iter()
+                .position(|(src, _)| src.abs == new_operation.src.abs);
+            let resolution = if errors.is_empty() {
+                resolve(&new_operation, &error)?
+            } else {
+                Resolution::Edit
+            };
+            match resolution {
+                Resolution::Edit => {
+                    errors.push(LineError { line, error });
+                    continue 'candidates;
+                }
+                Resolution::Abort => return Err(Failure::Aborted.into()),
+                Resolution::Skip | Resolution::Continue => {
+                    skipped.push(new_operation);
+                    continue 'candidates;
+                }
+                // Remove the existing destination in advance.
+                Resolution::Overwrite => operations.push(Operation {
+                    kind: OperationKind::Remove,
+                    src: Source::from_path(&new_operation.dst.path)?,
+                    dst: new_operation.dst.to_owned(),
+                }),
+                Resolution::Rename => {
+                    new_operation.dst = free_destination(&new_operation.dst, &operations)?;
+                }
+            }
         }
+        operations.push(new_operation);
+    }
+    if !errors.is_empty() {
+        return Err(InvalidLines(errors).into());
+    }
+    Ok(Plan {
+        operations,
+        deleted: sources.len() - pairs.len(),
+        skipped,
+        text: None,
+    })
+}
 
-        fn source_from(&self, s: &str) -> Source {
-            let path = self.sandbox.join(if cfg!(target_family = "windows") {
-                s.replace('/', "\\")
-            } else {
+/// Destination with a number appended to its file name like `name (1).ext`,
+/// which neither exists nor is a destination of the operations.
+pub fn free_destination(dst: &Destination, operations: &[Operation]) -> Result<Destination> {
+    // NOTE Can be unwrapped safely, destinations have file names.
+    let name = dst.path.file_name().unwrap();
+    let path = (1..)
+        .map(|n| dst.path.with_file_name(suffixed_name(Path::new(name), n)))
+        .find(|path| {
+            path.symlink_metadata().is_err()
+                && !operations.iter().any(|o| o.dst.path.is_identical(path))
+        })
+        .unwrap();
+    Destination::new(escape_path(&path), path)
+}
+
+/// Operations to move sources into the directory without editing.
+pub fn flatten_operations(
+    sources: &[Source],
+    dir: &Path,
+    args: &CommandLine,
+) -> Result<Vec<Operation>> {
+    // NOTE Can be unwrapped safely, sources cannot be root nor `..`.
+    planned_operations(sources, args, |src| dir.join(src.path.file_name().unwrap()))
+}
+
+/// Operations to move files into directories named after their uppercased extensions,
+/// or `OTHER` for files without extension, in the parent of the first source.
+pub fn organize_operations(sources: &[Source], args: &CommandLine) -> Result<Vec<Operation>> {
+    let base = sources
+        .first()
+        .and_then(|src| src.path.parent())
+        .unwrap_or(Path::new(""))
+        .to_owned();
+    let files: Vec<Source> = sources.iter().filter(|s| !s.meta.is_dir).cloned().collect();
+    planned_operations(&files, args, |src| {
+        let dir = match src.path.extension() {
+            Some(ext) => ext.to_string_lossy().to_uppercase(),
+            None => "OTHER".to_owned(),
+        };
+        // NOTE Can be unwrapped safely, sources cannot be root nor `..`.
+        base.join(dir).join(src.path.file_name().unwrap())
+    })
+}
+
+/// Operations to rename file names by prefix and suffix options without editing.
+///
+/// Prefixes are applied to file names first, then suffixes to their stems,
+/// the case of the whole file names is converted, and finally dates are prepended.
+pub fn rename_operations(sources: &[Source], args: &CommandLine) -> Result<Vec<Operation>> {
+    if (args.to_lowercase || args.to_uppercase)
+        && cfg!(any(target_os = "macos", target_os = "windows"))
+    {
+        args.reporter().warn(
+            "Renaming only in case may appear to be a no-op on case-insensitive filesystems.",
+        );
+    }
+    if let Some(prefix) = &args.strip_prefix {
+        if !args.ignore_missing_prefix
+            && !sources.iter().any(|src| {
+                src.path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(prefix))
+            })
+        {
+            return Err(
+                anyhow::Error::new(MooveError::MissingPrefix(prefix.to_owned()))
+                    .context(Failure::Validation),
+            );
+        }
+    }
+    if let Some(format) = &args.date_prefix {
+        use chrono::format::{Item, StrftimeItems};
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            return Err(
+                anyhow::anyhow!("Invalid date format. {}", format.yellow().underline())
+                    .context(Failure::Validation),
+            );
+        }
+    }
+    planned_operations(sources, args, |src| {
+        // NOTE Can be unwrapped safely, sources cannot be root nor `..`.
+        let name = src.path.file_name().unwrap();
+        let Some(name) = name.to_str() else {
+            return src.path.to_owned();
+        };
+        let name = apply_prefix_ops(
+            name,
+            args.strip_prefix.as_deref(),
+            args.add_prefix.as_deref(),
+        );
+        let name = Path::new(&name);
+        let stem = apply_suffix_ops(
+            &name.file_stem().unwrap_or_default().to_string_lossy(),
+            args.strip_suffix.as_deref(),
+            args.add_suffix.as_deref(),
+        );
+        let name = match name.extension() {
+            Some(ext) => format!("{}.{}", stem, ext.to_string_lossy()),
+            None => stem,
+        };
+        let name = if args.to_lowercase || args.to_uppercase {
+            convert_case(&name, args.to_uppercase)
+        } else {
+            name
+        };
+        src.path.with_file_name(match &args.date_prefix {
+            Some(format) => match src.meta.mtime {
+                Some(mtime) => date_prefixed_name(&name, mtime, format),
+                None => match args.date_fallback {
+                    DateFallback::Now => date_prefixed_name(&name, SystemTime::now(), format),
+                    DateFallback::Skip => name,
+                },
+            },
+            None => name,
+        })
+    })
+}
+
+/// File name with the local date of the time prepended, e.g. `20240102-name.ext`.
+pub fn date_prefixed_name(name: &str, time: SystemTime, format: &str) -> String {
+    format!(
+        "{}-{}",
+        chrono::DateTime::<chrono::Local>::from(time).format(format),
+        name
+    )
+}
+
+/// File name with the prefix removed if it has, and then the other prefix prepended.
+pub fn apply_prefix_ops(name: &str, strip: Option<&str>, add: Option<&str>) -> String {
+    let name = strip
+        .and_then(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    format!("{}{}", add.unwrap_or_default(), name)
+}
+
+/// File name with characters illegal on Windows and control characters replaced.
+pub fn sanitize_filename(name: &str, replacement: char) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_control() || r#"<>:"/\|?*"#.contains(c) {
+                replacement
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Destination with its file name sanitized and truncated by options, or `None` if unchanged.
+fn adjust_destination(path: &Path, args: &CommandLine) -> Result<Option<PathBuf>> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(None);
+    };
+    let mut adjusted = name.to_owned();
+    if args.sanitize {
+        adjusted = sanitize_filename(&adjusted, args.sanitize_char);
+    }
+    if let Some(max) = args.truncate {
+        adjusted = truncate_filename(&adjusted, max)?;
+    }
+    Ok((adjusted != name).then(|| path.with_file_name(adjusted)))
+}
+
+/// File name truncated to be at most the bytes, keeping its extension and UTF-8 characters.
+pub fn truncate_filename(name: &str, max_bytes: usize) -> Result<String> {
+    if name.len() <= max_bytes {
+        return Ok(name.to_owned());
+    }
+    let path = Path::new(name);
+    let ext = match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy()),
+        None => String::new(),
+    };
+    if ext.len() >= max_bytes {
+        return Err(MooveError::TooLongExtension {
+            name: name.to_owned(),
+            max: max_bytes,
+        }
+        .into());
+    }
+    let stem = &name[..name.len() - ext.len()];
+    let mut end = max_bytes - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    Ok(format!("{}{}", &stem[..end], ext))
+}
+
+/// File name converted to uppercase or lowercase.
+pub fn convert_case(name: &str, upper: bool) -> String {
+    if upper {
+        name.to_uppercase()
+    } else {
+        name.to_lowercase()
+    }
+}
+
+/// File stem with the suffix removed if it has, and then the other suffix appended.
+pub fn apply_suffix_ops(stem: &str, strip: Option<&str>, add: Option<&str>) -> String {
+    let stem = strip
+        .and_then(|suffix| stem.strip_suffix(suffix))
+        .unwrap_or(stem);
+    format!("{}{}", stem, add.unwrap_or_default())
+}
+
+/// Operations to the destinations computed without editing, resolving conflicts by `--conflict`.
+fn planned_operations(
+    sources: &[Source],
+    args: &CommandLine,
+    destination: impl Fn(&Source) -> PathBuf,
+) -> Result<Vec<Operation>> {
+    let mut candidates: Vec<Operation> = Vec::new();
+    for src in sources.iter() {
+        let mut dst_path = destination(src);
+        if let Some(adjusted) = adjust_destination(&dst_path, args).context(Failure::Validation)? {
+            dst_path = adjusted;
+        }
+        // NOTE Can be unwrapped safely, destinations are joined with file names.
+        let name = dst_path.file_name().unwrap().to_owned();
+        if dst_path == src.path || dst_path == src.abs {
+            continue;
+        }
+        let taken = |path: &Path| {
+            candidates.iter().any(|o| o.dst.path.is_identical(path))
+                || (path.symlink_metadata().is_ok()
+                    && !sources.iter().any(|s| s.path.is_identical(path)))
+        };
+        if taken(&dst_path) {
+            match args.conflict {
+                Conflict::Abort => {
+                    return Err(
+                        anyhow::Error::new(MooveError::DuplicateDestination(dst_path))
+                            .context(Failure::Validation),
+                    )
+                }
+                Conflict::Skip => continue,
+                Conflict::Suffix => {
+                    dst_path = (1..)
+                        .map(|n| dst_path.with_file_name(suffixed_name(Path::new(&name), n)))
+                        .find(|path| !taken(path))
+                        .unwrap();
+                }
+            }
+        }
+        let kind = if args.copy {
+            OperationKind::Copy
+        } else {
+            move_kind(src, &dst_path)
+        };
+        candidates.push(Operation {
+            kind,
+            src: src.to_owned(),
+            dst: Destination::new(escape_path(&dst_path), dst_path)?,
+        });
+    }
+    reorder_operations_with(&mut candidates, args.cycle_stage).context(Failure::Validation)?;
+    let mut operations = Vec::new();
+    for new_operation in candidates {
+        is_unmoved_source(sources, &operations, &new_operation).context(Failure::Validation)?;
+        is_operational_with(&operations, &new_operation, args.name_rules())
+            .context(Failure::Validation)?;
+        operations.push(new_operation);
+    }
+    Ok(operations)
+}
+
+/// File name with a number appended to its stem, e.g. `name (1).ext`.
+pub fn suffixed_name(name: &Path, n: usize) -> String {
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    match name.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, n, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    }
+}
+
+/// Edit the text with the editor, or the default one of the `edit` crate if it is `None`.
+pub fn open_editor(text: &str, editor: Option<&str>, editor_args: &[String]) -> Result<String> {
+    let edited = match editor {
+        Some(editor) => run_editor(text, Path::new(editor), editor_args),
+        None if editor_args.is_empty() => edit::edit(text),
+        None => edit::get_editor().and_then(|editor| run_editor(text, &editor, editor_args)),
+    };
+    edited.map_err(|err| {
+        // Editors exit with error to abort editing, e.g. `:cq` of Vim.
+        if err.kind() == std::io::ErrorKind::Other {
+            anyhow::Error::new(err).context(Failure::Aborted)
+        } else {
+            err.into()
+        }
+    })
+}
+
+/// Edit the text in a temporary file with the editor spawned directly.
+fn run_editor(text: &str, editor: &Path, editor_args: &[String]) -> std::io::Result<String> {
+    let mut file = edit::Builder::new().tempfile()?;
+    file.write_all(text.as_bytes())?;
+    let path = file.into_temp_path();
+    let status = std::process::Command::new(editor)
+        .args(editor_args)
+        .arg(&path)
+        .status()?;
+    if !status.success() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "editor '{}' exited with error: {}",
+                editor.to_string_lossy(),
+                status
+            ),
+        ));
+    }
+    let edited = std::fs::read_to_string(&path)?;
+    path.close()?;
+    Ok(edited)
+}
+
+/// Split arguments by whitespace like a shell, respecting quotes and backslash escapes.
+pub fn shell_split(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                arg.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => arg.push('\\'),
+                        },
+                        c => arg.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let arg = arg.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    arg.push(c);
+                }
+            }
+            c if c.is_whitespace() => args.extend(arg.take()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// Command line with options of `MOOVE_OPTIONS` merged, along with the ones dropped.
+///
+/// Options on the command line win over the same ones, while flags and repeatable options
+/// are combined. Options conflicting with the command line and paths are dropped.
+/// `cli` starts with the program name, and it fails if `env` is invalid by itself.
+pub fn merge_env_options(
+    env: &[String],
+    cli: &[std::ffi::OsString],
+) -> Result<(CommandLine, Vec<String>), clap::Error> {
+    use clap::parser::ValueSource;
+    use clap::{CommandFactory, FromArgMatches};
+    use std::ffi::OsString;
+    let command = CommandLine::command();
+    let program = cli.first().cloned().unwrap_or_default();
+    let env_matches = command.clone().try_get_matches_from(
+        std::iter::once(program.to_owned()).chain(env.iter().map(Into::into)),
+    )?;
+    let cli_matches = command.clone().try_get_matches_from(cli)?;
+    let given = |matches: &clap::ArgMatches, arg: &clap::Arg| {
+        matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    };
+    let conflicts = |a: &clap::Arg, b: &clap::Arg| {
+        command
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|c| c.get_id() == b.get_id())
+    };
+    // Options of the environment variable with their arguments, restored from matches.
+    let mut options: Vec<(String, Vec<OsString>)> = Vec::new();
+    let mut dropped = Vec::new();
+    for arg in command
+        .get_arguments()
+        .filter(|arg| given(&env_matches, arg))
+    {
+        let id = arg.get_id().as_str();
+        let name = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{}", long),
+            (None, Some(short)) => format!("-{}", short),
+            (None, None) => id.to_owned(),
+        };
+        if arg.is_positional()
+            || command
+                .get_arguments()
+                .filter(|other| given(&cli_matches, other))
+                .any(|other| conflicts(arg, other) || conflicts(other, arg))
+        {
+            dropped.push(name);
+            continue;
+        }
+        let tokens = match arg.get_action() {
+            clap::ArgAction::SetTrue | clap::ArgAction::SetFalse => vec![OsString::from(&name)],
+            clap::ArgAction::Count => vec![OsString::from(&name); env_matches.get_count(id).into()],
+            _ => env_matches
+                .get_raw_occurrences(id)
+                .into_iter()
+                .flatten()
+                .flat_map(|values| {
+                    let values: Vec<&std::ffi::OsStr> = values.collect();
+                    match values[..] {
+                        [value] => {
+                            let mut token = OsString::from(format!("{}=", name));
+                            token.push(value);
+                            vec![token]
+                        }
+                        _ => std::iter::once(OsString::from(&name))
+                            .chain(values.into_iter().map(Into::into))
+                            .collect(),
+                    }
+                })
+                .collect(),
+        };
+        options.push((name, tokens));
+    }
+    // Options on the command line follow the ones of the environment variable to win over them.
+    let parse = |options: &[(String, Vec<OsString>)]| {
+        let tokens = options
+            .iter()
+            .flat_map(|(_, tokens)| tokens.iter().cloned());
+        CommandLine::try_parse_from(
+            std::iter::once(program.to_owned())
+                .chain(tokens)
+                .chain(cli.iter().skip(1).cloned()),
+        )
+    };
+    let mut args = parse(&options)?;
+    // Contradictions not known to clap are resolved by dropping options involved one by one.
+    let cli_valid = CommandLine::from_arg_matches(&cli_matches)?
+        .validate()
+        .is_ok();
+    while let (true, Err(err)) = (cli_valid, args.validate()) {
+        let involved = (0..options.len()).find(|&i| {
+            let mut rest = options.to_owned();
+            rest.remove(i);
+            parse(&rest).is_ok_and(|rest| {
+                rest.validate().err().map(|e| e.to_string()) != Some(err.to_string())
+            })
+        });
+        let Some(i) = involved else {
+            break;
+        };
+        dropped.push(options.remove(i).0);
+        args = parse(&options)?;
+    }
+    Ok((args, dropped))
+}
+
+/// `Rename` if the destination is in the same directory as the source, otherwise `Move`.
+pub fn move_kind(src: &Source, dst_path: &Path) -> OperationKind {
+    if src.path.parent() == dst_path.parent() {
+        OperationKind::Rename
+    } else {
+        OperationKind::Move
+    }
+}
+
+/// Text to be edited, a line for each source.
+///
+/// A line starting with `#` is a comment, hence `#` at the beginning of a path is escaped as `\#`.
+pub fn text_from(sources: &[Source], args: &CommandLine) -> String {
+    let mut number = 0;
+    let mut counters = std::collections::HashMap::new();
+    let lines = sources
+        .iter()
+        .map(|src| {
+            let is_dir = src.path.is_dir() && !src.path.is_symlink();
+            let mut line = src.text.to_owned();
+            if is_dir && !line.ends_with(std::path::MAIN_SEPARATOR) {
+                line.push(std::path::MAIN_SEPARATOR);
+            }
+            let mut dst_line = line.to_owned();
+            if let Some(template) = &args.number {
+                let name = escape_path(Path::new(src.path.file_name().unwrap_or_default()));
+                if !(is_dir && args.number_files_only) {
+                    number += 1;
+                    dst_line = format!(
+                        "{}{}",
+                        src.text.strip_suffix(&name).unwrap_or_default(),
+                        numbered_name(template, number, &name)
+                    );
+                    if is_dir {
+                        dst_line.push(std::path::MAIN_SEPARATOR);
+                    }
+                }
+            } else if args.sequence {
+                let key = if args.sequence_per_dir {
+                    src.abs.parent().map(|p| p.to_path_buf())
+                } else {
+                    None
+                };
+                let count = counters.entry(key).or_insert(0);
+                let n = args.counter_start + *count * args.counter_step;
+                *count += 1;
+                dst_line = apply_sequence(src, n, args.counter_pad);
+                if is_dir {
+                    dst_line.push(std::path::MAIN_SEPARATOR);
+                }
+            }
+            if args.effective_format() == Format::TwoColumn {
+                line = format!("{}\t{}", escape_column(&line), escape_column(&dst_line));
+            } else {
+                line = dst_line;
+            }
+            if line.starts_with('#') {
+                line.insert(0, '\\');
+            }
+            line
+        })
+        .collect::<Vec<_>>();
+    let lines = if args.annotate {
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        lines
+            .iter()
+            .zip(sources.iter())
+            .map(|(line, src)| {
+                format!(
+                    "{}{}{}{}",
+                    line,
+                    " ".repeat(width - line.chars().count()),
+                    ANNOTATION_MARKER,
+                    annotation_from(&src.meta)
+                )
+            })
+            .collect()
+    } else {
+        lines
+    };
+    let lines = if args.group_by_extension {
+        let mut grouped = Vec::new();
+        for (i, (line, src)) in lines.into_iter().zip(sources.iter()).enumerate() {
+            let ext = extension_of(src);
+            if i == 0 || extension_of(&sources[i - 1]) != ext {
+                grouped.push(match ext {
+                    Some(ext) => format!("# .{}", ext),
+                    None => "# (no extension)".to_string(),
+                });
+            }
+            grouped.push(line);
+        }
+        grouped
+    } else {
+        lines
+    };
+    match args.effective_format() {
+        Format::Lines => lines.join("\n"),
+        Format::TwoColumn => format!(
+            "# SOURCE<TAB>DESTINATION\n\
+             # Write tab as %09 and % as %25 in paths.\n\
+             {}",
+            lines.join("\n")
+        ),
+    }
+}
+
+/// Path of the source with the counter appended to its file stem, e.g. `photo-001.jpg`.
+pub fn apply_sequence(src: &Source, n: usize, pad: usize) -> String {
+    let text = src.text.trim_end_matches(SEPARATORS);
+    let name = escape_path(Path::new(src.path.file_name().unwrap_or_default()));
+    let parent = text.strip_suffix(&name).unwrap_or_default();
+    let path = Path::new(&name);
+    match path.extension().filter(|_| !src.meta.is_dir) {
+        Some(ext) => format!(
+            "{}{}-{:0pad$}.{}",
+            parent,
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            n,
+            ext.to_string_lossy()
+        ),
+        None => format!("{}{}-{:0pad$}", parent, name, n),
+    }
+}
+
+/// Expand `{n}`, `{n:03}`, `{name}`, `{stem}` and `{ext}` in the template.
+///
+/// Unknown placeholders are left as they are.
+pub fn numbered_name(template: &str, number: usize, name: &str) -> String {
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    // Compiled once, this is called for each source.
+    static PLACEHOLDER: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    PLACEHOLDER
+        .get_or_init(|| Regex::new(r"\{(n(?::(0?)(\d+))?|name|stem|ext)\}").unwrap())
+        .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+            "name" => name.to_owned(),
+            "stem" => stem.to_string(),
+            "ext" => ext.to_string(),
+            _ => {
+                let width = caps.get(3).map_or(0, |w| w.as_str().parse().unwrap_or(0));
+                if caps.get(2).is_some_and(|zero| !zero.as_str().is_empty()) {
+                    format!("{:0width$}", number, width = width)
+                } else {
+                    format!("{:width$}", number, width = width)
+                }
+            }
+        })
+        .to_string()
+}
+
+/// Size and modification date of a file.
+pub fn annotation_from(meta: &SourceMeta) -> String {
+    let size = if meta.is_dir {
+        "-".to_owned()
+    } else {
+        human_size(meta.size)
+    };
+    let date = meta
+        .mtime
+        .map(|mtime| {
+            chrono::DateTime::<chrono::Local>::from(mtime)
+                .format("%Y-%m-%d")
+                .to_string()
+        })
+        .unwrap_or_else(|| "-".to_owned());
+    format!("{:>9} {}", size, date)
+}
+
+/// Throughput of bytes transferred since the start.
+#[derive(Debug, Clone)]
+pub struct ThroughputTracker {
+    pub start: std::time::Instant,
+    pub bytes: u64,
+    reported: Option<std::time::Instant>,
+}
+
+impl Default for ThroughputTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThroughputTracker {
+    /// Interval to report throughput.
+    const INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+            bytes: 0,
+            reported: None,
+        }
+    }
+
+    /// Update transferred bytes and return whether the interval to report has passed.
+    pub fn update(&mut self, bytes: u64) -> bool {
+        self.bytes = bytes;
+        let now = std::time::Instant::now();
+        let due = now.duration_since(self.reported.unwrap_or(self.start)) >= Self::INTERVAL;
+        if due {
+            self.reported = Some(now);
+        }
+        due
+    }
+
+    /// Throughput and estimated time to transfer all bytes, e.g. `3.2 MiB/s, ETA 00:12`.
+    pub fn report(&self, total: u64) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if rate > 0.0 {
+            let secs = (total.saturating_sub(self.bytes) as f64 / rate).ceil() as u64;
+            format!("{:02}:{:02}", secs / 60, secs % 60)
+        } else {
+            "--:--".to_owned()
+        };
+        format!("{}/s, ETA {}", human_size(rate as u64), eta)
+    }
+}
+
+/// Size in binary units, e.g. `4.2 MiB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Pair each line of two-column text with the source in its first column.
+pub fn pairs_from<'a>(sources: &'a [Source], lines: &[&str]) -> Result<Vec<(&'a Source, String)>> {
+    let indices = sources
+        .iter()
+        .enumerate()
+        .map(|(i, src)| (src.text.trim_end_matches(SEPARATORS), i))
+        .collect::<std::collections::HashMap<_, _>>();
+    let mut paired = vec![false; sources.len()];
+    let mut pairs = Vec::new();
+    for line in lines.iter() {
+        let (removing, columns) = match line.strip_prefix("//") {
+            Some(columns) => (true, columns),
+            None => (false, *line),
+        };
+        let (src_text, dst_text) = columns
+            .split_once('\t')
+            .or_else(|| columns.split_once(" → "))
+            .with_context(|| format!("Missing destination. {}", line.yellow().underline()))?;
+        let src_text = unescape_column(src_text.trim().trim_end_matches(SEPARATORS));
+        let dst_text = unescape_column(dst_text.trim().trim_end_matches(SEPARATORS));
+        let i = *indices
+            .get(src_text.as_str())
+            .with_context(|| format!("Unknown source. {}", line.yellow().underline()))?;
+        if paired[i] {
+            anyhow::bail!("Duplicated source. {}", line.yellow().underline());
+        }
+        paired[i] = true;
+        pairs.push((
+            &sources[i],
+            if removing {
+                format!("//{}", dst_text)
+            } else {
+                dst_text
+            },
+        ));
+    }
+    Ok(pairs)
+}
+
+/// Whether the process can create entries in the directory.
+#[cfg(target_family = "unix")]
+fn is_writable_dir(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(dir) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return true;
+    };
+    // SAFETY: The path is a valid null-terminated string.
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Read-only attribute of directories is not enforced on Windows.
+#[cfg(not(target_family = "unix"))]
+fn is_writable_dir(_dir: &Path) -> bool {
+    true
+}
+
+/// Text of the path, escaped by `escape_bytes` only if it is invalid as UTF-8.
+pub fn escape_path(path: &Path) -> String {
+    match path.to_str() {
+        Some(text) => text.to_owned(),
+        None => escape_bytes(path),
+    }
+}
+
+/// Text of the path with `%` and bytes invalid as UTF-8 escaped like `%25` and `%E9`,
+/// which `unescape_path` decodes back.
+#[cfg(target_family = "unix")]
+pub fn escape_bytes(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut text = String::new();
+    let mut rest = path.as_os_str().as_bytes();
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(&valid.replace('%', "%25"));
+                return text;
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                // NOTE Can be unwrapped safely, validated above.
+                text.push_str(&std::str::from_utf8(valid).unwrap().replace('%', "%25"));
+                let len = err.error_len().unwrap_or(invalid.len());
+                for b in invalid[..len].iter() {
+                    text.push_str(&format!("%{:02X}", b));
+                }
+                rest = &invalid[len..];
+            }
+        }
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+pub fn escape_bytes(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+/// Path of the text escaped by `escape_bytes`, leaving `%` not followed by two hex digits as it is.
+#[cfg(target_family = "unix")]
+pub fn unescape_path(text: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = text
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(b) = byte {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(decoded))
+}
+
+#[cfg(not(target_family = "unix"))]
+pub fn unescape_path(text: &str) -> PathBuf {
+    PathBuf::from(text)
+}
+
+fn escape_column(s: &str) -> String {
+    s.replace('%', "%25").replace('\t', "%09")
+}
+
+fn unescape_column(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('%') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("%09") {
+            unescaped.push('\t');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("%25") {
+            unescaped.push('%');
+            rest = after;
+        } else {
+            unescaped.push('%');
+            rest = &rest[1..];
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Lines of edited text except empty lines and comments.
+pub fn lines_from(text: &str) -> Vec<&str> {
+    numbered_lines(text)
+        .into_iter()
+        .map(|(_, line)| line)
+        .collect()
+}
+
+/// `lines_from` with their line numbers starting from 1.
+fn numbered_lines(text: &str) -> Vec<(usize, &str)> {
+    text.split('\n')
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = match line.find(ANNOTATION_MARKER) {
+                Some(i) => &line[..i],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let line = match line.strip_prefix('\\') {
+                Some(escaped) if escaped.starts_with('#') => escaped,
+                _ => line,
+            };
+            Some((i + 1, line.trim_end_matches(SEPARATORS)))
+        })
+        .collect()
+}
+
+/// Text without comments of errors written by `text_with_errors`.
+pub fn strip_errors(text: &str) -> String {
+    text.split_inclusive('\n')
+        .filter(|line| !line.starts_with(ERROR_MARKER))
+        .collect()
+}
+
+/// Text without escape sequences of colors.
+pub fn strip_colors(text: &str) -> std::borrow::Cow<'_, str> {
+    // Compiled once, this is called for each error and each failed record.
+    static COLORS: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    COLORS
+        .get_or_init(|| Regex::new("\x1b\\[[0-9;]*m").unwrap())
+        .replace_all(text, "")
+}
+
+/// Text with comments of errors at the top, referring to their lines by indices of `lines_from`.
+pub fn text_with_errors(text: &str, errors: &[(Option<usize>, String)]) -> String {
+    let text = strip_errors(text);
+    let messages: Vec<String> = errors
+        .iter()
+        .map(|(_, message)| strip_colors(message).into_owned())
+        .collect();
+    let offset: usize = messages.iter().map(|message| message.lines().count()).sum();
+    let numbers = numbered_lines(&text);
+    let mut comments = String::new();
+    for ((line, _), message) in errors.iter().zip(messages.iter()) {
+        for (i, message_line) in message.lines().enumerate() {
+            match line.and_then(|line| numbers.get(line)) {
+                Some((number, _)) if i == 0 => comments.push_str(&format!(
+                    "{} line {}: {}\n",
+                    ERROR_MARKER,
+                    number + offset,
+                    message_line
+                )),
+                _ if i == 0 => comments.push_str(&format!("{}: {}\n", ERROR_MARKER, message_line)),
+                _ => comments.push_str(&format!("{}  {}\n", ERROR_MARKER, message_line)),
+            }
+        }
+    }
+    comments + &text
+}
+
+/// Ask whether to edit again, or abort without executing any operation.
+pub fn prompt_redo(args: &CommandLine) -> Result<bool> {
+    prompt_redo_from(args, &mut std::io::stdin().lock())
+}
+
+/// `prompt_redo` reading answers from the reader, which aborts at the end of input.
+pub fn prompt_redo_from(args: &CommandLine, reader: &mut impl std::io::BufRead) -> Result<bool> {
+    let prompt = format!(
+        "{}{} again or {}{} without any operation? > ",
+        "E".bold().underline(),
+        "dit".bold(),
+        "A".bold().underline(),
+        "bort".bold()
+    );
+    let answer = prompt_from(args, reader, &prompt, |ans| match ans {
+        "" | "e" | "edit" => Some(Resolution::Edit),
+        "a" | "abort" => Some(Resolution::Abort),
+        _ => None,
+    })?;
+    Ok(answer == Resolution::Edit)
+}
+
+/// Ask how to resolve a conflicting operation.
+pub fn prompt_resolution(args: &CommandLine) -> Result<Resolution> {
+    prompt_resolution_from(args, &mut std::io::stdin().lock())
+}
+
+/// `prompt_resolution` reading answers from the reader, which aborts at the end of input.
+pub fn prompt_resolution_from(
+    args: &CommandLine,
+    reader: &mut impl std::io::BufRead,
+) -> Result<Resolution> {
+    let prompt = format!(
+        "{}{} again, {}{} it, {}{} with valid ones or {}{} without any operation? > ",
+        "E".bold().underline(),
+        "dit".bold(),
+        "S".bold().underline(),
+        "kip".bold(),
+        "C".bold().underline(),
+        "ontinue".bold(),
+        "A".bold().underline(),
+        "bort".bold()
+    );
+    prompt_from(args, reader, &prompt, |ans| match ans {
+        "" | "e" | "edit" => Some(Resolution::Edit),
+        "s" | "skip" => Some(Resolution::Skip),
+        "c" | "continue" => Some(Resolution::Continue),
+        "a" | "abort" => Some(Resolution::Abort),
+        _ => None,
+    })
+}
+
+/// Ask how to resolve an operation whose destination exists.
+pub fn prompt_collision(args: &CommandLine, can_overwrite: bool) -> Result<Resolution> {
+    prompt_collision_from(args, can_overwrite, &mut std::io::stdin().lock())
+}
+
+/// `prompt_collision` reading answers from the reader, which aborts at the end of input.
+///
+/// Directories cannot be overwritten, not to remove their contents.
+pub fn prompt_collision_from(
+    args: &CommandLine,
+    can_overwrite: bool,
+    reader: &mut impl std::io::BufRead,
+) -> Result<Resolution> {
+    let prompt = format!(
+        "{}{}{}{}, {}{} it, {}{} again or {}{} without any operation? > ",
+        if can_overwrite {
+            format!("{}{}, ", "O".bold().underline(), "verwrite".bold())
+        } else {
+            String::new()
+        },
+        "R".bold().underline(),
+        "ename".bold(),
+        " with a number".bold(),
+        "S".bold().underline(),
+        "kip".bold(),
+        "E".bold().underline(),
+        "dit".bold(),
+        "A".bold().underline(),
+        "bort".bold()
+    );
+    prompt_from(args, reader, &prompt, |ans| match ans {
+        "o" | "overwrite" if can_overwrite => Some(Resolution::Overwrite),
+        "r" | "rename" => Some(Resolution::Rename),
+        "s" | "skip" => Some(Resolution::Skip),
+        "" | "e" | "edit" => Some(Resolution::Edit),
+        "a" | "abort" => Some(Resolution::Abort),
+        _ => None,
+    })
+}
+
+/// Repeat the prompt until the answer is parsed, or abort at the end of input.
+fn prompt_from(
+    args: &CommandLine,
+    reader: &mut impl std::io::BufRead,
+    prompt: &str,
+    parse: impl Fn(&str) -> Option<Resolution>,
+) -> Result<Resolution> {
+    loop {
+        if args.stdout_occupied() {
+            eprint!("{}", prompt);
+            std::io::stderr().flush()?;
+        } else {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+        }
+        let mut ans = String::new();
+        if reader.read_line(&mut ans)? == 0 {
+            return Ok(Resolution::Abort);
+        }
+        if let Some(resolution) = parse(&ans.trim().to_ascii_lowercase()) {
+            return Ok(resolution);
+        }
+    }
+}
+
+pub fn is_operational(
+    operations: &[Operation],
+    new_operation: &Operation,
+) -> Result<(), MooveError> {
+    is_operational_with(operations, new_operation, NameRules::native())
+}
+
+/// `is_operational` comparing and validating destinations by the rules.
+pub fn is_operational_with(
+    operations: &[Operation],
+    new_operation: &Operation,
+    rules: NameRules,
+) -> Result<(), MooveError> {
+    let src = &new_operation.src;
+    let dst = &new_operation.dst;
+    if dst.text.ends_with(std::path::MAIN_SEPARATOR) && (src.meta.is_file || src.meta.is_symlink) {
+        return Err(MooveError::MissingFileName {
+            dst: dst.text.to_owned(),
+            src: src.text.to_owned(),
+        });
+    }
+    if src.meta.is_dir && !src.meta.is_symlink && dst.abs.starts_with(&src.abs) {
+        return Err(MooveError::DestinationInsideSource {
+            src: src.path.to_owned(),
+            dst: dst.path.to_owned(),
+        });
+    }
+    // Names of existing ancestors are not created by the operation.
+    for path in dst
+        .path
+        .ancestors()
+        .take_while(|a| *a == dst.path || a.symlink_metadata().is_err())
+    {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if let Some(max) = rules.max_name_length {
+            let length = name_length(name);
+            if length > max {
+                return Err(MooveError::TooLongName {
+                    dst: dst.path.to_owned(),
+                    name: name.to_string_lossy().to_string(),
+                    length,
+                    max,
+                });
+            }
+        }
+        let name = name.to_string_lossy();
+        if let Some(reason) = windows_name_error(&name).filter(|_| rules.windows) {
+            return Err(MooveError::InvalidWindowsName {
+                dst: dst.path.to_owned(),
+                name: name.to_string(),
+                reason,
+            });
+        }
+    }
+    if let Some(max) = rules.max_path_length {
+        let length = name_length(dst.abs.as_os_str());
+        if length > max {
+            return Err(MooveError::TooLongPath {
+                dst: dst.path.to_owned(),
+                length,
+                max,
+            });
+        }
+    }
+    if let Some(o) = operations
+        .iter()
+        .find(|o| is_same_path(&o.dst.abs, &dst.abs, rules))
+    {
+        if o.dst.abs.is_identical(&dst.abs) {
+            return Err(MooveError::DuplicateDestination(dst.path.to_owned()));
+        }
+        return Err(MooveError::EquivalentDestination {
+            dst: dst.path.to_owned(),
+            other: o.dst.path.to_owned(),
+        });
+    }
+    if operations.iter().any(|o| {
+        o.dst
+            .abs
+            .ancestors()
+            .any(|a| is_same_path(a, &dst.abs, rules))
+    }) {
+        return Err(MooveError::NestedDestination(dst.path.to_owned()));
+    }
+    // Broken symbolic links do not exist for `exists`, but are entries to collide with.
+    if let Some((path, meta)) = existing_entry(dst, rules) {
+        // Only the case of the source itself is changed, for example.
+        let refolds = rules.folds() && is_same_path(&path, &src.abs, rules);
+        if !refolds
+            && !operations.iter().any(|o| {
+                !o.src.abs.is_identical(&src.abs)
+                    && (o.vacates_destination(dst) || o.vacates(&path))
+            })
+        {
+            let path = dst.path.to_owned();
+            return Err(if meta.is_symlink() {
+                MooveError::DestinationLinkExists(path)
+            } else {
+                MooveError::DestinationExists(path)
+            });
+        }
+    }
+    // The destination itself is checked above, which may be moved away.
+    if dst.path.ancestors().skip(1).any(|a| {
+        if !a.exists() {
+            false
+        } else if a.is_file() {
+            true
+        } else if a.is_symlink() {
+            if let Ok(p) = a.read_link() {
+                p.is_file()
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }) {
+        return Err(MooveError::FileAncestor(dst.path.to_owned()));
+    }
+    // Missing ancestors will be created in the nearest existing one.
+    if let Some(dir) = dst
+        .abs
+        .parent()
+        .and_then(|p| p.ancestors().find(|a| a.is_dir()))
+    {
+        if !is_writable_dir(dir) {
+            return Err(MooveError::PermissionDenied(dir.to_owned()));
+        }
+    }
+    for o in operations.iter() {
+        let vacated = if dst.ancestors().any(|a| o.vacates(a)) {
+            Some((&dst.path, &o.src.path))
+        } else if o.dst.ancestors().any(|a| new_operation.vacates(a)) {
+            Some((&o.dst.path, &src.path))
+        } else {
+            None
+        };
+        if let Some((dst, src)) = vacated {
+            return Err(MooveError::VacatedAncestor {
+                dst: dst.to_owned(),
+                src: src.to_owned(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Length of the name or path as limited by the filesystem,
+/// in UTF-16 code units on Windows and in bytes on the others.
+#[cfg(target_family = "windows")]
+pub fn name_length(name: &std::ffi::OsStr) -> usize {
+    use std::os::windows::ffi::OsStrExt;
+    name.encode_wide().count()
+}
+
+#[cfg(target_family = "unix")]
+pub fn name_length(name: &std::ffi::OsStr) -> usize {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().len()
+}
+
+/// Unit of `name_length` in messages.
+pub const NAME_LENGTH_UNIT: &str = if cfg!(target_family = "windows") {
+    "UTF-16 units"
+} else {
+    "bytes"
+};
+
+/// Why the file name is invalid on Windows, if it is.
+pub fn windows_name_error(name: &str) -> Option<String> {
+    for (i, c) in name.chars().enumerate() {
+        if matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*' | '\\') {
+            return Some(format!("character {:?} at {}", c, i + 1));
+        }
+        if c.is_control() {
+            return Some(format!("control character U+{:04X} at {}", c as u32, i + 1));
+        }
+    }
+    // Device names are reserved even with extensions, e.g. `con.txt`.
+    let base = name.split('.').next().unwrap_or_default().trim_end();
+    let reserved = match base.to_ascii_uppercase().as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        upper => {
+            (upper.starts_with("COM") || upper.starts_with("LPT"))
+                && matches!(upper.as_bytes().get(3..), Some([b'1'..=b'9']))
+        }
+    };
+    if reserved {
+        return Some(format!("reserved name {}", base.to_ascii_uppercase()));
+    }
+    match name.chars().last() {
+        Some('.') if name != "." && name != ".." => Some("trailing dot".to_string()),
+        Some(' ') => Some("trailing space".to_string()),
+        _ => None,
+    }
+}
+
+/// Whether the destination is the source itself, or written differently only in Unicode normalization
+/// if it is ignored, while differences in case are renaming.
+pub fn is_unchanged(src: &Source, dst_path: &Path, rules: NameRules) -> bool {
+    let rules = NameRules {
+        fold_case: false,
+        ..rules
+    };
+    dst_path == src.path
+        || dst_path == src.abs
+        || (rules.folds()
+            && (rules.fold(dst_path) == rules.fold(&src.path)
+                || rules.fold(dst_path) == rules.fold(&src.abs)))
+}
+
+/// Whether the paths are identical, or differ only by the rules.
+pub fn is_same_path(a: &Path, b: &Path, rules: NameRules) -> bool {
+    a.is_identical(b) || (rules.folds() && rules.fold(a) == rules.fold(b))
+}
+
+/// Existing entry at the destination with its metadata, of the name differing only by the rules.
+fn existing_entry(dst: &Destination, rules: NameRules) -> Option<(PathBuf, Metadata)> {
+    if let Ok(meta) = dst.path.symlink_metadata() {
+        return Some((dst.abs.to_owned(), meta));
+    }
+    if !rules.folds() {
+        return None;
+    }
+    let (parent, name) = (dst.abs.parent()?, dst.abs.file_name()?);
+    std::fs::read_dir(parent).ok()?.flatten().find_map(|entry| {
+        if !is_same_path(Path::new(&entry.file_name()), Path::new(name), rules) {
+            return None;
+        }
+        let path = entry.path();
+        let meta = path.symlink_metadata().ok()?;
+        Some((path, meta))
+    })
+}
+
+/// Check if the destination is one of the sources which is not moved away by the operations.
+///
+/// Operations should be reordered in advance, so that ones vacating the destination precede.
+pub fn is_unmoved_source(
+    sources: &[Source],
+    operations: &[Operation],
+    new_operation: &Operation,
+) -> Result<(), MooveError> {
+    let Ok(abs) = new_operation.dst.path.absolute() else {
+        return Ok(());
+    };
+    if let Some(src) = sources
+        .iter()
+        .find(|s| !s.abs.is_identical(&new_operation.src.abs) && s.abs.is_identical(abs.as_path()))
+    {
+        if !operations.iter().any(|o| o.vacates(&src.path)) {
+            return Err(MooveError::UnmovedSource(new_operation.dst.path.to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Reorder operations so that each of them is executed after the others
+/// which move its destination away.
+///
+/// The order of independent operations is kept as it is.
+/// Circular operations like `a → b` and `b → a` cannot be ordered.
+pub fn reorder_operations(operations: &mut Vec<Operation>) -> Result<()> {
+    reorder_operations_with(operations, false)
+}
+
+/// `reorder_operations` breaking circular operations by `stage_cycles`, with `--cycle-stage`.
+///
+/// An operation of each cycle is split into two through a temporary name next to its source,
+/// e.g. `a → b` and `b → a` into `a → .a.moove-stage`, `b → a` and `.a.moove-stage → b`.
+pub fn reorder_operations_with(operations: &mut Vec<Operation>, stage_cycles: bool) -> Result<()> {
+    loop {
+        match order_operations(operations) {
+            Ok(order) => {
+                let mut operations_by_index = operations.drain(..).map(Some).collect::<Vec<_>>();
+                operations.extend(
+                    order
+                        .into_iter()
+                        .map(|i| operations_by_index[i].take().unwrap()),
+                );
+                return Ok(());
+            }
+            Err(cycle) if stage_cycles => {
+                let Some(&i) = cycle.iter().find(|&&i| {
+                    matches!(
+                        operations[i].kind,
+                        OperationKind::Move | OperationKind::Rename
+                    )
+                }) else {
+                    return Err(circular_error(operations, &cycle, false));
+                };
+                let (first, second) = stage_operation(&operations[i], operations)?;
+                operations[i] = first;
+                operations.push(second);
+            }
+            Err(cycle) => return Err(circular_error(operations, &cycle, true)),
+        }
+    }
+}
+
+/// Order of operations, or indices of operations making a cycle if they cannot be ordered.
+fn order_operations(operations: &[Operation]) -> Result<Vec<usize>, Vec<usize>> {
+    let count = operations.len();
+    let mut blockers = vec![0; count];
+    let mut blocked_by = vec![Vec::new(); count];
+    let mut dependents = vec![Vec::new(); count];
+    for (i, o) in operations.iter().enumerate() {
+        // Nothing to wait for at a destination not existing yet, like a staged name.
+        if o.dst.path.symlink_metadata().is_err() {
+            continue;
+        }
+        for (j, other) in operations.iter().enumerate() {
+            if i != j && other.vacates_destination(&o.dst) {
+                blockers[i] += 1;
+                blocked_by[i].push(j);
+                dependents[j].push(i);
+            }
+        }
+    }
+    let mut ready = (0..count)
+        .filter(|&i| blockers[i] == 0)
+        .collect::<std::collections::BTreeSet<_>>();
+    let mut order = Vec::with_capacity(count);
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for &dependent in dependents[i].iter() {
+            blockers[dependent] -= 1;
+            if blockers[dependent] == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+    if order.len() == count {
+        return Ok(order);
+    }
+    // Each of the rest is blocked by another of the rest, hence following them reaches a cycle.
+    let next = |i: usize| {
+        // NOTE Can be unwrapped safely, explained above.
+        *blocked_by[i].iter().find(|&&j| blockers[j] > 0).unwrap()
+    };
+    // NOTE Can be unwrapped safely, some are left.
+    let mut start = (0..count).find(|&i| blockers[i] > 0).unwrap();
+    for _ in 0..count {
+        start = next(start);
+    }
+    let mut cycle = vec![start];
+    let mut i = next(start);
+    while i != start {
+        cycle.push(i);
+        i = next(i);
+    }
+    cycle.sort_unstable();
+    Err(cycle)
+}
+
+fn circular_error(operations: &[Operation], cycle: &[usize], stageable: bool) -> anyhow::Error {
+    let circular = cycle
+        .iter()
+        .map(|&i| {
+            format!(
+                "{} → {}",
+                operations[i].src.text.yellow().underline(),
+                operations[i].dst.text.yellow().underline()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if stageable {
+        anyhow::anyhow!(
+            "Circular operations cannot be ordered.\n\
+             {}\n\
+             Specify --cycle-stage to move one of them to a temporary name first.",
+            circular
+        )
+    } else {
+        anyhow::anyhow!("Circular operations cannot be ordered.\n{}", circular)
+    }
+}
+
+/// Operations moving the source to a temporary name next to it, and from there to the destination.
+fn stage_operation(o: &Operation, operations: &[Operation]) -> Result<(Operation, Operation)> {
+    // NOTE Can be unwrapped safely, sources cannot be root nor `..`.
+    let name = o.src.path.file_name().unwrap().to_string_lossy();
+    let taken = |path: &Path| {
+        path.symlink_metadata().is_ok()
+            || operations
+                .iter()
+                .any(|other| other.src.path.is_identical(path) || other.dst.path.is_identical(path))
+    };
+    let stage = (0..)
+        .map(|n| match n {
+            0 => o.src.path.with_file_name(format!(".{}.moove-stage", name)),
+            n => o
+                .src
+                .path
+                .with_file_name(format!(".{}.moove-stage-{}", name, n)),
+        })
+        .find(|path| !taken(path))
+        // NOTE Can be unwrapped safely, names are unlimited.
+        .unwrap();
+    let staged = Destination::new(escape_path(&stage), stage.to_owned())?;
+    let first = Operation {
+        kind: OperationKind::Rename,
+        src: o.src.to_owned(),
+        dst: staged.to_owned(),
+    };
+    let second = Operation {
+        kind: o.kind.to_owned(),
+        src: Source {
+            text: staged.text,
+            path: staged.path,
+            abs: staged.abs,
+            ..o.src.to_owned()
+        },
+        dst: o.dst.to_owned(),
+    };
+    Ok((first, second))
+}
+
+pub fn execute_operation(o: &Operation, args: &CommandLine) -> Result<()> {
+    execute_operation_to(o, args, None)
+}
+
+/// `execute_operation` showing its output through the pager if any.
+pub fn execute_operation_to(
+    o: &Operation,
+    args: &CommandLine,
+    mut pager: Option<&mut PagerWriter>,
+) -> Result<()> {
+    let reporter = args.reporter();
+    // Operations are announced before executed in dry-run or with `--verbose`.
+    let mut report = |done: bool, line: String| match pager.as_deref_mut() {
+        // Failure to write means the pager has been closed by user.
+        Some(pager) => {
+            let _ = writeln!(pager, "{}", line);
+        }
+        None if done => reporter.operation_done(o, &line),
+        None if args.dry_run => reporter.info(&line),
+        None => reporter.verbose(&line),
+    };
+    let kind = match o.kind {
+        OperationKind::Move => "Move",
+        OperationKind::Rename => "Rename",
+        OperationKind::Copy => "Copy",
+        OperationKind::Remove => "Remove",
+    };
+    if matches!(o.kind, OperationKind::Remove) {
+        report(
+            false,
+            format!("{} {}", kind.dimmed(), o.src.text.dimmed().underline()),
+        );
+    } else {
+        report(
+            false,
+            format!(
+                "{} {}{}{}",
+                kind.dimmed(),
+                o.src.text.dimmed().underline(),
+                " → ".dimmed(),
+                o.dst.text.dimmed().underline()
+            ),
+        );
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+    match o.kind {
+        OperationKind::Move | OperationKind::Copy => {
+            with_retry(o, args, || Ok(execute_move_or_copy(o, args)?))?;
+        }
+        OperationKind::Rename => with_retry(o, args, || Ok(execute_rename(o, args)?))?,
+        OperationKind::Remove => {
+            with_retry(o, args, || execute_remove(o, args))?;
+            report(true, format!("Removed {}", o.src.text.green().underline()));
+            return Ok(());
+        }
+    }
+    if args.verify {
+        verify_operation(o)?;
+    }
+    report(true, done_message(o, args));
+    Ok(())
+}
+
+/// Check that the destination exists with the size of the source,
+/// and also has the same CRC32 checksum as the source if copied.
+pub fn verify_operation(o: &Operation) -> Result<()> {
+    let failed = || MooveError::VerificationFailed(o.dst.path.to_owned());
+    let meta = long_path(&o.dst.path)
+        .symlink_metadata()
+        .map_err(|_| failed())?;
+    if o.src.meta.is_file {
+        if meta.len() != o.src.meta.size {
+            return Err(failed().into());
+        }
+        if matches!(o.kind, OperationKind::Copy) && checksum(&o.src.path)? != checksum(&o.dst.path)?
+        {
+            return Err(failed().into());
+        }
+    }
+    Ok(())
+}
+
+/// CRC32 checksum of the file.
+fn checksum(path: &Path) -> Result<u32> {
+    use std::io::Read;
+    let read_error = || {
+        format!(
+            "Failed to read {}",
+            path.to_string_lossy().yellow().underline()
+        )
+    };
+    let mut file = std::fs::File::open(long_path(path)).with_context(read_error)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer).with_context(read_error)? {
+            0 => return Ok(hasher.finalize()),
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+}
+
+/// Writer to the standard input of the pager, which receives EOF and is waited on drop.
+pub struct PagerWriter {
+    child: std::process::Child,
+    stdin: Option<std::process::ChildStdin>,
+}
+
+impl PagerWriter {
+    /// Launch PAGER, or `less -R` by default.
+    pub fn spawn() -> std::io::Result<Self> {
+        let pager = std::env::var("PAGER")
+            .ok()
+            .filter(|p| !p.trim().is_empty())
+            .unwrap_or_else(|| "less -R".to_owned());
+        let argv = shell_split(&pager);
+        let mut command = std::process::Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        Self::new(command)
+    }
+
+    /// Launch the command with piped standard input.
+    pub fn new(mut command: std::process::Command) -> std::io::Result<Self> {
+        let mut child = command.stdin(std::process::Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin })
+    }
+}
+
+impl Write for PagerWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(std::io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PagerWriter {
+    fn drop(&mut self) {
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// OS error codes of transient failures retried by default.
+const RETRIED_ERRNOS: &[i32] = if cfg!(target_family = "windows") {
+    // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    &[32, 33]
+} else if cfg!(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)) {
+    // EIO, EBUSY, EAGAIN
+    &[5, 16, 35]
+} else {
+    // EIO, EAGAIN, EBUSY
+    &[5, 11, 16]
+};
+
+/// Run the operation, retrying it on transient failures with `--retry`.
+pub fn with_retry(
+    o: &Operation,
+    args: &CommandLine,
+    mut run: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        let Err(err) = run() else {
+            return Ok(());
+        };
+        let transient = err
+            .chain()
+            .filter_map(|e| e.downcast_ref::<std::io::Error>())
+            .any(|e| {
+                matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+                ) || e.raw_os_error().is_some_and(|code| {
+                    RETRIED_ERRNOS.contains(&code) || args.retry_errnos.contains(&code)
+                })
+            });
+        if !args.retry || !transient || retries >= args.retry_max {
+            return Err(err);
+        }
+        retries += 1;
+        args.reporter().verbose(&format!(
+            "{} {} ({}/{}) {}",
+            "Retrying".dimmed(),
+            o.src.text.dimmed().underline(),
+            retries,
+            args.retry_max,
+            err.to_string().dimmed()
+        ));
+        std::thread::sleep(args.retry_delay);
+    }
+}
+
+/// Message of a completed operation, highlighting the changed part of the paths.
+fn done_message(o: &Operation, args: &CommandLine) -> String {
+    if args.no_diff_colors {
+        return format!(
+            "{} → {}",
+            o.src.text.green().underline(),
+            o.dst.text.green().underline()
+        );
+    }
+    let (prefix, src_end, dst_end) = changed_range(&o.src.text, &o.dst.text);
+    format!(
+        "{}{}{} → {}{}{}",
+        o.src.text[..prefix].dimmed(),
+        o.src.text[prefix..src_end].red().underline(),
+        o.src.text[src_end..].dimmed(),
+        o.dst.text[..prefix].dimmed(),
+        o.dst.text[prefix..dst_end].green().underline(),
+        o.dst.text[dst_end..].dimmed()
+    )
+}
+
+/// Byte range of the changed part between the common prefix and suffix of the texts,
+/// as the length of the prefix and the end of the changed part in each text.
+pub fn changed_range(a: &str, b: &str) -> (usize, usize, usize) {
+    let prefix: usize = a
+        .chars()
+        .zip(b.chars())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    let suffix: usize = a[prefix..]
+        .chars()
+        .rev()
+        .zip(b[prefix..].chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    (prefix, a.len() - suffix, b.len() - suffix)
+}
+
+/// `execute_operation` in another thread, which fails if it does not finish within the timeout.
+///
+/// The thread is left running on timeout, since it cannot be interrupted safely.
+pub fn execute_operation_within(
+    o: &Operation,
+    args: &CommandLine,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let (operation, thread_args) = (o.to_owned(), args.to_owned());
+    std::thread::spawn(move || {
+        let _ = sender.send(execute_operation(&operation, &thread_args));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => Err(MooveError::Timeout {
+            src: o.src.path.to_owned(),
+            timeout,
+        }
+        .into()),
+    }
+}
+
+/// Write an operation as a record of source and destination terminated by null characters.
+pub fn write_record(writer: &mut impl Write, o: &Operation) -> std::io::Result<()> {
+    write!(writer, "{}\0{}\0", o.src.text, o.dst.text)
+}
+
+pub fn execute_move_or_copy(operation: &Operation, args: &CommandLine) -> Result<(), MooveError> {
+    let Operation { kind, src, dst, .. } = operation;
+    let moving = !matches!(kind, OperationKind::Copy);
+    // Destination is never over-written, even if it is created by others after planning,
+    // except by the source itself renamed only in case on case-insensitive filesystems.
+    if dst.path.symlink_metadata().is_ok() && !is_same_path(&src.abs, &dst.abs, NameRules::native())
+    {
+        return Err(MooveError::DestinationExists(dst.path.to_owned()));
+    }
+    let dst_parent = create_dir(dst, args)?;
+    // Operate directly if possible, since the intermediate path
+    // with the original file name in the destination directory might be occupied.
+    let (src_path, dst_path) = (&long_path(&src.path), &long_path(&dst.path));
+    // Renaming is atomic and instant on the same filesystem.
+    let renamed = if moving && !args.force_copy && !operation.crosses_filesystem() {
+        match std::fs::rename(src_path, dst_path) {
+            Ok(_) => true,
+            // Filesystems may be unknown, or differ without devices differing, e.g. on overlayfs.
+            Err(err) if is_cross_device(&err) => false,
+            Err(source) => {
+                return Err(MooveError::Rename {
+                    from: src.path.to_owned(),
+                    to: dst.path.to_owned(),
+                    source,
+                })
+            }
+        }
+    } else {
+        false
+    };
+    // Read in advance, since the source is removed after moved across devices.
+    let xattrs = if args.xattrs && !renamed {
+        read_xattrs(src_path)?
+    } else {
+        Vec::new()
+    };
+    // Links are recreated as they are instead of following them, unless copying with `--dereference`.
+    let preserves_links = !renamed
+        && (moving || !args.dereference)
+        && contains_symlink(src_path).map_err(|source| MooveError::SourceAccess {
+            path: src.path.to_owned(),
+            source,
+        })?;
+    if preserves_links {
+        copy_preserving_links(src_path, dst_path).map_err(|source| MooveError::Transfer {
+            src: src.path.to_owned(),
+            dir: dst_parent.to_owned(),
+            source,
+        })?;
+        if moving {
+            remove_path(src_path).map_err(|source| MooveError::Remove {
+                path: src.path.to_owned(),
+                source,
+            })?;
+        }
+    }
+    let copied = !moving && !preserves_links && src.meta.is_file;
+    if copied {
+        if let Err(source) = std::fs::copy(src_path, dst_path) {
+            // The partial file is the own one, since the destination did not exist.
+            let _ = std::fs::remove_file(dst_path);
+            return Err(MooveError::Transfer {
+                src: src.path.to_owned(),
+                dir: dst_parent.to_owned(),
+                source,
+            });
+        }
+    }
+    if renamed || preserves_links || copied {
+        args.reporter().verbose(&format!(
+            "{} {}{}{}",
+            if moving { "Moving" } else { "Copying" }.dimmed(),
+            src.text.dimmed().underline(),
+            " → ".dimmed(),
+            dst.text.dimmed().underline()
+        ));
+        return write_xattrs(dst_path, &xattrs, args);
+    }
+    // Directories are copied directly, since the source itself occupies its name
+    // if copied into the same directory.
+    let copies_dir = !moving && src.meta.is_dir;
+    if copies_dir || should_relocate(&src.abs, &dst.abs) {
+        args.reporter().verbose(&format!(
+            "{} {} {}",
+            if moving { "Moving" } else { "Copying" }.dimmed(),
+            src.abs.to_string_lossy().dimmed().underline(),
+            if copies_dir { &dst.path } else { &dst_parent }
+                .to_string_lossy()
+                .dimmed()
+                .underline()
+        ));
+        let mut tracker = ThroughputTracker::new();
+        let progress = |p: fs_extra::TransitProcess| {
+            if args.verbose && !args.no_progress && tracker.update(p.copied_bytes) {
+                args.reporter().verbose(&format!(
+                    "{} {}",
+                    "Progress".dimmed(),
+                    tracker.report(p.total_bytes).dimmed()
+                ));
+            }
+            if let Some(hooks) = &args.hooks {
+                hooks.on_progress(
+                    operation,
+                    &Progress {
+                        copied_bytes: p.copied_bytes,
+                        total_bytes: p.total_bytes,
+                        file_name: p.file_name,
+                        file_copied_bytes: p.file_bytes_copied,
+                        file_total_bytes: p.file_total_bytes,
+                    },
+                );
+            }
+            // Existing items are never overwritten nor skipped, since they are not planned to be
+            // and only appear if created by others meanwhile.
+            fs_extra::dir::TransitProcessResult::ContinueOrAbort
+        };
+        let options = &copy_options(args);
+        let dst_parent = &long_path(&dst_parent);
+        let failed = |err: fs_extra::error::Error| MooveError::Transfer {
+            src: src.path.to_owned(),
+            dir: dst_parent.to_path_buf(),
+            source: match err.kind {
+                fs_extra::error::ErrorKind::Io(source) => source,
+                _ => std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+            },
+        };
+        // Moving by fs_extra renames if possible, hence copying and removing with `--force-copy`.
+        if copies_dir {
+            let options = &CopyOptions {
+                content_only: true,
+                ..copy_options(args)
+            };
+            // Total of files as `copy_items_with_progress`, excluding sizes of directories.
+            let total_bytes = fs_extra::dir::get_size(src_path).map_err(failed)?;
+            let mut progress = progress;
+            fs_extra::dir::copy_with_progress(src_path, dst_path, options, |p| {
+                progress(fs_extra::TransitProcess {
+                    copied_bytes: p.copied_bytes,
+                    total_bytes,
+                    file_bytes_copied: p.file_bytes_copied,
+                    file_total_bytes: p.file_total_bytes,
+                    file_name: p.file_name,
+                    dir_name: String::new(),
+                    state: p.state,
+                })
+            })
+        } else if moving && !args.force_copy {
+            fs_extra::move_items_with_progress(&[src_path], dst_parent, options, progress)
+        } else {
+            fs_extra::copy_items_with_progress(&[src_path], dst_parent, options, progress)
+        }
+        .map_err(failed)?;
+        if moving && args.force_copy {
+            fs_extra::remove_items(&[src_path]).map_err(failed)?;
+        }
+    }
+    // Rename if its file name need to be changed.
+    // NOTE Can be unwrapped safely, `src` and `dst` cannot be root nor `..`.
+    let src_basename = src.path.file_name().unwrap();
+    let dst_basename = dst.path.file_name().unwrap();
+    if !copies_dir && src_basename != dst_basename {
+        let from = &dst_parent.join(src_basename);
+        let to = &dst_parent.join(dst_basename);
+        args.reporter().verbose(&format!(
+            "{} {}{}{}",
+            "Renaming".dimmed(),
+            from.to_string_lossy().dimmed().underline(),
+            " → ".dimmed(),
+            to.to_string_lossy().dimmed().underline()
+        ));
+        // Destination is never over-written, ensured when the operation was made.
+        std::fs::rename(long_path(from), long_path(to)).map_err(|source| MooveError::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            source,
+        })?;
+    }
+    write_xattrs(dst_path, &xattrs, args)
+}
+
+/// Whether renaming failed because of the source and the destination on different filesystems.
+fn is_cross_device(err: &std::io::Error) -> bool {
+    #[cfg(target_family = "unix")]
+    let code = libc::EXDEV;
+    #[cfg(target_family = "windows")]
+    let code = windows_sys::Win32::Foundation::ERROR_NOT_SAME_DEVICE as i32;
+    err.raw_os_error() == Some(code)
+}
+
+/// Extended attribute of a path relative to the copied one, with its name and value.
+pub type Xattr = (PathBuf, std::ffi::OsString, Vec<u8>);
+
+/// Extended attributes of the path and its descendants, not of targets of symbolic links.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn read_xattrs(root: &Path) -> Result<Vec<Xattr>, MooveError> {
+    let mut xattrs = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let path = join_relative(root, &relative);
+        let read_error = |source| MooveError::ReadXattrs {
+            path: path.to_owned(),
+            source,
+        };
+        for name in xattr::list(&path).map_err(read_error)? {
+            if let Some(value) = xattr::get(&path, &name).map_err(read_error)? {
+                xattrs.push((relative.to_owned(), name, value));
+            }
+        }
+        let meta = path.symlink_metadata().map_err(read_error)?;
+        if meta.is_dir() {
+            for entry in std::fs::read_dir(&path).map_err(read_error)? {
+                pending.push(relative.join(entry.map_err(read_error)?.file_name()));
+            }
+        }
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read_xattrs(_root: &Path) -> Result<Vec<Xattr>, MooveError> {
+    Ok(Vec::new())
+}
+
+/// Apply extended attributes read by `read_xattrs` to the copy,
+/// warning about ones which its filesystem does not support.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn write_xattrs(root: &Path, xattrs: &[Xattr], args: &CommandLine) -> Result<(), MooveError> {
+    for (relative, name, value) in xattrs.iter() {
+        let path = join_relative(root, relative);
+        match xattr::set(&path, name, value) {
+            Ok(_) => {}
+            Err(err) if matches!(err.raw_os_error(), Some(code) if code == libc::ENOTSUP || code == libc::EOPNOTSUPP) =>
+            {
+                args.reporter().warn(&format!(
+                    "Extended attribute is not supported. {} of {}",
+                    name.to_string_lossy().yellow(),
+                    path.to_string_lossy().yellow().underline()
+                ));
+            }
+            Err(source) => {
+                return Err(MooveError::WriteXattr {
+                    name: name.to_owned(),
+                    path,
+                    source,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Path relative to the root, which is the root itself without a trailing separator if empty.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn join_relative(root: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn write_xattrs(
+    _root: &Path,
+    _xattrs: &[Xattr],
+    _args: &CommandLine,
+) -> Result<(), MooveError> {
+    Ok(())
+}
+
+/// Whether the path is a symbolic link or a directory containing any.
+pub fn contains_symlink(path: &Path) -> std::io::Result<bool> {
+    let meta = path.symlink_metadata()?;
+    if meta.is_symlink() {
+        return Ok(true);
+    }
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            if contains_symlink(&entry?.path())? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Copy the file or directory recursively, recreating symbolic links with their targets as they are.
+///
+/// Relative targets are kept relative and broken links are copied as broken.
+pub fn copy_preserving_links(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let meta = src.symlink_metadata()?;
+    if meta.is_symlink() {
+        symlink_like(&meta, &src.read_link()?, dst)
+    } else if meta.is_dir() {
+        std::fs::create_dir(dst)?;
+        std::fs::set_permissions(dst, meta.permissions())?;
+        for entry in std::fs::read_dir(src)? {
+            let name = entry?.file_name();
+            copy_preserving_links(&src.join(&name), &dst.join(&name))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(src, dst).map(|_| ())
+    }
+}
+
+/// Remove the file or directory recursively, or the symbolic link itself rather than its target.
+pub fn remove_path(path: &Path) -> std::io::Result<()> {
+    let meta = path.symlink_metadata()?;
+    #[cfg(target_family = "windows")]
+    {
+        use std::os::windows::fs::FileTypeExt;
+        if meta.file_type().is_symlink_dir() {
+            return std::fs::remove_dir(path);
+        }
+    }
+    if meta.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+/// Create a symbolic link to the target, of the same kind as the link of the metadata on Windows.
+#[cfg(target_family = "unix")]
+fn symlink_like(_meta: &Metadata, target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(target_family = "windows")]
+fn symlink_like(meta: &Metadata, target: &Path, link: &Path) -> std::io::Result<()> {
+    use std::os::windows::fs::FileTypeExt;
+    if meta.file_type().is_symlink_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Options to copy and move items by fs_extra.
+///
+/// Destinations are never overwritten nor skipped, since their absence is ensured when planned.
+/// The buffer size of zero, e.g. by `CommandLine::default`, is the default one of fs_extra.
+pub fn copy_options(args: &CommandLine) -> CopyOptions {
+    let default = CopyOptions::default();
+    CopyOptions {
+        overwrite: false,
+        skip_exist: false,
+        buffer_size: match args.buffer_size {
+            0 => default.buffer_size,
+            size => size as usize,
+        },
+        ..default
+    }
+}
+
+/// Rename in the same directory, which never needs relocation.
+pub fn execute_rename(operation: &Operation, args: &CommandLine) -> Result<(), MooveError> {
+    let Operation { src, dst, .. } = operation;
+    args.reporter().verbose(&format!(
+        "{} {}{}{}",
+        "Renaming".dimmed(),
+        src.text.dimmed().underline(),
+        " → ".dimmed(),
+        dst.text.dimmed().underline()
+    ));
+    // Destination is never over-written, ensured when the operation was made.
+    std::fs::rename(long_path(&src.path), long_path(&dst.path)).map_err(|source| {
+        MooveError::Rename {
+            from: src.path.to_owned(),
+            to: dst.path.to_owned(),
+            source,
+        }
+    })
+}
+
+/// Create parent directory if missing, unless `--no-create-dir`.
+pub fn create_dir(dst: &Destination, args: &CommandLine) -> Result<PathBuf, MooveError> {
+    let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+    let dst_parent = if dst.text.contains(std::path::MAIN_SEPARATOR) {
+        dst.path.parent().unwrap()
+    } else {
+        &current_dir
+    };
+    if !dst_parent.exists() {
+        if args.no_create_dir {
+            return Err(MooveError::MissingParent(dst_parent.to_path_buf()));
+        }
+        args.reporter().verbose(&format!(
+            "{} {}",
+            "Creating directory".dimmed(),
+            dst_parent.to_string_lossy().dimmed().underline()
+        ));
+        std::fs::create_dir_all(long_path(dst_parent)).map_err(|source| {
+            MooveError::CreateDirectory {
+                path: dst_parent.to_owned(),
+                source,
+            }
+        })?;
+    }
+    Ok(dst_parent.to_path_buf())
+}
+
+/// Maximum length of paths on Windows without the verbatim prefix `\\?\`, including the terminating null.
+pub const MAX_PATH: usize = 260;
+
+/// Whether the absolute path is too long to be operated without the verbatim prefix on Windows,
+/// even if it already has the prefix.
+pub fn is_long_path(abs: &Path) -> bool {
+    cfg!(target_family = "windows")
+        && strip_verbatim(abs).to_string_lossy().encode_utf16().count() >= MAX_PATH
+}
+
+/// Path to operate, which is verbatim if it is too long on Windows.
+pub fn long_path(path: &Path) -> std::borrow::Cow<'_, Path> {
+    if cfg!(not(target_family = "windows")) {
+        return path.into();
+    }
+    match path.absolute() {
+        Ok(abs) if is_long_path(abs.as_path()) => verbatim_path(abs.as_path()).into(),
+        _ => path.into(),
+    }
+}
+
+/// Absolute Windows path in the verbatim form, e.g. `\\?\C:\dir` or `\\?\UNC\server\share`.
+pub fn verbatim_path(abs: &Path) -> PathBuf {
+    let text = abs.to_string_lossy();
+    if text.starts_with(r"\\?\") {
+        abs.to_path_buf()
+    } else if let Some(unc) = text.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", unc))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", text))
+    }
+}
+
+/// Windows path without the verbatim prefix, which is the inverse of `verbatim_path`.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(unc) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(path) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Whether the source needs to be transferred into the directory of the destination,
+/// comparing their normalized absolute paths, since relative ones might be in the current directory.
+pub fn should_relocate(src: &Path, dst: &Path) -> bool {
+    src.parent() != dst.parent()
+}
+
+pub fn execute_remove(operation: &Operation, _args: &CommandLine) -> Result<()> {
+    let path = &operation.src.abs;
+    remove_path(path).map_err(|source| MooveError::Remove {
+        path: path.to_owned(),
+        source,
+    })?;
+    Ok(())
+}
+
+/// State file open to record progress of operations.
+pub struct StateFile {
+    path: PathBuf,
+    file: std::fs::File,
+}
+
+impl StateFile {
+    /// Write operations and the number of completed ones by `save_state`, to append progress.
+    pub fn create(path: &Path, operations: &[Operation], completed: usize) -> Result<StateFile> {
+        save_state(path, operations, completed)?;
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                format!(
+                    "Failed to open state file. {}",
+                    path.to_string_lossy().yellow().underline()
+                )
+            })?;
+        Ok(StateFile {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    /// Append the number of completed operations, which wins over the previous ones.
+    pub fn complete(&mut self, completed: usize) -> Result<()> {
+        writeln!(self.file, "completed\t{}", completed).with_context(|| {
+            format!(
+                "Failed to write state file. {}",
+                self.path.to_string_lossy().yellow().underline()
+            )
+        })
+    }
+}
+
+/// Write operations and the number of completed ones to the state file.
+///
+/// Paths are recorded as absolute so that they can be resumed from anywhere.
+/// The file is replaced atomically not to be corrupted by interruption.
+pub fn save_state(path: &Path, operations: &[Operation], completed: usize) -> Result<()> {
+    let current_dir = std::env::current_dir().map_err(MooveError::CurrentDirectory)?;
+    let mut text = format!("moove-state\ncompleted\t{}\n", completed);
+    for o in operations.iter() {
+        text.push_str(&format!(
+            "{}\t{}\t{}\n",
+            o.kind.name(),
+            escape_state_field(&escape_bytes(&o.src.abs)),
+            escape_state_field(&escape_bytes(&current_dir.join(&o.dst.path)))
+        ));
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    std::fs::write(&temp, text)
+        .and_then(|_| std::fs::rename(&temp, path))
+        .with_context(|| {
+            format!(
+                "Failed to write state file. {}",
+                path.to_string_lossy().yellow().underline()
+            )
+        })
+}
+
+pub fn load_state(path: &Path) -> Result<State> {
+    let text = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Failed to read state file. {}",
+            path.to_string_lossy().yellow().underline()
+        )
+    })?;
+    let invalid = || {
+        format!(
+            "Invalid state file. {}",
+            path.to_string_lossy().yellow().underline()
+        )
+    };
+    // A line being appended at interruption is ignored.
+    let text = &text[..text.rfind('\n').map_or(0, |i| i + 1)];
+    let mut lines = text.lines();
+    if lines.next() != Some("moove-state") {
+        anyhow::bail!(invalid());
+    }
+    let mut completed = None;
+    let mut entries = Vec::new();
+    for line in lines {
+        if let Some(n) = line.strip_prefix("completed\t") {
+            completed = Some(n.parse::<usize>().ok().with_context(invalid)?);
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 3 {
+            anyhow::bail!(invalid());
+        }
+        let kind = match fields[0] {
+            "move" => OperationKind::Move,
+            "rename" => OperationKind::Rename,
+            "copy" => OperationKind::Copy,
+            "remove" => OperationKind::Remove,
+            _ => anyhow::bail!(invalid()),
+        };
+        entries.push(StateEntry {
+            kind,
+            src: unescape_state_field(fields[1]).with_context(invalid)?,
+            dst: unescape_state_field(fields[2]).with_context(invalid)?,
+        });
+    }
+    let completed = completed.with_context(invalid)?;
+    if entries.len() < completed {
+        anyhow::bail!(invalid());
+    }
+    Ok(State { entries, completed })
+}
+
+/// Re-validate operations not completed yet.
+pub fn resume_operations(state: &State, args: &CommandLine) -> Result<Vec<Operation>> {
+    let mut operations = Vec::new();
+    for entry in state.entries.iter().skip(state.completed) {
+        let dst = unescape_path(&entry.dst);
+        let new_operation = Operation {
+            kind: entry.kind.clone(),
+            src: Source::from_path(&unescape_path(&entry.src))?,
+            dst: Destination::new(escape_path(&dst), dst)?,
+        };
+        if !matches!(new_operation.kind, OperationKind::Remove) {
+            is_operational_with(&operations, &new_operation, args.name_rules())
+                .context("Failed to resume.")
+                .context(Failure::Validation)?;
+        }
+        operations.push(new_operation);
+    }
+    args.reporter().verbose(&format!(
+        "{} {} of {}",
+        "Resuming".dimmed(),
+        operations.len().to_string().dimmed(),
+        state.entries.len().to_string().dimmed()
+    ));
+    Ok(operations)
+}
+
+fn escape_state_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape_state_field(s: &str) -> Result<String> {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            _ => anyhow::bail!("Invalid escape sequence. {}", s),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod lib {
+    use std::path::PathBuf;
+
+    use anyhow::{Context, Result};
+    use colored::*;
+    use normpath::PathExt;
+
+    use super::*;
+
+    /// Create temporary files before starting tests and removed by RAII.
+    struct Setup {
+        sandbox: PathBuf,
+        args: CommandLine,
+    }
+
+    impl Setup {
+        ///
+        /// Create following tree.
+        ///
+        /// ```ignore
+        /// {sandbox}/
+        ///   1/
+        ///   ├─1.txt
+        ///   ├─11/
+        ///   │  └─11.txt
+        ///   ├─12/
+        ///   │  └─12.txt
+        ///   2/
+        ///   ├─2.txt
+        ///   ├─21/
+        ///   │  ├─21.txt
+        ///   │  └─211/
+        ///   │      └─211.txt
+        ///   └─22
+        ///       └─22.txt
+        /// ```
+        fn init(key: &str) -> Result<Setup> {
+            let sandbox = &std::env::temp_dir().join("moove").join("test").join(key);
+            std::fs::create_dir_all(sandbox)?;
+            let dirs: Vec<PathBuf> = ["1", "1/11", "1/12", "2", "2/21", "2/21/211", "2/22"]
+                .iter()
+                .map(|d| {
+                    sandbox.join(PathBuf::from(if cfg!(target_family = "windows") {
+                        d.replace('/', "\\")
+                    } else {
+                        d.to_string()
+                    }))
+                })
+                .collect();
+            for dir in dirs.iter() {
+                println!("{} {}", "Creating".dimmed(), dir.to_string_lossy().dimmed());
+                std::fs::create_dir_all(dir)?;
+            }
+            let files: Vec<PathBuf> = dirs
+                .iter()
+                .map(|dir| dir.join(dir.file_name().unwrap()).with_extension("txt"))
+                .collect();
+            for file in files.iter() {
+                println!(
+                    "{} {}",
+                    "Creating".dimmed(),
+                    file.to_string_lossy().dimmed()
+                );
+                std::fs::File::create(file)?;
+            }
+            let setup = Setup {
+                sandbox: sandbox.to_owned(),
+                args: CommandLine {
+                    verbose: true,
+                    ..CommandLine::default()
+                },
+            };
+            Ok(setup)
+        }
+
+        fn source_from(&self, s: &str) -> Source {
+            let path = self.sandbox.join(if cfg!(target_family = "windows") {
+                s.replace('/', "\\")
+            } else {
                 s.to_string()
             });
             Source {
@@ -714,122 +5150,2047 @@ mod lib {
                     .context(format!("Failed to normalize {:?}", path))
                     .unwrap()
                     .into_path_buf(),
-                meta: path
-                    .metadata()
-                    .context(format!("Failed to get metadata {:?}", path))
-                    .unwrap(),
+                meta: SourceMeta::from(
+                    &path
+                        .metadata()
+                        .context(format!("Failed to get metadata {:?}", path))
+                        .unwrap(),
+                ),
+                inode: path.file_id(),
+            }
+        }
+
+        fn destination_from(&self, s: &str) -> Destination {
+            let path = self.sandbox.join(if cfg!(target_family = "windows") {
+                s.replace('/', "\\")
+            } else {
+                s.to_string()
+            });
+            Destination::new(path.to_string_lossy().to_string(), path.to_owned()).unwrap()
+        }
+
+        fn operation_from(&self, src: &str, dst: &str) -> Operation {
+            Operation {
+                kind: OperationKind::Move,
+                src: self.source_from(src),
+                dst: self.destination_from(dst),
+            }
+        }
+    }
+
+    impl Drop for Setup {
+        fn drop(&mut self) {
+            println!(
+                "{} {}",
+                "Removing".dimmed(),
+                self.sandbox.to_string_lossy().dimmed()
+            );
+            std::fs::remove_dir_all(&self.sandbox).unwrap();
+        }
+    }
+
+    #[test]
+    fn list_sources_normally() -> Result<()> {
+        let mut setup = Setup::init("list_sources_normally")?;
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.join("1").to_string_lossy().to_string());
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources[0].path, setup.sandbox.join("1/1.txt"));
+        assert_eq!(sources[1].path, setup.sandbox.join("1/11"));
+        assert_eq!(sources[2].path, setup.sandbox.join("1/12"));
+        setup.args.paths.clear();
+        setup.args.paths.push(
+            setup
+                .sandbox
+                .join("..")
+                .join(&setup.sandbox)
+                .join("1")
+                .to_string_lossy()
+                .to_string(),
+        );
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources[0].path, setup.sandbox.join("1/1.txt"));
+        assert_eq!(sources[1].path, setup.sandbox.join("1/11"));
+        assert_eq!(sources[2].path, setup.sandbox.join("1/12"));
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_patterns() -> Result<()> {
+        let mut setup = Setup::init("exclude_patterns")?;
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.join("1").to_string_lossy().to_string());
+        let listed = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?
+                .into_iter()
+                .map(|src| src.path)
+                .collect())
+        };
+        setup.args.exclude_patterns = vec![Regex::new(r"\.txt$")?];
+        assert_eq!(
+            listed(&setup.args)?,
+            vec![setup.sandbox.join("1/11"), setup.sandbox.join("1/12")]
+        );
+        setup.args.exclude_patterns = vec![Regex::new(r"11$")?];
+        assert_eq!(
+            listed(&setup.args)?,
+            vec![setup.sandbox.join("1/1.txt"), setup.sandbox.join("1/12")]
+        );
+        setup.args.exclude_patterns = vec![Regex::new(r"\.txt$")?, Regex::new(r"11$")?];
+        assert_eq!(listed(&setup.args)?, vec![setup.sandbox.join("1/12")]);
+        assert_eq!(sources_with_excluded(&setup.args)?.1, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_extensions() -> Result<()> {
+        let mut setup = Setup::init("filter_extensions")?;
+        let dir = setup.sandbox.join("3");
+        std::fs::create_dir_all(&dir)?;
+        for name in ["main.rs", "lib.RS", "Cargo.toml", "README.txt"] {
+            std::fs::write(dir.join(name), "")?;
+        }
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let listed = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?
+                .into_iter()
+                .map(|src| src.path)
+                .collect())
+        };
+        setup.args.extensions = vec!["rs".to_owned()];
+        assert_eq!(
+            listed(&setup.args)?,
+            [dir.join("lib.RS"), dir.join("main.rs")]
+        );
+        setup.args.extensions.push(".toml".to_owned());
+        assert_eq!(
+            listed(&setup.args)?,
+            [
+                dir.join("Cargo.toml"),
+                dir.join("lib.RS"),
+                dir.join("main.rs")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn list_sources_recursively() -> Result<()> {
+        let mut setup = Setup::init("list_sources_recursively")?;
+        std::fs::create_dir_all(setup.sandbox.join("1/.hidden"))?;
+        std::fs::write(setup.sandbox.join("1/.hidden/hidden.txt"), "")?;
+        setup.args.recursive = true;
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.to_string_lossy().to_string());
+        let listed = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?
+                .into_iter()
+                .map(|src| src.path)
+                .collect())
+        };
+        let txt = [
+            "1/1.txt",
+            "1/11/11.txt",
+            "1/12/12.txt",
+            "2/2.txt",
+            "2/21/21.txt",
+            "2/21/211/211.txt",
+            "2/22/22.txt",
+        ]
+        .map(|p| setup.sandbox.join(p));
+        assert_eq!(listed(&setup.args)?, txt);
+        setup.args.max_depth = Some(2);
+        assert_eq!(listed(&setup.args)?, [txt[0].to_owned(), txt[3].to_owned()]);
+        setup.args.directory = true;
+        setup.args.exclude_patterns = vec![Regex::new(r"2$")?];
+        assert_eq!(
+            listed(&setup.args)?,
+            ["1", "1/1.txt", "1/11"].map(|p| setup.sandbox.join(p))
+        );
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn move_non_utf8_path() -> Result<()> {
+        use std::os::unix::ffi::OsStringExt;
+        let setup = Setup::init("move_non_utf8_path")?;
+        let name = std::ffi::OsString::from_vec(b"caf\xe9 100%.txt".to_vec());
+        let path = setup.sandbox.join("1").join(&name);
+        std::fs::write(&path, "")?;
+        let src = Source::from_path(&path)?;
+        assert!(src.text.ends_with("caf%E9 100%25.txt"));
+        assert!(src.is_escaped());
+        assert_eq!(unescape_path(&src.text), path);
+        let dst_text = src.text.replace("%E9", "%E9%E9");
+        let operation = Operation {
+            kind: OperationKind::Rename,
+            src,
+            dst: Destination::new(dst_text.to_owned(), unescape_path(&dst_text))?,
+        };
+        execute_operation(&operation, &setup.args)?;
+        assert!(!path.exists());
+        assert!(setup
+            .sandbox
+            .join("1")
+            .join(std::ffi::OsString::from_vec(
+                b"caf\xe9\xe9 100%.txt".to_vec()
+            ))
+            .is_file());
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn list_broken_symlinks() -> Result<()> {
+        let mut setup = Setup::init("list_broken_symlinks")?;
+        let link = setup.sandbox.join("1").join("broken");
+        if link.symlink_metadata().is_err() {
+            std::os::unix::fs::symlink(setup.sandbox.join("nowhere"), &link)?;
+        }
+        let pattern = setup.sandbox.join("1").join("*");
+        let literal = link.to_string_lossy().to_string();
+        assert!(list_files(&[pattern.to_string_lossy().to_string()])?.contains(&literal));
+        assert_eq!(list_files(&[literal.to_owned()])?, [literal.to_owned()]);
+        setup.args.paths.push(literal);
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].abs, link);
+        assert!(sources[0].meta.is_symlink);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn follow_symlinks_to_filter() -> Result<()> {
+        let mut setup = Setup::init("follow_symlinks_to_filter")?;
+        let dir = setup.sandbox.join("1");
+        std::os::unix::fs::symlink(setup.sandbox.join("2/2.txt"), dir.join("link.txt"))?;
+        std::os::unix::fs::symlink(setup.sandbox.join("2/21"), dir.join("link"))?;
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let listed = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?
+                .into_iter()
+                .map(|src| src.path)
+                .collect())
+        };
+        setup.args.file_type = Some(FileType::File);
+        assert_eq!(listed(&setup.args)?, [dir.join("1.txt")]);
+        setup.args.follow_symlinks = true;
+        assert_eq!(
+            listed(&setup.args)?,
+            [dir.join("1.txt"), dir.join("link.txt")]
+        );
+        let sources = sources_from(&setup.args)?;
+        assert!(sources[1].meta.is_symlink);
+        setup.args.file_type = Some(FileType::Directory);
+        assert_eq!(
+            listed(&setup.args)?,
+            [dir.join("11"), dir.join("12"), dir.join("link")]
+        );
+        setup.args.follow_symlinks = false;
+        setup.args.file_type = Some(FileType::Symlink);
+        assert_eq!(
+            listed(&setup.args)?,
+            [dir.join("link"), dir.join("link.txt")]
+        );
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn hide_symlinks_by_themselves() -> Result<()> {
+        let mut setup = Setup::init("hide_symlinks_by_themselves")?;
+        let dir = setup.sandbox.join("1");
+        std::fs::write(dir.join(".hidden.txt"), "")?;
+        std::os::unix::fs::symlink(dir.join(".hidden.txt"), dir.join("link.txt"))?;
+        std::os::unix::fs::symlink(dir.join("1.txt"), dir.join(".link.txt"))?;
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let names: Vec<String> = sources_from(&setup.args)?
+            .iter()
+            .map(|src| src.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert!(names.contains(&"link.txt".to_owned()));
+        assert!(!names.contains(&".hidden.txt".to_owned()));
+        assert!(!names.contains(&".link.txt".to_owned()));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn hide_files_under_hidden_dirs() -> Result<()> {
+        let mut setup = Setup::init("hide_files_under_hidden_dirs")?;
+        let hidden = setup.sandbox.join("1/.hidden");
+        std::fs::create_dir(&hidden)?;
+        std::fs::write(hidden.join("file.txt"), "")?;
+        let sources = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?
+                .into_iter()
+                .map(|src| src.path)
+                .collect())
+        };
+        let pattern = setup.sandbox.join("1/*/*.txt");
+        setup.args.paths = vec![pattern.to_string_lossy().to_string()];
+        let listed = sources(&setup.args)?;
+        assert_eq!(listed.len(), 2);
+        assert!(!listed.contains(&hidden.join("file.txt")));
+        // Hidden directories given explicitly are not hidden by themselves.
+        setup.args.paths = vec![hidden.to_string_lossy().to_string()];
+        assert_eq!(sources(&setup.args)?, [hidden.join("file.txt")]);
+        setup.args.directory = true;
+        assert_eq!(sources(&setup.args)?, [hidden.to_owned()]);
+        setup.args.directory = false;
+        setup.args.paths = vec![pattern.to_string_lossy().to_string()];
+        setup.args.with_hidden = true;
+        assert_eq!(sources(&setup.args)?.len(), 3);
+        Ok(())
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn hide_symlinks_by_themselves() -> Result<()> {
+        let mut setup = Setup::init("hide_symlinks_by_themselves")?;
+        let dir = setup.sandbox.join("1");
+        let hidden = dir.join("hidden.txt");
+        std::fs::write(&hidden, "")?;
+        std::process::Command::new("attrib")
+            .arg("+h")
+            .arg(&hidden)
+            .status()?;
+        // Creating symbolic links needs the developer mode or the privilege.
+        if std::os::windows::fs::symlink_file(&hidden, dir.join("link.txt")).is_err() {
+            return Ok(());
+        }
+        std::os::windows::fs::symlink_file(dir.join("nowhere.txt"), dir.join("dangling.txt"))?;
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let names: Vec<String> = sources_from(&setup.args)?
+            .iter()
+            .map(|src| src.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert!(names.contains(&"link.txt".to_owned()));
+        assert!(names.contains(&"dangling.txt".to_owned()));
+        assert!(!names.contains(&"hidden.txt".to_owned()));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn edit_with_editor() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let setup = Setup::init("edit_with_editor")?;
+        let script = |name: &str, body: &str| -> Result<String> {
+            let path = setup.sandbox.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body))?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            Ok(path.to_string_lossy().to_string())
+        };
+        let append = script("append.sh", r#"echo added >> "$1""#)?;
+        assert_eq!(open_editor("text\n", Some(&append), &[])?, "text\nadded\n");
+        let argv = script("argv.sh", r#"printf '%s\n' "$1" "$2" > "$3""#)?;
+        let editor_args = shell_split(r#"-c "set ft=moove""#);
+        assert_eq!(
+            open_editor("", Some(&argv), &editor_args)?,
+            "-c\nset ft=moove\n"
+        );
+        let fail = script("fail.sh", "exit 1")?;
+        let err = open_editor("text\n", Some(&fail), &[]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Failure>(),
+            Some(Failure::Aborted)
+        ));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn write_to_pager() -> Result<()> {
+        let setup = Setup::init("write_to_pager")?;
+        let paged = setup.sandbox.join("paged.txt");
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("cat > '{}'", paged.display()));
+        let mut pager = PagerWriter::new(command)?;
+        assert_eq!(pager.write(b"Move a")?, 6);
+        writeln!(pager, " → b")?;
+        // `cat` exits on EOF, which must be waited on drop.
+        drop(pager);
+        assert_eq!(std::fs::read_to_string(&paged)?, "Move a → b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn keep_percent_in_valid_names() -> Result<()> {
+        let setup = Setup::init("keep_percent_in_valid_names")?;
+        let path = setup.sandbox.join("1").join("100%E9.txt");
+        std::fs::write(&path, "")?;
+        let src = Source::from_path(&path)?;
+        assert_eq!(src.text, path.to_string_lossy());
+        assert!(!src.is_escaped());
+        let sources = vec![src];
+        let line = sources[0].text.to_owned();
+        assert!(plan_from_lines(&sources, &[&line], &setup.args)?
+            .operations
+            .is_empty());
+        let line = line.replace("100%E9", "100%E9%41");
+        let plan = plan_from_lines(&sources, &[&line], &setup.args)?;
+        assert_eq!(plan.operations[0].dst.path, PathBuf::from(&line));
+        Ok(())
+    }
+
+    #[test]
+    fn confirm_plan() -> Result<()> {
+        let setup = Setup::init("confirm_plan")?;
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/11/11.txt", "4/44/b.txt"),
+            Operation {
+                kind: OperationKind::Copy,
+                ..setup.operation_from("2/2.txt", "4/c.txt")
+            },
+        ];
+        let mut plan = Vec::new();
+        write_plan(&mut plan, &operations)?;
+        let plan = String::from_utf8(plan)?;
+        let lines: Vec<&str> = plan.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let arrow = lines[0].find('→');
+        assert!(lines[..3].iter().all(|line| line.find('→') == arrow));
+        assert_eq!(lines[3], "2 moves, 1 copy, 2 directories will be created");
+        let prompt = |editable, answers: &str| {
+            prompt_proceed_from(&setup.args, editable, &mut answers.as_bytes()).unwrap()
+        };
+        assert_eq!(prompt(true, "y\n"), Resolution::Continue);
+        assert_eq!(prompt(true, "\n"), Resolution::Abort);
+        assert_eq!(prompt(true, "e\n"), Resolution::Edit);
+        assert_eq!(prompt(false, "e\nn\n"), Resolution::Abort);
+        assert_eq!(prompt(true, ""), Resolution::Abort);
+        Ok(())
+    }
+
+    #[test]
+    fn answer_preview() -> Result<()> {
+        let setup = Setup::init("answer_preview")?;
+        let preview = |answers: &str| prompt_preview_from(&setup.args, &mut answers.as_bytes());
+        assert!(matches!(preview("\n")?, Preview::Continue));
+        assert!(matches!(preview("a\n")?, Preview::Abort));
+        assert!(matches!(preview("x\n")?, Preview::Abort));
+        let Preview::Exclude(pattern) = preview("x\n(\nx\n\\.txt$\n")? else {
+            panic!("Pattern should be excluded.");
+        };
+        assert!(pattern.is_match("1/1.txt"));
+        assert!(!pattern.is_match("1/11"));
+        Ok(())
+    }
+
+    #[test]
+    fn copy_with_buffer_size() -> Result<()> {
+        let mut setup = Setup::init("copy_with_buffer_size")?;
+        assert_eq!(
+            copy_options(&setup.args).buffer_size,
+            CopyOptions::default().buffer_size
+        );
+        setup.args.buffer_size = 1000;
+        assert_eq!(copy_options(&setup.args).buffer_size, 1000);
+        let content: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(setup.sandbox.join("1/12/large.bin"), &content)?;
+        let mut operation = setup.operation_from("1/12", "2/copied-12");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        assert_eq!(
+            std::fs::read(setup.sandbox.join("2/copied-12/large.bin"))?,
+            content
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn copy_dir_within_same_dir() -> Result<()> {
+        let setup = Setup::init("copy_dir_within_same_dir")?;
+        let mut operation = setup.operation_from("1/12", "1/copied-12");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        assert!(setup.sandbox.join("1/12/12.txt").is_file());
+        assert!(setup.sandbox.join("1/copied-12/12.txt").is_file());
+        assert!(!setup.sandbox.join("1/copied-12/12").exists());
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn copy_symlinks_as_links() -> Result<()> {
+        let mut setup = Setup::init("copy_symlinks_as_links")?;
+        for (link, target) in [
+            ("1/12/linked", "12.txt"),
+            ("1/12/broken", "nowhere"),
+            ("1/linked", "1.txt"),
+        ] {
+            let link = setup.sandbox.join(link);
+            if link.symlink_metadata().is_err() {
+                std::os::unix::fs::symlink(target, link)?;
+            }
+        }
+        for (src, dst) in [("1/12", "2/copied-12"), ("1/linked", "2/copied-linked")] {
+            let mut operation = setup.operation_from(src, dst);
+            operation.kind = OperationKind::Copy;
+            execute_operation(&operation, &setup.args)?;
+        }
+        let target_of = |path: &str| setup.sandbox.join(path).read_link();
+        assert_eq!(target_of("2/copied-12/linked")?, Path::new("12.txt"));
+        assert_eq!(target_of("2/copied-12/broken")?, Path::new("nowhere"));
+        assert!(setup.sandbox.join("2/copied-12/12.txt").is_file());
+        assert_eq!(target_of("2/copied-linked")?, Path::new("1.txt"));
+        setup.args.dereference = true;
+        let mut operation = setup.operation_from("1/linked", "2/dereferenced");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        let meta = setup.sandbox.join("2/dereferenced").symlink_metadata()?;
+        assert!(meta.is_file());
+        Ok(())
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn copy_symlinks_as_links() -> Result<()> {
+        let setup = Setup::init("copy_symlinks_as_links")?;
+        let link = setup.sandbox.join("1/12/linked");
+        // Creating symbolic links requires the privilege or the developer mode.
+        if link.symlink_metadata().is_err()
+            && std::os::windows::fs::symlink_file("12.txt", &link).is_err()
+        {
+            return Ok(());
+        }
+        let mut operation = setup.operation_from("1/12", "2/copied-12");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        let copied = setup.sandbox.join("2/copied-12/linked");
+        assert_eq!(copied.read_link()?, Path::new("12.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn never_overwrite_destination_created_after_planning() -> Result<()> {
+        let setup = Setup::init("never_overwrite_destination_created_after_planning")?;
+        std::fs::write(setup.sandbox.join("1/1.txt"), "source")?;
+        let mut operations = [
+            setup.operation_from("1/1.txt", "2/created.txt"),
+            setup.operation_from("1/1.txt", "2/created.txt"),
+        ];
+        operations[1].kind = OperationKind::Copy;
+        std::fs::write(setup.sandbox.join("2/created.txt"), "created")?;
+        for o in operations.iter() {
+            let err = execute_operation(o, &setup.args).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<MooveError>(),
+                Some(MooveError::DestinationExists(_))
+            ));
+            assert_eq!(std::fs::read_to_string(&o.dst.path)?, "created");
+            assert_eq!(std::fs::read_to_string(&o.src.path)?, "source");
+        }
+        // Empty directories are replaced by renaming on Unix.
+        let o = setup.operation_from("1/11", "2/created");
+        std::fs::create_dir(&o.dst.path)?;
+        assert!(execute_operation(&o, &setup.args).is_err());
+        assert!(o.src.path.join("11.txt").is_file());
+        assert!(!o.dst.path.join("11.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn fail_to_rename_on_same_filesystem() -> Result<()> {
+        let setup = Setup::init("fail_to_rename_on_same_filesystem")?;
+        // Into itself, which is not copied instead.
+        let o = setup.operation_from("1", "1/12/1");
+        let err = execute_operation(&o, &setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::Rename { .. })
+        ));
+        assert!(o.src.path.join("11/11.txt").is_file());
+        assert!(!o.dst.path.exists());
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn move_symlinks_themselves() -> Result<()> {
+        let mut setup = Setup::init("move_symlinks_themselves")?;
+        let absolute = setup.sandbox.join("1/1.txt");
+        for (link, target) in [
+            ("1/broken", Path::new("nowhere")),
+            ("1/linked-dir", Path::new("12")),
+            ("1/absolute", absolute.as_path()),
+        ] {
+            std::os::unix::fs::symlink(target, setup.sandbox.join(link))?;
+        }
+        // Copying and removing is what moving across devices does.
+        for force_copy in [false, true] {
+            setup.args.force_copy = force_copy;
+            for (src, dst) in [
+                ("1/broken", "2/21/broken"),
+                ("1/linked-dir", "2/linked-dir"),
+                ("1/absolute", "2/22/absolute"),
+            ] {
+                let (src, dst) = (setup.sandbox.join(src), setup.destination_from(dst));
+                let operation = Operation {
+                    kind: OperationKind::Move,
+                    src: Source::from_path(&src)?,
+                    dst: dst.to_owned(),
+                };
+                execute_operation(&operation, &setup.args)?;
+                assert!(src.symlink_metadata().is_err());
+                assert!(dst.path.symlink_metadata()?.is_symlink());
+                execute_operation(&operation.inverse()?, &setup.args)?;
+            }
+            let target_of = |path: &str| setup.sandbox.join(path).read_link();
+            assert_eq!(target_of("1/broken")?, Path::new("nowhere"));
+            assert_eq!(target_of("1/linked-dir")?, Path::new("12"));
+            assert_eq!(target_of("1/absolute")?, absolute);
+            assert!(setup.sandbox.join("1/12/12.txt").is_file());
+            assert!(absolute.is_file());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn report_progress_to_hooks() -> Result<()> {
+        #[derive(Debug, Default)]
+        struct Recorder(std::sync::Mutex<Vec<Progress>>);
+        impl Hooks for Recorder {
+            fn on_progress(&self, _o: &Operation, progress: &Progress) {
+                self.0.lock().unwrap().push(progress.to_owned());
+            }
+        }
+        let mut setup = Setup::init("report_progress_to_hooks")?;
+        let recorder = std::sync::Arc::new(Recorder::default());
+        setup.args.hooks = Some(recorder.clone());
+        setup.args.buffer_size = 1024;
+        std::fs::write(setup.sandbox.join("1/12/large.bin"), vec![0u8; 10 * 1024])?;
+        let mut operation = setup.operation_from("1/12", "2/copied-12");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        let progresses = recorder.0.lock().unwrap();
+        assert!(progresses.len() > 1);
+        assert!(progresses
+            .windows(2)
+            .all(|w| w[0].copied_bytes <= w[1].copied_bytes));
+        let last = progresses.last().unwrap();
+        assert_eq!(last.copied_bytes, last.total_bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn track_throughput() -> Result<()> {
+        let setup = Setup::init("track_throughput")?;
+        let large = setup.sandbox.join("large.bin");
+        std::fs::write(&large, vec![0u8; 1024 * 1024])?;
+        let mut tracker = ThroughputTracker::new();
+        fs_extra::copy_items_with_progress(
+            &[&large],
+            setup.sandbox.join("2"),
+            &CopyOptions::default(),
+            |p| {
+                tracker.update(p.copied_bytes);
+                fs_extra::dir::TransitProcessResult::ContinueOrAbort
+            },
+        )?;
+        assert_eq!(tracker.bytes, 1024 * 1024);
+        let report = tracker.report(1024 * 1024);
+        assert!(report.ends_with("/s, ETA 00:00"), "{}", report);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn time_out_operation() -> Result<()> {
+        let setup = Setup::init("time_out_operation")?;
+        let fifo = setup.sandbox.join("fifo");
+        assert!(std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()?
+            .success());
+        // Copying blocks until something is written to the FIFO, treated as a file.
+        let mut operation = Operation {
+            kind: OperationKind::Copy,
+            ..setup.operation_from("fifo", "1/fifo")
+        };
+        operation.src.meta.is_file = true;
+        let timeout = std::time::Duration::from_millis(200);
+        let started = std::time::Instant::now();
+        let err = execute_operation_within(&operation, &setup.args, timeout).unwrap_err();
+        assert!(started.elapsed() < timeout * 2);
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::Timeout { .. })
+        ));
+        // Unblock the copying thread.
+        std::fs::write(&fifo, "")?;
+        let operation = setup.operation_from("1/1.txt", "1/a.txt");
+        execute_operation_within(&operation, &setup.args, timeout)?;
+        assert!(setup.sandbox.join("1/a.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn find_changed_range() {
+        let range = |a: &'static str, b: &'static str| {
+            let (prefix, a_end, b_end) = changed_range(a, b);
+            (
+                &a[..prefix],
+                &a[prefix..a_end],
+                &b[prefix..b_end],
+                &a[a_end..],
+            )
+        };
+        assert_eq!(
+            range("1/IMG_0101.jpg", "1/2024-01-02.jpg"),
+            ("1/", "IMG_0101", "2024-01-02", ".jpg")
+        );
+        assert_eq!(range("abc", "xyz"), ("", "abc", "xyz", ""));
+        assert_eq!(range("aa.txt", "aaa.txt"), ("aa", "", "a", ".txt"));
+        assert_eq!(range("写真.jpg", "写眞.jpg"), ("写", "真", "眞", ".jpg"));
+    }
+
+    #[test]
+    fn retry_transient_failures() -> Result<()> {
+        let mut setup = Setup::init("retry_transient_failures")?;
+        setup.args.retry = true;
+        setup.args.retry_max = 3;
+        setup.args.retry_delay = std::time::Duration::from_millis(10);
+        let o = setup.operation_from("1/1.txt", "1/a.txt");
+        let busy = || std::io::Error::from_raw_os_error(RETRIED_ERRNOS[0]);
+        let mut attempts = 0;
+        with_retry(&o, &setup.args, || {
+            attempts += 1;
+            if attempts < 3 {
+                return Err(anyhow::Error::new(busy()).context("Failed to move."));
             }
+            Ok(execute_rename(&o, &setup.args)?)
+        })?;
+        assert_eq!(attempts, 3);
+        assert!(setup.sandbox.join("1/a.txt").is_file());
+        attempts = 0;
+        let result = with_retry(&o, &setup.args, || {
+            attempts += 1;
+            Err(busy().into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 4);
+        attempts = 0;
+        let result = with_retry(&o, &setup.args, || {
+            attempts += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn list_files_matched_by_patterns_once() -> Result<()> {
+        let setup = Setup::init("list_files_matched_by_patterns_once")?;
+        let sandbox = setup.sandbox.to_string_lossy().to_string();
+        let sep = std::path::MAIN_SEPARATOR;
+        let files = list_files(&[
+            format!("{}{}1{}*.txt", sandbox, sep, sep),
+            format!("{}{}1{}1.*", sandbox, sep, sep),
+            format!("{}{}1{}11{}..{}*.txt", sandbox, sep, sep, sep, sep),
+        ])?;
+        assert_eq!(
+            files,
+            [setup.sandbox.join("1").join("1.txt").to_string_lossy()]
+        );
+        let literal = setup.sandbox.join("1").join("1.txt");
+        let literal = literal.to_string_lossy().to_string();
+        assert_eq!(
+            list_files(&[literal.to_owned(), literal.to_owned()])?.len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expand_brace_patterns() -> Result<()> {
+        assert_eq!(
+            expand_braces("img{01,02,03}.jpg"),
+            ["img01.jpg", "img02.jpg", "img03.jpg"]
+        );
+        assert_eq!(expand_braces("{a{1,2},b}"), ["a1", "a2", "b"]);
+        assert_eq!(expand_braces("{a,b}{1,2}"), ["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("{a}"), ["{a}"]);
+        assert_eq!(expand_braces("{a,b"), ["{a,b"]);
+        assert_eq!(expand_braces("a,b}"), ["a,b}"]);
+        assert_eq!(expand_braces(r"\{a,b\}"), ["{a,b}"]);
+        assert_eq!(expand_braces(r"{a\,b,c}"), ["a,b", "c"]);
+        assert_eq!(expand_braces(""), [""]);
+        let setup = Setup::init("expand_brace_patterns")?;
+        for name in ["fileA.txt", "fileB.txt", "fileC.txt"] {
+            std::fs::write(setup.sandbox.join("1").join(name), name)?;
+        }
+        let pattern = setup.sandbox.join("1/file{A,B,C}.txt");
+        let files = list_files(&[pattern.to_string_lossy().to_string()])?;
+        assert_eq!(files.len(), 3);
+        assert!(files[2].ends_with("fileC.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_windows_patterns() {
+        assert_eq!(windows_pattern(r"photos\*.jpg"), "photos/*.jpg");
+        assert_eq!(windows_pattern("photos/*.jpg"), "photos/*.jpg");
+        assert_eq!(windows_pattern(r"C:\a\\[1\].txt"), "C:/a/[[]1[]].txt");
+        assert_eq!(windows_pattern(r"a\b\?"), "a/b/?");
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn glob_with_backslashes() -> Result<()> {
+        let setup = Setup::init("glob_with_backslashes")?;
+        let sandbox = setup.sandbox.to_string_lossy();
+        std::fs::write(setup.sandbox.join("1/[1].txt"), "")?;
+        let slashed = list_files(&[format!("{}/1/*.txt", sandbox.replace('\\', "/"))])?;
+        let backslashed = list_files(&[format!(r"{}\1\*.txt", sandbox)])?;
+        assert_eq!(slashed.len(), 2);
+        assert_eq!(slashed, backslashed);
+        let escaped = list_files(&[format!(r"{}\1\\[1\].txt", sandbox)])?;
+        assert_eq!(escaped.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn make_verbatim_paths() {
+        assert_eq!(
+            verbatim_path(Path::new(r"C:\a\b")),
+            Path::new(r"\\?\C:\a\b")
+        );
+        assert_eq!(
+            verbatim_path(Path::new(r"\\server\share\a")),
+            Path::new(r"\\?\UNC\server\share\a")
+        );
+        assert_eq!(
+            verbatim_path(Path::new(r"\\?\C:\a")),
+            Path::new(r"\\?\C:\a")
+        );
+    }
+
+    #[test]
+    fn strip_verbatim_paths() {
+        for path in [r"C:\a\b", r"\\server\share\a", "a/b"] {
+            assert_eq!(
+                strip_verbatim(&verbatim_path(Path::new(path))),
+                Path::new(path)
+            );
+        }
+        assert_eq!(strip_verbatim(Path::new("a/b")), Path::new("a/b"));
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn absolute_long_paths() -> Result<()> {
+        let setup = Setup::init("absolute_long_paths")?;
+        let long = ["a".repeat(100), "b".repeat(100), "c".repeat(100)].join("\\");
+        let dir = long_path(&setup.sandbox.join(&long)).into_owned();
+        std::fs::create_dir_all(&dir)?;
+        let abs = setup.sandbox.join(&long).absolute()?;
+        assert!(abs.as_path().to_string_lossy().starts_with(r"\\?\"));
+        assert_eq!(dir.absolute()?.as_path(), abs.as_path());
+        assert!(!setup
+            .sandbox
+            .absolute()?
+            .as_path()
+            .to_string_lossy()
+            .starts_with(r"\\?\"));
+        Ok(())
+    }
+
+    #[cfg(target_family = "windows")]
+    #[test]
+    fn operate_long_paths() -> Result<()> {
+        let setup = Setup::init("operate_long_paths")?;
+        let long = ["a".repeat(100), "b".repeat(100), "c".repeat(100)].join("\\");
+        let operation = setup.operation_from("1/1.txt", &format!("1/{}/1.txt", long));
+        assert!(is_long_path(&operation.dst.abs));
+        execute_operation(&operation, &setup.args)?;
+        assert!(long_path(&operation.dst.path).is_file());
+        let operation = setup.operation_from(&format!("1/{}/1.txt", long), "1/1.txt");
+        execute_operation(&operation, &setup.args)?;
+        assert!(setup.sandbox.join("1/1.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn read_paths_from_list_file() -> Result<()> {
+        let mut setup = Setup::init("read_paths_from_list_file")?;
+        let list = setup.sandbox.join("list.txt");
+        let sandbox = setup.sandbox.to_string_lossy();
+        std::fs::write(
+            &list,
+            format!(
+                "# Sources\n{0}/1/1.txt\n\n  \n{0}/1/11\r\n# {0}/2\n{0}/2/2.txt\n",
+                sandbox
+            ),
+        )?;
+        setup.args.paths = read_paths_from_file(&list)?;
+        assert_eq!(setup.args.paths.len(), 3);
+        setup.args.directory = true;
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources.len(), 3);
+        assert!(sources[2].path.ends_with("2/2.txt"));
+        assert!(read_paths_from_file(&setup.sandbox.join("missing.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn split_like_shell() {
+        assert_eq!(shell_split(""), Vec::<String>::new());
+        assert_eq!(shell_split("  --wait  -n "), ["--wait", "-n"]);
+        assert_eq!(
+            shell_split(r#"-c "set ft=moove" 'a "b"' c\ d "\"e\\" f"'g'""#),
+            ["-c", "set ft=moove", r#"a "b""#, "c d", r#""e\"#, "f'g'"]
+        );
+        assert_eq!(shell_split("''"), [""]);
+    }
+
+    #[test]
+    fn read_paths_separated_by_null() -> Result<()> {
+        let mut setup = Setup::init("read_paths_separated_by_null")?;
+        std::fs::File::create(setup.sandbox.join("with space.txt"))?;
+        std::fs::File::create(setup.sandbox.join("[glob].txt"))?;
+        let input = format!(
+            "{}\0{}\0",
+            setup.sandbox.join("with space.txt").display(),
+            setup.sandbox.join("[glob].txt").display()
+        );
+        setup.args.literal_paths = read_null_separated_paths(std::io::Cursor::new(input))?;
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[0].path, setup.sandbox.join("with space.txt"));
+        assert_eq!(sources[1].path, setup.sandbox.join("[glob].txt"));
+        assert_eq!(
+            read_paths(std::io::Cursor::new("a\r\nb c\n"))?,
+            vec!["a", "b c"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn read_paths_separated_by_null_with_new_line() -> Result<()> {
+        let mut setup = Setup::init("read_paths_separated_by_null_with_new_line")?;
+        let path = setup.sandbox.join("new\nline.txt");
+        std::fs::File::create(&path)?;
+        let input = format!("{}\0", path.display());
+        setup.args.literal_paths = read_null_separated_paths(std::io::Cursor::new(input))?;
+        let sources = sources_from(&setup.args)?;
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].path, path);
+        Ok(())
+    }
+
+    #[test]
+    fn sort_sources_by_key() -> Result<()> {
+        let mut setup = Setup::init("sort_sources_by_key")?;
+        let dir = setup.sandbox.join("3");
+        std::fs::create_dir(&dir)?;
+        let now = std::time::SystemTime::now();
+        for (name, size, age) in [("a.txt", 3, 10), ("b.log", 1, 30), ("c", 2, 20)] {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0; size])?;
+            let mtime = now - std::time::Duration::from_secs(age);
+            filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(mtime))?;
         }
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let names = |args: &CommandLine| -> Result<Vec<String>> {
+            let sources = sources_from(args)?;
+            Ok(text_from(&sources, args)
+                .split('\n')
+                .map(|line| {
+                    Path::new(line)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect())
+        };
+        setup.args.sort_by = Some(SortKey::Name);
+        assert_eq!(names(&setup.args)?, ["a.txt", "b.log", "c"]);
+        setup.args.sort_by = Some(SortKey::Mtime);
+        assert_eq!(names(&setup.args)?, ["b.log", "c", "a.txt"]);
+        setup.args.sort_by = Some(SortKey::Size);
+        assert_eq!(names(&setup.args)?, ["b.log", "c", "a.txt"]);
+        setup.args.sort_by = Some(SortKey::Ext);
+        assert_eq!(names(&setup.args)?, ["c", "b.log", "a.txt"]);
+        setup.args.reverse = true;
+        setup.args.sort_by = Some(SortKey::Size);
+        assert_eq!(names(&setup.args)?, ["a.txt", "c", "b.log"]);
+        setup.args.reverse = false;
+        std::fs::create_dir(dir.join("0"))?;
+        std::fs::write(dir.join("0").join("d"), "")?;
+        setup.args.recursive = true;
+        setup.args.sort = true;
+        setup.args.sort_by = Some(SortKey::Depth);
+        assert_eq!(names(&setup.args)?, ["a.txt", "b.log", "c", "d"]);
+        Ok(())
+    }
 
-        fn destination_from(&self, s: &str) -> Destination {
-            let path = self.sandbox.join(if cfg!(target_family = "windows") {
-                s.replace('/', "\\")
+    #[test]
+    fn reverse_sources() -> Result<()> {
+        let mut setup = Setup::init("reverse_sources")?;
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.join("1").to_string_lossy().to_string());
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.join("2").to_string_lossy().to_string());
+        for (sort, sort_by) in [
+            (false, None),
+            (true, None),
+            (false, Some(SortKey::Name)),
+            (false, Some(SortKey::Size)),
+        ] {
+            setup.args.sort = sort;
+            setup.args.sort_by = sort_by;
+            setup.args.reverse = false;
+            let text = text_from(&sources_from(&setup.args)?, &setup.args);
+            let mut lines = text.split('\n').collect::<Vec<_>>();
+            setup.args.reverse = true;
+            let reversed = text_from(&sources_from(&setup.args)?, &setup.args);
+            if !sort && sort_by.is_none() {
+                // Children are reversed in each directory.
+                lines[..3].reverse();
+                lines[3..].reverse();
             } else {
-                s.to_string()
-            });
-            Destination {
-                text: path.to_string_lossy().to_string(),
-                path: path.to_owned(),
+                lines.reverse();
             }
+            assert_eq!(reversed.split('\n').collect::<Vec<_>>(), lines);
         }
+        Ok(())
+    }
 
-        fn operation_from(&self, src: &str, dst: &str) -> Operation {
-            Operation {
-                kind: OperationKind::Move,
-                src: self.source_from(src),
-                dst: self.destination_from(dst),
-            }
+    #[test]
+    fn shuffle_sources() -> Result<()> {
+        let mut setup = Setup::init("shuffle_sources")?;
+        let dir = setup.sandbox.join("3");
+        std::fs::create_dir_all(&dir)?;
+        for i in 0..20 {
+            std::fs::write(dir.join(format!("{}.txt", i)), "")?;
         }
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let paths = |args: &CommandLine| -> Result<Vec<PathBuf>> {
+            Ok(sources_from(args)?.into_iter().map(|s| s.path).collect())
+        };
+        let original = paths(&setup.args)?;
+        setup.args.shuffle = true;
+        setup.args.seed = Some(42);
+        let shuffled = paths(&setup.args)?;
+        assert_ne!(shuffled, original);
+        assert_eq!(paths(&setup.args)?, shuffled);
+        let mut sorted = shuffled.to_owned();
+        sorted.sort_by(|a, b| natord::compare(&a.to_string_lossy(), &b.to_string_lossy()));
+        assert_eq!(sorted, original);
+        setup.args.sort = true;
+        assert!(matches!(
+            setup.args.validate(),
+            Err(MooveError::ConflictingFlags(_))
+        ));
+        Ok(())
     }
 
-    impl Drop for Setup {
-        fn drop(&mut self) {
-            println!(
-                "{} {}",
-                "Removing".dimmed(),
-                self.sandbox.to_string_lossy().dimmed()
-            );
-            std::fs::remove_dir_all(&self.sandbox).unwrap();
+    #[test]
+    fn group_sources_by_extension() -> Result<()> {
+        let mut setup = Setup::init("group_sources_by_extension")?;
+        let dir = setup.sandbox.join("3");
+        std::fs::create_dir_all(&dir)?;
+        for name in ["b.toml", "a.rs", "README.md", "c.rs", "a.toml", "b.md"] {
+            std::fs::write(dir.join(name), "")?;
         }
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        setup.args.group_by_extension = true;
+        let sources = sources_from(&setup.args)?;
+        let text = text_from(&sources, &setup.args);
+        let name = |line: &str| {
+            if line.starts_with('#') {
+                line.to_string()
+            } else {
+                Path::new(line)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            }
+        };
+        assert_eq!(
+            text.lines().map(name).collect::<Vec<_>>(),
+            [
+                "# .md",
+                "README.md",
+                "b.md",
+                "# .rs",
+                "a.rs",
+                "c.rs",
+                "# .toml",
+                "a.toml",
+                "b.toml"
+            ]
+        );
+        assert_eq!(
+            lines_from(&text),
+            sources.iter().map(|s| s.text.as_str()).collect::<Vec<_>>()
+        );
+        Ok(())
     }
 
     #[test]
-    fn list_sources_normally() -> Result<()> {
-        let mut setup = Setup::init("list_sources_normally")?;
+    fn should_fail_to_list_sources() -> Result<()> {
+        let mut setup = Setup::init("should_fail_to_list_sources")?;
+        let missing = setup.sandbox.join("does not exist");
+        setup.args.paths.push(missing.to_string_lossy().to_string());
+        assert!(sources_from(&setup.args).is_err());
+        let err = put_source(&mut vec![], &missing, &setup.args).unwrap_err();
+        let source = std::error::Error::source(&err)
+            .and_then(|e| e.downcast_ref::<std::io::Error>())
+            .map(|e| e.kind());
+        assert_eq!(source, Some(std::io::ErrorKind::NotFound));
+        let err = Source::from_path(&missing).unwrap_err();
+        assert!(matches!(err, MooveError::SourceAccess { path, .. } if path == missing));
+        setup.args.paths.clear();
+        setup.args.paths.push("/".to_owned());
+        assert!(sources_from(&setup.args).is_err());
+        setup.args.directory = true;
+        let err = sources_from(&setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::RootSource(_))
+        ));
+        setup.args.directory = false;
+        setup.args.paths.clear();
         setup
             .args
             .paths
             .push(setup.sandbox.join("1").to_string_lossy().to_string());
+        setup
+            .args
+            .paths
+            .push(setup.args.paths.last().unwrap().clone());
+        let err = sources_from(&setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::DuplicateSource(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn ignore_comment_lines() -> Result<()> {
+        let setup = &Setup::init("ignore_comment_lines")?;
+        std::fs::File::create(setup.sandbox.join("#1.txt"))?;
+        let sources = vec![
+            setup.source_from("1/1.txt"),
+            Source {
+                text: "#1.txt".to_owned(),
+                ..setup.source_from("#1.txt")
+            },
+        ];
+        let text = text_from(&sources, &setup.args);
+        assert!(text.ends_with("\n\\#1.txt"));
+        let text = format!("# Comment\n  # Indented comment\n\n{}\n", text);
+        assert_eq!(lines_from(&text), vec![sources[0].text.as_str(), "#1.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_errors_into_text() {
+        let text = "# Comment\n1/1.txt\n1/a.txt\n";
+        let errors = [
+            (
+                None,
+                "Number of lines 2 does not match\nthe original one 3".to_owned(),
+            ),
+            (
+                Some(1),
+                format!("Destination exists. {}", "1/a.txt".yellow()),
+            ),
+        ];
+        let annotated = text_with_errors(text, &errors);
+        assert_eq!(
+            annotated,
+            "# ERROR: Number of lines 2 does not match\n\
+             # ERROR  the original one 3\n\
+             # ERROR line 6: Destination exists. 1/a.txt\n\
+             # Comment\n1/1.txt\n1/a.txt\n"
+        );
+        assert_eq!(annotated.lines().nth(5), Some("1/a.txt"));
+        assert_eq!(lines_from(&annotated), lines_from(text));
+        assert_eq!(strip_errors(&annotated), text);
+        assert_eq!(text_with_errors(&annotated, &errors), annotated);
+    }
+
+    #[test]
+    fn should_reject_hard_linked_source() -> Result<()> {
+        let setup = &Setup::init("should_reject_hard_linked_source")?;
+        let original = &setup.sandbox.join("1/1.txt");
+        let link = &setup.sandbox.join("1/linked-1.txt");
+        std::fs::hard_link(original, link)?;
+        let mut sources = Vec::new();
+        put_source(&mut sources, original, &setup.args)?;
+        assert!(matches!(
+            put_source(&mut sources, link, &setup.args),
+            Err(MooveError::AliasedSource { path, other }) if &path == link && &other == original
+        ));
+        assert_eq!(sources.len(), 1);
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn should_reject_source_through_linked_dir() -> Result<()> {
+        let setup = &Setup::init("should_reject_source_through_linked_dir")?;
+        std::os::unix::fs::symlink("1", setup.sandbox.join("3"))?;
+        let original = &setup.sandbox.join("1/1.txt");
+        let aliased = &setup.sandbox.join("3/1.txt");
+        let mut sources = Vec::new();
+        put_source(&mut sources, original, &setup.args)?;
+        let err = put_source(&mut sources, aliased, &setup.args).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains(&*original.to_string_lossy()));
+        assert!(message.contains(&*aliased.to_string_lossy()));
+        assert_eq!(sources.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn annotate_lines() -> Result<()> {
+        let mut setup = Setup::init("annotate_lines")?;
+        setup.args.annotate = true;
+        std::fs::write(setup.sandbox.join("1/1.txt"), [0; 2048])?;
+        let sources = vec![setup.source_from("1/1.txt"), setup.source_from("1/11")];
+        let text = text_from(&sources, &setup.args);
+        let lines = text.split('\n').collect::<Vec<_>>();
+        assert!(lines[0].contains("\t#   2.0 KiB "));
+        assert!(lines[1].contains("\t#         - "));
+        assert_eq!(lines[0].find('\t'), lines[1].find('\t'));
+        assert_eq!(
+            lines_from(&text),
+            vec![sources[0].text.as_str(), sources[1].text.as_str()]
+        );
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(4404019), "4.2 MiB");
+        Ok(())
+    }
+
+    #[test]
+    fn number_lines() -> Result<()> {
+        let mut setup = Setup::init("number_lines")?;
+        setup.args.number = Some("{n:02} - {name}".to_owned());
+        let sources = vec![
+            setup.source_from("1/1.txt"),
+            setup.source_from("1/11"),
+            setup.source_from("1/11/11.txt"),
+        ];
+        let numbered = |args: &CommandLine| {
+            text_from(&sources, args)
+                .split('\n')
+                .map(|line| line.to_owned())
+                .collect::<Vec<_>>()
+        };
+        let sep = std::path::MAIN_SEPARATOR;
+        let expected = [
+            setup.sandbox.join("1/01 - 1.txt"),
+            setup.sandbox.join(format!("1/02 - 11{}", sep)),
+            setup.sandbox.join("1/11/03 - 11.txt"),
+        ];
+        assert_eq!(
+            numbered(&setup.args),
+            expected
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        );
+        setup.args.number_files_only = true;
+        setup.args.number = Some("{stem}-{n:3}.{ext}".to_owned());
+        assert_eq!(
+            numbered(&setup.args)[1..],
+            [
+                format!("{}{}", sources[1].text, sep),
+                setup
+                    .sandbox
+                    .join("1/11/11-  2.txt")
+                    .to_string_lossy()
+                    .to_string()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_lines() -> Result<()> {
+        let mut setup = Setup::init("sequence_lines")?;
+        setup.args.sequence = true;
+        setup.args.counter_start = 1;
+        setup.args.counter_step = 1;
+        setup.args.counter_pad = 2;
+        let sources = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|dir| {
+                let dir = setup.sandbox.join("3").join(dir);
+                std::fs::create_dir_all(&dir)?;
+                std::fs::write(dir.join("file.txt"), "")?;
+                Ok(setup.source_from(&format!(
+                    "3/{}/file.txt",
+                    dir.file_name().unwrap().to_string_lossy()
+                )))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let names = |args: &CommandLine| {
+            text_from(&sources, args)
+                .split('\n')
+                .map(|line| {
+                    Path::new(line)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&setup.args),
+            [
+                "file-01.txt",
+                "file-02.txt",
+                "file-03.txt",
+                "file-04.txt",
+                "file-05.txt"
+            ]
+        );
+        setup.args.sequence_per_dir = true;
+        assert!(names(&setup.args).iter().all(|name| name == "file-01.txt"));
+        assert_eq!(
+            apply_sequence(&setup.source_from("1/11"), 10, 3),
+            format!("{}-010", setup.source_from("1/11").text)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn flatten_sources() -> Result<()> {
+        let mut setup = Setup::init("flatten_sources")?;
+        std::fs::write(setup.sandbox.join("2/21/1.txt"), "")?;
+        let flat = setup.sandbox.join("flat");
+        setup.args.recursive = true;
+        setup.args.flatten = Some(flat.to_owned());
+        setup
+            .args
+            .paths
+            .push(setup.sandbox.to_string_lossy().to_string());
         let sources = sources_from(&setup.args)?;
-        assert_eq!(sources[0].path, setup.sandbox.join("1/1.txt"));
-        assert_eq!(sources[1].path, setup.sandbox.join("1/11"));
-        assert_eq!(sources[2].path, setup.sandbox.join("1/12"));
-        setup.args.paths.clear();
+        let err = operations_from(&sources, &setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::DuplicateDestination(_))
+        ));
+        setup.args.conflict = Conflict::Skip;
+        assert_eq!(
+            operations_from(&sources, &setup.args)?.len(),
+            sources.len() - 1
+        );
+        setup.args.conflict = Conflict::Suffix;
+        let operations = operations_from(&sources, &setup.args)?;
+        assert_eq!(operations.len(), sources.len());
+        for o in operations.iter() {
+            execute_operation(o, &setup.args)?;
+        }
+        let mut names = std::fs::read_dir(&flat)?
+            .map(|entry| {
+                let path = entry?.path();
+                assert!(path.is_file());
+                Ok(path.file_name().unwrap().to_string_lossy().to_string())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "1 (1).txt",
+                "1.txt",
+                "11.txt",
+                "12.txt",
+                "2.txt",
+                "21.txt",
+                "211.txt",
+                "22.txt"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn organize_sources() -> Result<()> {
+        let mut setup = Setup::init("organize_sources")?;
+        let dir = setup.sandbox.join("1");
+        for name in ["a.jpg", "b.JPG", "README"] {
+            std::fs::write(dir.join(name), "")?;
+        }
+        setup.args.organize = true;
+        setup.args.dry_run = true;
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        let sources = sources_from(&setup.args)?;
+        let operations = operations_from(&sources, &setup.args)?;
+        let mut dsts: Vec<PathBuf> = operations.iter().map(|o| o.dst.path.to_owned()).collect();
+        dsts.sort();
+        assert_eq!(
+            dsts,
+            [
+                dir.join("JPG").join("a.jpg"),
+                dir.join("JPG").join("b.JPG"),
+                dir.join("OTHER").join("README"),
+                dir.join("TXT").join("1.txt"),
+            ]
+        );
+        for o in operations.iter() {
+            execute_operation(o, &setup.args)?;
+        }
+        assert!(!dir.join("JPG").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn rename_with_prefix() -> Result<()> {
+        assert_eq!(apply_prefix_ops("IMG_1.jpg", Some("IMG_"), None), "1.jpg");
+        assert_eq!(
+            apply_prefix_ops("1.jpg", Some("IMG_"), Some("x-")),
+            "x-1.jpg"
+        );
+        let mut setup = Setup::init("rename_with_prefix")?;
+        setup.args.directory = true;
         setup.args.paths.push(
             setup
                 .sandbox
-                .join("..")
-                .join(&setup.sandbox)
                 .join("1")
+                .join("1*")
                 .to_string_lossy()
                 .to_string(),
         );
         let sources = sources_from(&setup.args)?;
-        assert_eq!(sources[0].path, setup.sandbox.join("1/1.txt"));
-        assert_eq!(sources[1].path, setup.sandbox.join("1/11"));
-        assert_eq!(sources[2].path, setup.sandbox.join("1/12"));
+        setup.args.strip_prefix = Some("2".to_owned());
+        let err = operations_from(&sources, &setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::MissingPrefix(_))
+        ));
+        setup.args.ignore_missing_prefix = true;
+        setup.args.add_prefix = Some("1".to_owned());
+        for o in operations_from(&sources, &setup.args)? {
+            execute_operation(&o, &setup.args)?;
+        }
+        for name in ["11.txt", "111", "112"] {
+            assert!(setup.sandbox.join("1").join(name).exists());
+        }
+        setup.args.strip_prefix = Some("11".to_owned());
+        setup.args.add_prefix = None;
+        setup.args.paths = vec![setup
+            .sandbox
+            .join("1")
+            .join("111")
+            .to_string_lossy()
+            .to_string()];
+        let sources = sources_from(&setup.args)?;
+        let operations = operations_from(&sources, &setup.args)?;
+        assert_eq!(operations[0].dst.path, setup.sandbox.join("1").join("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn rename_with_suffix() -> Result<()> {
+        assert_eq!(apply_suffix_ops("report", None, Some("-v2")), "report-v2");
+        assert_eq!(
+            apply_suffix_ops("report-v1", Some("-v1"), Some("-v2")),
+            "report-v2"
+        );
+        assert_eq!(apply_suffix_ops("report", Some("-v1"), None), "report");
+        let mut setup = Setup::init("rename_with_suffix")?;
+        let dir = setup.sandbox.join("1");
+        std::fs::write(dir.join("IMG_a.tar.gz"), "")?;
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        setup.args.strip_prefix = Some("IMG_".to_owned());
+        setup.args.add_suffix = Some("-v2".to_owned());
+        let sources = sources_from(&setup.args)?;
+        for o in operations_from(&sources, &setup.args)? {
+            execute_operation(&o, &setup.args)?;
+        }
+        assert!(dir.join("a.tar-v2.gz").is_file());
+        assert!(dir.join("1-v2.txt").is_file());
+        assert!(dir.join("11-v2").is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn convert_names_case() -> Result<()> {
+        assert_eq!(convert_case("Straße.TXT", false), "straße.txt");
+        assert_eq!(convert_case("Straße.txt", true), "STRASSE.TXT");
+        let mut setup = Setup::init("convert_names_case")?;
+        let dir = setup.sandbox.join("1");
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        setup.args.to_uppercase = true;
+        let sources = sources_from(&setup.args)?;
+        for o in operations_from(&sources, &setup.args)? {
+            execute_operation(&o, &setup.args)?;
+        }
+        let names = |dir: &Path| -> Result<Vec<String>> {
+            std::fs::read_dir(dir)?
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                .collect()
+        };
+        let mut upper = names(&dir)?;
+        upper.sort();
+        assert_eq!(upper, ["1.TXT", "11", "12"]);
+        setup.args.to_uppercase = false;
+        setup.args.to_lowercase = true;
+        let sources = sources_from(&setup.args)?;
+        for o in operations_from(&sources, &setup.args)? {
+            execute_operation(&o, &setup.args)?;
+        }
+        assert!(names(&dir)?.iter().all(|name| *name == name.to_lowercase()));
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_dates() -> Result<()> {
+        use chrono::TimeZone;
+        let mut setup = Setup::init("prefix_dates")?;
+        let time = chrono::Local
+            .with_ymd_and_hms(2024, 1, 2, 12, 0, 0)
+            .unwrap()
+            .timestamp();
+        let file = setup.sandbox.join("1").join("1.txt");
+        filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(time, 0))?;
+        setup.args.paths.push(file.to_string_lossy().to_string());
+        setup.args.date_prefix = Some("%Y%m%d".to_owned());
+        let sources = sources_from(&setup.args)?;
+        let operations = operations_from(&sources, &setup.args)?;
+        assert_eq!(
+            operations[0].dst.path,
+            setup.sandbox.join("1").join("20240102-1.txt")
+        );
+        setup.args.date_prefix = Some("%Y-%m".to_owned());
+        setup.args.add_prefix = Some("x-".to_owned());
+        let operations = operations_from(&sources, &setup.args)?;
+        assert_eq!(
+            operations[0].dst.path,
+            setup.sandbox.join("1").join("2024-01-x-1.txt")
+        );
+        setup.args.date_prefix = Some("%Q".to_owned());
+        assert!(operations_from(&sources, &setup.args).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sanitize_names() -> Result<()> {
+        assert_eq!(sanitize_filename("a:b?c\0d.txt", '_'), "a_b_c_d.txt");
+        assert_eq!(sanitize_filename("<a|b>\t*.txt", '-'), "-a-b---.txt");
+        assert_eq!(sanitize_filename("safe name.txt", '_'), "safe name.txt");
+        let mut setup = Setup::init("sanitize_names")?;
+        let dir = setup.sandbox.join("1");
+        setup.args.paths.push(dir.to_string_lossy().to_string());
+        setup.args.add_prefix = Some("a:b?".to_owned());
+        setup.args.sanitize = true;
+        setup.args.sanitize_char = '_';
+        let sources = sources_from(&setup.args)?;
+        let operations = operations_from(&sources, &setup.args)?;
+        assert_eq!(operations[0].dst.path, dir.join("a_b_1.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_names() -> Result<()> {
+        assert_eq!(truncate_filename("abcdef.txt", 8)?, "abcd.txt");
+        assert_eq!(truncate_filename("abc.txt", 8)?, "abc.txt");
+        assert_eq!(truncate_filename("abcdef", 4)?, "abcd");
+        // 3 bytes for each character.
+        assert_eq!(truncate_filename("日本語.txt", 10)?, "日本.txt");
+        assert_eq!(truncate_filename("日本語.txt", 9)?, "日.txt");
+        let err = truncate_filename("a.longextension", 5).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::TooLongExtension { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn pair_two_column_lines() -> Result<()> {
+        let mut setup = Setup::init("pair_two_column_lines")?;
+        setup.args.format = Format::TwoColumn;
+        std::fs::File::create(setup.sandbox.join("1/tab\t%.txt"))?;
+        let sources = vec![
+            setup.source_from("1/1.txt"),
+            setup.source_from("2/2.txt"),
+            setup.source_from("1/tab\t%.txt"),
+        ];
+        let text = text_from(&sources, &setup.args);
+        let lines = lines_from(&text);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].ends_with("tab%09%25.txt"));
+        let pairs = pairs_from(&sources, &lines)?;
+        assert_eq!(pairs[2].1, sources[2].text);
+        let edited = format!(
+            "{}\tmoved-2.txt\n{}\tmoved-tab%09%25.txt",
+            sources[1].text,
+            escape_column(&sources[2].text)
+        );
+        let pairs = pairs_from(&sources, &lines_from(&edited))?;
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].0.path, sources[1].path);
+        assert_eq!(pairs[0].1, "moved-2.txt");
+        assert_eq!(pairs[1].0.path, sources[2].path);
+        assert_eq!(pairs[1].1, "moved-tab\t%.txt");
+        let unknown = format!("{}\tmoved-3.txt", setup.sandbox.join("3.txt").display());
+        assert!(pairs_from(&sources, &lines_from(&unknown)).is_err());
+        let duplicated = format!("{0}\ta.txt\n{0}\tb.txt", sources[0].text);
+        assert!(pairs_from(&sources, &lines_from(&duplicated)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn allow_removal_of_lines() -> Result<()> {
+        let mut setup = Setup::init("allow_removal_of_lines")?;
+        setup.args.allow_removal = true;
+        let sources = vec![setup.source_from("1/1.txt"), setup.source_from("2/2.txt")];
+        let text = text_from(&sources, &setup.args);
+        let lines = lines_from(&text);
+        assert!(lines[1].starts_with(&format!("{}\t", sources[1].text)));
+        let pairs = pairs_from(&sources, &lines[1..])?;
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].0.path, sources[1].path);
+        let added = format!("{}\t{}", setup.sandbox.join("3.txt").display(), "4.txt");
+        assert!(pairs_from(&sources, &[lines[0], lines[1], &added]).is_err());
+        let report = Report {
+            files: 1,
+            skipped: 1,
+            ..Report::default()
+        };
+        assert!(report
+            .to_string()
+            .ends_with(", skipped 1 source of deleted lines"));
+        let report = Report {
+            skipped_operations: vec![setup.operation_from("1/1.txt", "2/2.txt")],
+            ..report
+        };
+        assert!(report
+            .to_string()
+            .ends_with(", skipped 1 conflicting operation"));
+        Ok(())
+    }
+
+    #[test]
+    fn operate_normally() -> Result<()> {
+        let setup = &Setup::init("operate_normally")?;
+        let mut operations = Vec::new();
+        let new_operation = setup.operation_from("1/11/11.txt", "1/12/moved-11.txt");
+        is_operational(&operations, &new_operation)?;
+        operations.push(new_operation);
+        let new_operation = setup.operation_from("1/12/12.txt", "1/11/moved-12.txt");
+        is_operational(&operations, &new_operation)?;
+        operations.push(new_operation);
+        let new_operation = setup.operation_from("1/1.txt", "1/11/moved-1.txt");
+        is_operational(&operations, &new_operation)?;
+        operations.push(new_operation);
+        let new_operation = setup.operation_from("2/21/211", "moved-211");
+        is_operational(&operations, &new_operation)?;
+        operations.push(new_operation);
+        let new_operation = setup.operation_from("2/22", "moved-211/moved-22");
+        is_operational(&operations, &new_operation)?;
+        operations.push(new_operation);
+        for o in operations.iter() {
+            execute_operation(o, &setup.args)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_destinations_differing_in_case() -> Result<()> {
+        let setup = &Setup::init("reject_destinations_differing_in_case")?;
+        let first = setup.operation_from("1/1.txt", "2/Readme.md");
+        let second = setup.operation_from("1/11/11.txt", "2/README.md");
+        let case = NameRules {
+            fold_case: true,
+            ..NameRules::default()
+        };
+        assert!(matches!(
+            is_operational_with(&[first.to_owned()], &second, case),
+            Err(MooveError::EquivalentDestination { dst, other }) if dst == second.dst.path && other == first.dst.path
+        ));
+        is_operational_with(&[first], &second, NameRules::default())?;
+        let onto_existing = setup.operation_from("1/1.txt", "2/2.TXT");
+        assert!(matches!(
+            is_operational_with(&[], &onto_existing, case),
+            Err(MooveError::DestinationExists(_))
+        ));
+        is_operational_with(&[], &onto_existing, NameRules::default())?;
+        // Moving away the existing one of the other case makes room.
+        let vacating = setup.operation_from("2/2.txt", "2/moved-2.txt");
+        is_operational_with(&[vacating], &onto_existing, case)?;
+        // Changing only the case of the source itself is not a collision.
+        is_operational_with(&[], &setup.operation_from("2/2.txt", "2/2.TXT"), case)?;
+        let args = CommandLine {
+            case_sensitive: true,
+            ..CommandLine::default()
+        };
+        assert!(!args.name_rules().fold_case);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_names_invalid_on_windows() -> Result<()> {
+        let setup = &Setup::init("reject_names_invalid_on_windows")?;
+        for (name, error) in [
+            ("valid name.txt", None),
+            (".hidden", None),
+            ("a.b.c", None),
+            ("caf\u{e9} 100%.txt", None),
+            ("a<b.txt", Some("character '<' at 2")),
+            ("a>b.txt", Some("character '>' at 2")),
+            ("c:d.txt", Some("character ':' at 2")),
+            ("say \"hi\".txt", Some("character '\"' at 5")),
+            ("a|b", Some("character '|' at 2")),
+            ("what?", Some("character '?' at 5")),
+            ("*.txt", Some("character '*' at 1")),
+            ("tab\there", Some("control character U+0009 at 4")),
+            ("name.", Some("trailing dot")),
+            ("name ", Some("trailing space")),
+            ("CON", Some("reserved name CON")),
+            ("con.txt", Some("reserved name CON")),
+            ("Nul.tar.gz", Some("reserved name NUL")),
+            ("aux ", Some("reserved name AUX")),
+            ("prn.", Some("reserved name PRN")),
+            ("com1.log", Some("reserved name COM1")),
+            ("Lpt9", Some("reserved name LPT9")),
+            ("com0", None),
+            ("com10", None),
+            ("lpt", None),
+            ("console.txt", None),
+            ("my-con.txt", None),
+        ] {
+            assert_eq!(windows_name_error(name).as_deref(), error, "{}", name);
+        }
+        let windows = NameRules {
+            windows: true,
+            ..NameRules::default()
+        };
+        let o = setup.operation_from("1/1.txt", "3/bad:dir/a.txt");
+        assert!(matches!(
+            is_operational_with(&[], &o, windows),
+            Err(MooveError::InvalidWindowsName { name, .. }) if name == "bad:dir"
+        ));
+        is_operational_with(&[], &o, NameRules::default())?;
+        let o = setup.operation_from("1/1.txt", "2/Com1.txt");
+        let err = is_operational_with(&[], &o, windows)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("COM1"));
+        assert!(err.contains(&*o.dst.path.to_string_lossy()));
+        let args = CommandLine {
+            windows_safe: true,
+            ..CommandLine::default()
+        };
+        assert!(args.name_rules().windows);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_too_long_names() -> Result<()> {
+        let setup = &Setup::init("reject_too_long_names")?;
+        let rules = NameRules {
+            max_name_length: Some(255),
+            max_path_length: Some(4096),
+            ..NameRules::default()
+        };
+        // `é` is 2 bytes in UTF-8 but 1 unit in UTF-16.
+        let (near, over) = if cfg!(target_family = "windows") {
+            ("\u{e9}".repeat(255), "\u{e9}".repeat(256))
+        } else {
+            ("\u{e9}".repeat(127) + "a", "\u{e9}".repeat(128))
+        };
+        assert_eq!(name_length(near.as_ref()), 255);
+        assert_eq!(name_length(over.as_ref()), 256);
+        is_operational_with(&[], &setup.operation_from("1/1.txt", &near), rules)?;
+        let o = setup.operation_from("1/1.txt", &format!("{}/1.txt", over));
+        assert!(matches!(
+            is_operational_with(&[], &o, rules),
+            Err(MooveError::TooLongName {
+                length: 256,
+                max: 255,
+                ..
+            })
+        ));
+        let deep = vec!["a".repeat(200); 25].join("/");
+        assert!(matches!(
+            is_operational_with(&[], &setup.operation_from("1/1.txt", &deep), rules),
+            Err(MooveError::TooLongPath { max: 4096, .. })
+        ));
+        is_operational_with(&[], &o, NameRules::default())?;
+        let args = CommandLine {
+            max_name_length: Some(8),
+            ..CommandLine::default()
+        };
+        assert_eq!(args.name_rules().max_name_length, Some(8));
+        let err = is_operational_with(
+            &[],
+            &setup.operation_from("1/1.txt", "1/123456789"),
+            args.name_rules(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains('9'));
+        Ok(())
+    }
+
+    #[test]
+    fn compare_destinations_in_unicode_normalization() -> Result<()> {
+        let setup = &Setup::init("compare_destinations_in_unicode_normalization")?;
+        let (nfc, nfd) = ("caf\u{e9}.txt", "cafe\u{301}.txt");
+        std::fs::write(setup.sandbox.join("1").join(nfd), "")?;
+        let unicode = NameRules {
+            fold_unicode: true,
+            ..NameRules::default()
+        };
+        // The name typed in the other normal form is the source itself.
+        let src = setup.source_from(&format!("1/{}", nfd));
+        let typed = setup.sandbox.join("1").join(nfc);
+        assert!(is_unchanged(&src, &typed, unicode));
+        assert!(!is_unchanged(&src, &typed, NameRules::default()));
+        let upper = setup.sandbox.join("1").join("CAFE\u{301}.txt");
+        let both = NameRules {
+            fold_case: true,
+            fold_unicode: true,
+            ..NameRules::default()
+        };
+        assert!(!is_unchanged(&src, &upper, both));
+        // Destinations in both normal forms collide, also with the existing file.
+        let first = setup.operation_from("1/1.txt", &format!("2/{}", nfc));
+        let second = setup.operation_from("1/11/11.txt", &format!("2/{}", nfd));
+        assert!(matches!(
+            is_operational_with(&[first.to_owned()], &second, unicode),
+            Err(MooveError::EquivalentDestination { .. })
+        ));
+        is_operational_with(&[first], &second, NameRules::default())?;
+        let onto_existing = setup.operation_from("2/2.txt", &format!("1/{}", nfc));
+        assert!(matches!(
+            is_operational_with(&[], &onto_existing, unicode),
+            Err(MooveError::DestinationExists(_))
+        ));
+        // The destination is written as typed.
+        assert_eq!(onto_existing.dst.path.file_name().unwrap(), nfc);
+        Ok(())
+    }
+
+    #[test]
+    fn prompt_to_redo() -> Result<()> {
+        let args = CommandLine::default();
+        assert!(!prompt_redo_from(&args, &mut "what\nabort\n".as_bytes())?);
+        assert!(!prompt_redo_from(&args, &mut "A\n".as_bytes())?);
+        assert!(prompt_redo_from(&args, &mut "\n".as_bytes())?);
+        assert!(prompt_redo_from(&args, &mut "e\n".as_bytes())?);
+        // Not to edit again and again without input.
+        assert!(!prompt_redo_from(&args, &mut "".as_bytes())?);
+        for (input, resolution) in [
+            ("skip\n", Resolution::Skip),
+            ("x\nC\n", Resolution::Continue),
+            ("\n", Resolution::Edit),
+            ("a\n", Resolution::Abort),
+            ("", Resolution::Abort),
+        ] {
+            assert_eq!(
+                prompt_resolution_from(&args, &mut input.as_bytes())?,
+                resolution
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_collision() -> Result<()> {
+        let setup = Setup::init("resolve_collision")?;
+        let args = CommandLine::default();
+        assert_eq!(
+            prompt_collision_from(&args, true, &mut "o\n".as_bytes())?,
+            Resolution::Overwrite
+        );
+        assert_eq!(
+            prompt_collision_from(&args, false, &mut "o\nr\n".as_bytes())?,
+            Resolution::Rename
+        );
+        std::fs::write(setup.sandbox.join("1").join("1 (1).txt"), "")?;
+        let operations = [setup.operation_from("2/2.txt", "1/1 (2).txt")];
+        let dst = free_destination(&setup.destination_from("1/1.txt"), &operations)?;
+        assert_eq!(dst.path, setup.sandbox.join("1").join("1 (3).txt"));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn deny_read_only_directory() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let setup = Setup::init("deny_read_only_directory")?;
+        let dir = setup.sandbox.join("2").join("22");
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555))?;
+        let result = is_operational(&[], &setup.operation_from("1/1.txt", "2/22/new/1.txt"));
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))?;
+        // SAFETY: No precondition.
+        if unsafe { libc::geteuid() } == 0 {
+            // Permissions are not enforced for root.
+            assert!(result.is_ok());
+        } else {
+            assert!(matches!(
+                result.unwrap_err(),
+                MooveError::PermissionDenied(path) if path == dir
+            ));
+        }
+        assert!(is_operational(&[], &setup.operation_from("1/1.txt", "2/22/new/1.txt")).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_copied_file() -> Result<()> {
+        let mut setup = Setup::init("verify_copied_file")?;
+        setup.args.verify = true;
+        std::fs::write(setup.sandbox.join("1").join("1.txt"), "content")?;
+        let mut operation = setup.operation_from("1/1.txt", "2/copied-1.txt");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        verify_operation(&operation)?;
+        // Same size but different content.
+        std::fs::write(&operation.dst.path, "CONTENT")?;
+        assert!(matches!(
+            verify_operation(&operation)
+                .unwrap_err()
+                .downcast::<MooveError>()?,
+            MooveError::VerificationFailed(_)
+        ));
+        let operation = setup.operation_from("1/11/11.txt", "2/moved-11.txt");
+        execute_operation(&operation, &setup.args)?;
+        std::fs::remove_file(&operation.dst.path)?;
+        assert!(matches!(
+            verify_operation(&operation)
+                .unwrap_err()
+                .downcast::<MooveError>()?,
+            MooveError::VerificationFailed(_)
+        ));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn rename_on_same_filesystem() -> Result<()> {
+        let mut setup = Setup::init("rename_on_same_filesystem")?;
+        let inode_of = |path: &str| setup.sandbox.join(path).file_id();
+        let operation = setup.operation_from("1/1.txt", "2/moved-1.txt");
+        assert!(!operation.crosses_filesystem());
+        let inode = inode_of("1/1.txt");
+        execute_operation(&operation, &setup.args)?;
+        assert_eq!(inode_of("2/moved-1.txt"), inode);
+        let inode = inode_of("2/2.txt");
+        setup.args.force_copy = true;
+        execute_operation(&setup.operation_from("2/2.txt", "1/2.txt"), &setup.args)?;
+        assert!(!setup.sandbox.join("2/2.txt").exists());
+        assert_ne!(inode_of("1/2.txt"), inode);
         Ok(())
     }
 
     #[test]
-    fn should_fail_to_list_sources() -> Result<()> {
-        let mut setup = Setup::init("should_fail_to_list_sources")?;
-        setup.args.paths.push(
-            setup
-                .sandbox
-                .join("does not exist")
-                .to_string_lossy()
-                .to_string(),
-        );
-        assert!(sources_from(&setup.args).is_err());
-        setup.args.paths.clear();
-        setup.args.paths.push("/".to_owned());
-        assert!(sources_from(&setup.args).is_err());
-        setup.args.paths.clear();
-        setup
-            .args
-            .paths
-            .push(setup.sandbox.join("1").to_string_lossy().to_string());
-        setup
-            .args
-            .paths
-            .push(setup.args.paths.last().unwrap().clone());
-        assert!(sources_from(&setup.args).is_err());
+    fn should_not_create_missing_parent() -> Result<()> {
+        let mut setup = Setup::init("should_not_create_missing_parent")?;
+        setup.args.no_create_dir = true;
+        let operation = setup.operation_from("1/1.txt", "3/31/1.txt");
+        let err = execute_operation(&operation, &setup.args).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MooveError>(),
+            Some(MooveError::MissingParent(path)) if path == &setup.sandbox.join("3/31")
+        ));
+        assert!(!setup.sandbox.join("3").exists());
+        assert!(setup.sandbox.join("1/1.txt").is_file());
+        execute_operation(&setup.operation_from("1/1.txt", "2/1.txt"), &setup.args)?;
+        assert!(setup.sandbox.join("2/1.txt").is_file());
         Ok(())
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     #[test]
-    fn operate_normally() -> Result<()> {
-        let setup = &Setup::init("operate_normally")?;
-        let mut operations = Vec::new();
-        let new_operation = setup.operation_from("1/11/11.txt", "1/12/moved-11.txt");
-        is_operational(&operations, &new_operation)?;
-        operations.push(new_operation);
-        let new_operation = setup.operation_from("1/12/12.txt", "1/11/moved-12.txt");
-        is_operational(&operations, &new_operation)?;
-        operations.push(new_operation);
-        let new_operation = setup.operation_from("1/1.txt", "1/11/moved-1.txt");
-        is_operational(&operations, &new_operation)?;
-        operations.push(new_operation);
-        let new_operation = setup.operation_from("2/21/211", "moved-211");
-        is_operational(&operations, &new_operation)?;
-        operations.push(new_operation);
-        let new_operation = setup.operation_from("2/22", "moved-211/moved-22");
-        is_operational(&operations, &new_operation)?;
-        operations.push(new_operation);
-        for o in operations.iter() {
-            execute_operation(o, &setup.args)?;
+    fn preserve_xattrs() -> Result<()> {
+        let mut setup = Setup::init("preserve_xattrs")?;
+        setup.args.xattrs = true;
+        let src = setup.sandbox.join("1").join("1.txt");
+        // Some filesystems for temporary files do not support user attributes.
+        if xattr::set(&src, "user.moove", b"value").is_err() {
+            return Ok(());
         }
+        let mut operation = setup.operation_from("1/1.txt", "2/copied-1.txt");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        assert_eq!(
+            xattr::get(&operation.dst.path, "user.moove")?,
+            Some(b"value".to_vec())
+        );
+        std::fs::create_dir_all(setup.sandbox.join("1/12/121"))?;
+        xattr::set(setup.sandbox.join("1/12/12.txt"), "user.moove", b"12")?;
+        let mut operation = setup.operation_from("1/12", "2/copied-12");
+        operation.kind = OperationKind::Copy;
+        execute_operation(&operation, &setup.args)?;
+        assert_eq!(
+            xattr::get(setup.sandbox.join("2/copied-12/12.txt"), "user.moove")?,
+            Some(b"12".to_vec())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_from_lines_without_editor() -> Result<()> {
+        let setup = Setup::init("plan_from_lines_without_editor")?;
+        let sources = ["1/1.txt", "1/11/11.txt", "2/2.txt"].map(|s| setup.source_from(s));
+        let line_of = |s: &str| setup.sandbox.join(s).to_string_lossy().to_string();
+        let lines = [
+            line_of("3/a.txt"),
+            line_of("3/b.txt"),
+            sources[2].text.to_owned(),
+        ];
+        let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        let plan = plan_from_lines(&sources, &lines, &setup.args)?;
+        assert_eq!(plan.operations.len(), 2);
+        assert!(plan.text.is_none());
+        let err = plan_from_lines(&sources, &lines[..2], &setup.args).unwrap_err();
+        let InvalidLines(errors) = err.downcast::<InvalidLines>()?;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, None);
+        assert!(matches!(
+            errors[0].error.downcast_ref::<MooveError>(),
+            Some(MooveError::LineMismatch {
+                expected: 3,
+                got: 2
+            })
+        ));
+        let lines = [
+            line_of("3/a.txt"),
+            line_of("3/a.txt"),
+            line_of("2/22/22.txt"),
+        ];
+        let lines: Vec<&str> = lines.iter().map(|l| l.as_str()).collect();
+        let err = plan_from_lines(&sources, &lines, &setup.args).unwrap_err();
+        let InvalidLines(errors) = err.downcast::<InvalidLines>()?;
+        let lines: Vec<Option<usize>> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![Some(1), Some(2)]);
+        assert!(matches!(
+            errors[1].error.downcast_ref::<MooveError>(),
+            Some(MooveError::DestinationExists(_))
+        ));
+        assert!(setup.sandbox.join("1").join("1.txt").is_file());
         Ok(())
     }
 
@@ -843,16 +7204,209 @@ mod lib {
             setup.operation_from("2/21/211", "moved-211"),
             setup.operation_from("2/22", "moved-211/moved-22"),
         ];
-        [
+        let error_of = |src: &str, dst: &str| {
+            is_operational(&operations, &setup.operation_from(src, dst)).unwrap_err()
+        };
+        for (src, dst) in [
             ("1/11/11.txt", "1/11/11.txt"),
-            ("1/11/11.txt", "1/12/12.txt"),
-            ("1/11", "2/21/211"),
-            ("1/11", "moved-211"),
-        ]
-        .iter()
-        .for_each(|(src, dst)| {
-            assert!(is_operational(&operations, &setup.operation_from(src, dst)).is_err());
-        });
+            ("1/11/11.txt", "2/2.txt"),
+            ("1/11", "2/21"),
+        ] {
+            assert!(matches!(
+                error_of(src, dst),
+                MooveError::DestinationExists(_)
+            ));
+        }
+        assert!(matches!(
+            error_of("1/11", "moved-211"),
+            MooveError::DuplicateDestination(_)
+        ));
+        assert!(matches!(
+            error_of("2/22", "1/12"),
+            MooveError::NestedDestination(_)
+        ));
+        assert!(matches!(
+            error_of("1/11", "1/11/sub"),
+            MooveError::DestinationInsideSource { .. }
+        ));
+        assert!(matches!(
+            error_of("1/1.txt", "1/11/11.txt/1.txt"),
+            MooveError::FileAncestor(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_be_operational_inside_vacated_dir() -> Result<()> {
+        let setup = &Setup::init("should_not_be_operational_inside_vacated_dir")?;
+        let dir_moved = || setup.operation_from("1/11", "moved-11");
+        let file_moved = || setup.operation_from("1/1.txt", "1/11/sub/1.txt");
+        for (first, second) in [(dir_moved(), file_moved()), (file_moved(), dir_moved())] {
+            let err = is_operational(&[first], &second).unwrap_err();
+            match &err {
+                MooveError::VacatedAncestor { dst, src } => {
+                    assert_eq!(dst, &file_moved().dst.path);
+                    assert_eq!(src, &dir_moved().src.path);
+                }
+                _ => panic!("Unexpected error. {:?}", err),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_be_operational_with_other_spellings() -> Result<()> {
+        let setup = &Setup::init("should_not_be_operational_with_other_spellings")?;
+        let operations = [setup.operation_from("1/1.txt", "1/moved.txt")];
+        for dst in ["1/./moved.txt", "1/11/../moved.txt", "2/../1/moved.txt"] {
+            let err =
+                is_operational(&operations, &setup.operation_from("2/2.txt", dst)).unwrap_err();
+            assert!(matches!(err, MooveError::DuplicateDestination(_)));
+        }
+        let operations = [setup.operation_from("1/1.txt", "1/sub/moved.txt")];
+        let err = is_operational(&operations, &setup.operation_from("2/2.txt", "1/11/../sub"))
+            .unwrap_err();
+        assert!(matches!(err, MooveError::NestedDestination(_)));
+        let err =
+            is_operational(&[], &setup.operation_from("1/11", "1/./11/../11/sub")).unwrap_err();
+        assert!(matches!(err, MooveError::DestinationInsideSource { .. }));
+        let current_dir = std::env::current_dir()?;
+        assert_eq!(
+            resolve_destination(Path::new("./a/../b.txt"), &current_dir),
+            resolve_destination(Path::new("b.txt"), &current_dir)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_leading_parent_dirs() -> Result<()> {
+        let setup = &Setup::init("resolve_leading_parent_dirs")?;
+        let sandbox = setup.sandbox.canonicalize()?;
+        let current_dir = sandbox.join("1").join("11");
+        for (dst, resolved) in [
+            ("../../foo", sandbox.join("foo")),
+            ("./bar/../baz", current_dir.join("baz")),
+            ("../..", sandbox.to_owned()),
+            ("../../../resolve_leading_parent_dirs/2", sandbox.join("2")),
+        ] {
+            assert_eq!(resolve_destination(Path::new(dst), &current_dir), resolved);
+        }
+        let root = current_dir.ancestors().last().unwrap();
+        assert_eq!(
+            resolve_destination(Path::new("../../foo"), root),
+            root.join("foo")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_move_onto_unmoved_source() -> Result<()> {
+        let setup = &Setup::init("should_not_move_onto_unmoved_source")?;
+        let sources = vec![
+            setup.source_from("1/1.txt"),
+            setup.source_from("1/11/11.txt"),
+        ];
+        let moved = setup.operation_from("1/11/11.txt", "1/11/moved-11.txt");
+        let onto_listed = setup.operation_from("1/1.txt", "1/11/11.txt");
+        let err = is_unmoved_source(&sources, &[], &onto_listed).unwrap_err();
+        assert!(matches!(err, MooveError::UnmovedSource(_)));
+        is_unmoved_source(&sources, &[moved], &onto_listed)?;
+        let onto_unlisted = setup.operation_from("1/1.txt", "1/12/12.txt");
+        is_unmoved_source(&sources, &[], &onto_unlisted)?;
+        let err = is_operational(&[], &onto_unlisted).unwrap_err();
+        assert!(matches!(err, MooveError::DestinationExists(_)));
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn should_not_be_operational_onto_symlink() -> Result<()> {
+        let setup = &Setup::init("should_not_be_operational_onto_symlink")?;
+        for (link, target) in [("2/broken", "nowhere"), ("2/linked", "2.txt")] {
+            let link = setup.sandbox.join(link);
+            if link.symlink_metadata().is_err() {
+                std::os::unix::fs::symlink(target, link)?;
+            }
+        }
+        for dst in ["2/broken", "2/linked"] {
+            let err = is_operational(&[], &setup.operation_from("1/1.txt", dst)).unwrap_err();
+            assert!(matches!(err, MooveError::DestinationLinkExists(_)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_operation() -> Result<()> {
+        let setup = &Setup::init("serialize_operation")?;
+        let operation = setup.operation_from("1/1.txt", "1/moved-1.txt");
+        let json = serde_json::to_string(&operation)?;
+        let deserialized: Operation = serde_json::from_str(&json)?;
+        assert_eq!(deserialized, operation);
+        assert_eq!(deserialized.src.meta, operation.src.meta);
+        assert!(deserialized.src.meta.is_file);
+        Ok(())
+    }
+
+    #[test]
+    fn reorder_chained_operations() -> Result<()> {
+        let setup = &Setup::init("reorder_chained_operations")?;
+        let mut operations = vec![
+            setup.operation_from("1/1.txt", "2/2.txt"),
+            setup.operation_from("2/2.txt", "2/21/21.txt"),
+            setup.operation_from("2/21/21.txt", "moved-21.txt"),
+        ];
+        reorder_operations(&mut operations)?;
+        let mut validated = Vec::new();
+        for o in operations {
+            is_operational(&validated, &o)?;
+            execute_operation(&o, &setup.args)?;
+            validated.push(o);
+        }
+        assert_eq!(validated[0].dst.path, setup.sandbox.join("moved-21.txt"));
+        assert!(setup.sandbox.join("moved-21.txt").is_file());
+        assert!(setup.sandbox.join("2/21/21.txt").is_file());
+        assert!(setup.sandbox.join("2/2.txt").is_file());
+        assert!(!setup.sandbox.join("1/1.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_reorder_circular_operations() -> Result<()> {
+        let setup = &Setup::init("should_not_reorder_circular_operations")?;
+        let mut operations = vec![
+            setup.operation_from("1/1.txt", "2/2.txt"),
+            setup.operation_from("2/2.txt", "1/1.txt"),
+        ];
+        let err = reorder_operations(&mut operations).unwrap_err();
+        assert!(err.to_string().contains("--cycle-stage"));
+        Ok(())
+    }
+
+    #[test]
+    fn stage_circular_operations() -> Result<()> {
+        let setup = &Setup::init("stage_circular_operations")?;
+        std::fs::write(setup.sandbox.join("1/1.txt"), "1")?;
+        std::fs::write(setup.sandbox.join("2/2.txt"), "2")?;
+        std::fs::write(setup.sandbox.join("1/11/11.txt"), "11")?;
+        // Swap of 1.txt and 2.txt, and a chain waiting for them.
+        let mut operations = vec![
+            setup.operation_from("1/11/11.txt", "1/1.txt"),
+            setup.operation_from("1/1.txt", "2/2.txt"),
+            setup.operation_from("2/2.txt", "1/11/11.txt"),
+        ];
+        reorder_operations_with(&mut operations, true)?;
+        assert_eq!(operations.len(), 4);
+        let mut validated = Vec::new();
+        for o in operations {
+            is_operational(&validated, &o)?;
+            execute_operation(&o, &setup.args)?;
+            validated.push(o);
+        }
+        let read = |path: &str| std::fs::read_to_string(setup.sandbox.join(path));
+        assert_eq!(read("1/1.txt")?, "11");
+        assert_eq!(read("2/2.txt")?, "1");
+        assert_eq!(read("1/11/11.txt")?, "2");
+        assert_eq!(std::fs::read_dir(setup.sandbox.join("1/11"))?.count(), 1);
         Ok(())
     }
 
@@ -866,6 +7420,25 @@ mod lib {
         Ok(())
     }
 
+    #[test]
+    fn classify_rename_and_move() -> Result<()> {
+        let setup = &Setup::init("classify_rename_and_move")?;
+        let src = &setup.source_from("1/1.txt");
+        let renaming = setup.destination_from("1/renamed-1.txt");
+        let moving = setup.destination_from("2/moved-1.txt");
+        assert_eq!(move_kind(src, &renaming.path), OperationKind::Rename);
+        assert_eq!(move_kind(src, &moving.path), OperationKind::Move);
+        let operation = Operation {
+            kind: OperationKind::Rename,
+            src: src.to_owned(),
+            dst: renaming,
+        };
+        execute_operation(&operation, &setup.args)?;
+        assert!(operation.dst.path.is_file());
+        assert!(!operation.src.path.exists());
+        Ok(())
+    }
+
     #[test]
     fn rename_dir() -> Result<()> {
         let setup = &Setup::init("rename_dir")?;
@@ -906,6 +7479,604 @@ mod lib {
         Ok(())
     }
 
+    #[test]
+    fn resume_interrupted_operations() -> Result<()> {
+        let mut setup = Setup::init("resume_interrupted_operations")?;
+        let state_file = setup.sandbox.join("state");
+        setup.args.state_file = Some(state_file.to_owned());
+        let operations = vec![
+            setup.operation_from("1/1.txt", "3/1.txt"),
+            setup.operation_from("1/11/11.txt", "3/11.txt"),
+            setup.operation_from("2/2.txt", "3/2.txt"),
+            setup.operation_from("2/22/22.txt", "3/22.txt"),
+        ];
+        let mut state = StateFile::create(&state_file, &operations, 0)?;
+        let interrupted = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            for (i, o) in operations.iter().enumerate() {
+                if i == operations.len() / 2 {
+                    panic!("Interrupted");
+                }
+                execute_operation(o, &setup.args).unwrap();
+                state.complete(i + 1).unwrap();
+            }
+        }));
+        assert!(interrupted.is_err());
+        // Even with a line cut off by the interruption.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&state_file)?
+            .write_all(b"comp")?;
+        assert_eq!(load_state(&state_file)?.completed, 2);
+        setup.args.resume = true;
+        setup.args.no_lock = true;
+        assert_eq!(try_main(&setup.args)?.processed(), 2);
+        for o in operations.iter() {
+            assert!(o.dst.path.is_file());
+            assert!(!o.src.path.exists());
+        }
+        assert!(!state_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_conflicting_flags() -> Result<()> {
+        let setup = Setup::init("reject_conflicting_flags")?;
+        let base = CommandLine {
+            paths: vec![setup.sandbox.join("1").to_string_lossy().to_string()],
+            no_lock: true,
+            yes: true,
+            ..CommandLine::default()
+        };
+        let conflicts = [
+            CommandLine {
+                to_lowercase: true,
+                to_uppercase: true,
+                ..base.clone()
+            },
+            CommandLine {
+                organize: true,
+                flatten: Some(setup.sandbox.join("3")),
+                ..base.clone()
+            },
+            CommandLine {
+                sequence: true,
+                number: Some("{n}".to_owned()),
+                ..base.clone()
+            },
+            CommandLine {
+                interactive: true,
+                quiet: true,
+                ..base.clone()
+            },
+            CommandLine {
+                cleanup_state: true,
+                keep_state: true,
+                ..base.clone()
+            },
+            CommandLine {
+                wait_lock: true,
+                fail_on_lock: true,
+                ..base.clone()
+            },
+            CommandLine {
+                pager: true,
+                no_pager: true,
+                ..base.clone()
+            },
+            CommandLine {
+                resume: true,
+                ..base.clone()
+            },
+            CommandLine {
+                print0: true,
+                output_script: Some(PathBuf::from("-")),
+                ..base.clone()
+            },
+            // Timed out operations keep running, which might complete after rolling back.
+            CommandLine {
+                timeout: Some(std::time::Duration::from_secs(1)),
+                atomic: true,
+                ..base.clone()
+            },
+        ];
+        for args in conflicts.iter() {
+            let err = try_main(args).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<MooveError>(),
+                Some(MooveError::ConflictingFlags(_))
+            ));
+            assert_eq!(exit_code(&err), 2);
+        }
+        assert!(setup.sandbox.join("1").join("1.txt").is_file());
+        assert!(setup.sandbox.join("1").join("11").join("11.txt").is_file());
+        assert!(!setup.sandbox.join("3").exists());
+        assert!(base.validate().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn invert_operations() -> Result<()> {
+        let setup = Setup::init("invert_operations")?;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/moved-1.txt"),
+            setup.operation_from("2/2.txt", "2/renamed-2.txt"),
+            setup.operation_from("1/12", "2/moved-12"),
+        ];
+        for o in operations.iter() {
+            execute_operation(o, &setup.args)?;
+            let inverse = o.inverse()?;
+            assert_eq!(inverse.kind, o.kind);
+            assert_eq!(inverse.src.text, o.dst.text);
+            assert_eq!(inverse.dst.path, o.src.path);
+            execute_operation(&inverse, &setup.args)?;
+            assert!(o.src.path.exists());
+            assert!(!o.dst.path.exists());
+        }
+        assert!(setup.sandbox.join("1/12/12.txt").is_file());
+        let mut copy = setup.operation_from("1/1.txt", "1/copied-1.txt");
+        copy.kind = OperationKind::Copy;
+        execute_operation(&copy, &setup.args)?;
+        let inverse = copy.inverse()?;
+        assert_eq!(inverse.kind, OperationKind::Remove);
+        execute_operation(&inverse, &setup.args)?;
+        assert!(copy.src.path.is_file());
+        assert!(!copy.dst.path.exists());
+        assert!(matches!(
+            inverse.inverse(),
+            Err(MooveError::IrreversibleRemoval(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_on_failure() -> Result<()> {
+        let mut setup = Setup::init("rollback_on_failure")?;
+        setup.args.atomic = true;
+        let operations = vec![
+            setup.operation_from("1/1.txt", "3/1.txt"),
+            setup.operation_from("2/2.txt", "2/renamed-2.txt"),
+            // Fails since the parent of the destination is a file.
+            setup.operation_from("1/11/11.txt", "1/12/12.txt/11.txt"),
+        ];
+        let err = execute_operations(&operations, &setup.args, None).unwrap_err();
+        let rollback = err.downcast_ref::<RollbackError>().unwrap();
+        assert!(rollback.restored);
+        assert!(rollback.recovery_file.is_none());
+        for o in operations.iter() {
+            assert!(o.src.path.is_file());
+            assert!(!o.dst.path.exists());
+        }
+        assert_eq!(exit_code(&err), 3);
+        let failed = RollbackError {
+            restored: false,
+            recovery_file: None,
+        };
+        assert_eq!(exit_code(&failed.into()), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_only_executed_operations() -> Result<()> {
+        let mut setup = Setup::init("rollback_only_executed_operations")?;
+        setup.args.atomic = true;
+        setup.args.interactive = true;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/1.txt"),
+            setup.operation_from("2/2.txt", "2/renamed-2.txt"),
+            // Fails since the parent of the destination is a file.
+            setup.operation_from("1/11/11.txt", "1/12/12.txt/11.txt"),
+        ];
+        let err =
+            execute_operations_with(&operations, &setup.args, None, &mut "y\nn\ny\n".as_bytes())
+                .unwrap_err();
+        let rollback = err.downcast_ref::<RollbackError>().unwrap();
+        assert!(rollback.restored);
+        assert!(rollback.recovery_file.is_none());
+        assert_eq!(exit_code(&err), 3);
+        for o in operations.iter() {
+            assert!(o.src.path.is_file());
+            assert!(!o.dst.path.exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn keep_state_on_quit() -> Result<()> {
+        let mut setup = Setup::init("keep_state_on_quit")?;
+        let state_file = setup.sandbox.join("state");
+        setup.args.interactive = true;
+        setup.args.state_file = Some(state_file.to_owned());
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/11/11.txt", "1/11/b.txt"),
+            setup.operation_from("2/2.txt", "2/c.txt"),
+        ];
+        let report =
+            execute_operations_with(&operations, &setup.args, None, &mut "y\nq\n".as_bytes())?;
+        assert_eq!((report.files, report.declined), (1, 2));
+        let state = load_state(&state_file)?;
+        assert_eq!((state.completed, state.entries.len()), (1, 3));
+        setup.args.interactive = false;
+        setup.args.resume = true;
+        setup.args.no_lock = true;
+        assert_eq!(try_main(&setup.args)?.processed(), 2);
+        assert!(setup.sandbox.join("1/11/b.txt").is_file());
+        assert!(setup.sandbox.join("2/c.txt").is_file());
+        assert!(!state_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn write_records_separated_by_null() -> Result<()> {
+        let setup = Setup::init("write_records_separated_by_null")?;
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a b.txt"),
+            setup.operation_from("2/2.txt", "3/2\n.txt"),
+        ];
+        let mut records = Vec::new();
+        for o in operations.iter() {
+            write_record(&mut records, o)?;
+        }
+        let records = String::from_utf8(records)?;
+        let fields = records.split_terminator('\0').collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            operations
+                .iter()
+                .flat_map(|o| [o.src.text.as_str(), o.dst.text.as_str()])
+                .collect::<Vec<_>>()
+        );
+        assert!(fields[1].ends_with("a b.txt"));
+        assert!(fields[3].ends_with("2\n.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn lock_concurrent_invocations() -> Result<()> {
+        let setup = Setup::init("lock_concurrent_invocations")?;
+        let lock = acquire_lock(&setup.sandbox, &setup.args)?;
+        let err = acquire_lock(&setup.sandbox, &setup.args).err().unwrap();
+        assert!(err.downcast_ref::<LockError>().is_some());
+        let sandbox = setup.sandbox.clone();
+        let waiting = std::thread::spawn(move || {
+            let args = CommandLine {
+                wait_lock: true,
+                quiet: true,
+                ..Default::default()
+            };
+            acquire_lock(&sandbox, &args).map(|_| std::time::Instant::now())
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let released = std::time::Instant::now();
+        drop(lock);
+        let acquired = waiting.join().unwrap()?;
+        assert!(acquired >= released);
+        assert!(!setup.sandbox.join(LOCK_FILE_NAME).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn log_operations() -> Result<()> {
+        let setup = Setup::init("log_operations")?;
+        let log_file = setup.sandbox.join("log");
+        let mut log = OperationLog::open(&log_file)?;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/1.txt"),
+            setup.operation_from("1/1.txt", "3/2.txt"),
+        ];
+        for o in operations.iter() {
+            let result = execute_operation(o, &setup.args);
+            log.record(o, &result)?;
+        }
+        let text = std::fs::read_to_string(&log_file)?;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for (line, o) in lines.iter().zip(operations.iter()) {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            assert_eq!(fields.len(), 5);
+            assert_eq!(fields[1], "move");
+            assert_eq!(fields[2], o.src.abs.to_string_lossy());
+            assert_eq!(fields[3], o.dst.path.to_string_lossy());
+        }
+        assert_eq!(lines[0].rsplit('\t').next(), Some("ok"));
+        assert!(lines[1].rsplit('\t').next().unwrap().starts_with("error: "));
+        Ok(())
+    }
+
+    #[test]
+    fn log_operations_as_json_lines() -> Result<()> {
+        let setup = Setup::init("log_operations_as_json_lines")?;
+        let log_file = setup.sandbox.join("log.jsonl");
+        let operations = [
+            setup.operation_from("1/1.txt", "3/1.txt"),
+            setup.operation_from("1/11/11.txt", "1/11/a,\"b\".txt"),
+        ];
+        let mut log = OperationLog::open_with(&log_file, LogFormat::Jsonl, None)?;
+        for o in operations.iter() {
+            let result = execute_operation(o, &setup.args);
+            log.record(o, &result)?;
+        }
+        let text = std::fs::read_to_string(&log_file)?;
+        assert_eq!(text.lines().count(), 2);
+        for (line, o) in text.lines().zip(operations.iter()) {
+            let record: serde_json::Value = serde_json::from_str(line)?;
+            assert_eq!(record["kind"], "move");
+            assert_eq!(record["src"], o.src.abs.to_string_lossy().as_ref());
+            assert_eq!(record["dst"], o.dst.path.to_string_lossy().as_ref());
+            assert_eq!(record["result"], "ok");
+            assert!(record["ts"].as_str().unwrap().ends_with('Z'));
+        }
+        // Rotated before appending to the file exceeding the size.
+        let mut log = OperationLog::open_with(&log_file, LogFormat::Jsonl, Some(1))?;
+        let o = setup.operation_from("2/2.txt", "3/2.txt");
+        log.record(&o, &execute_operation(&o, &setup.args))?;
+        let rotated = std::fs::read_to_string(setup.sandbox.join("log.jsonl.1"))?;
+        assert_eq!(rotated, text);
+        let text = std::fs::read_to_string(&log_file)?;
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.contains("2.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn render_operations_as_csv() -> Result<()> {
+        let setup = Setup::init("render_operations_as_csv")?;
+        let mut removal = setup.operation_from("2/2.txt", "2/2.txt");
+        removal.kind = OperationKind::Remove;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/a,\"b\".txt"),
+            setup.operation_from("1/11", "3/c\td"),
+            setup.operation_from("1/12", "3/e\r\nf\\g"),
+            removal,
+        ];
+        let mut csv = Vec::new();
+        render_csv(&operations, ',', &mut csv)?;
+        let mut reader = csv::Reader::from_reader(&csv[..]);
+        assert_eq!(reader.headers()?, vec!["kind", "src", "dst"]);
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), operations.len());
+        for (record, o) in records.iter().zip(operations.iter()) {
+            assert_eq!(record.len(), 3);
+            assert_eq!(&record[0], o.kind.name());
+            assert_eq!(&record[1], o.src.text);
+        }
+        for i in 0..3 {
+            assert_eq!(&records[i][2], operations[i].dst.text);
+        }
+        assert_eq!(&records[3][2], "");
+        let mut tsv = Vec::new();
+        render_csv(&operations, '\t', &mut tsv)?;
+        assert_eq!(String::from_utf8(tsv.to_owned())?.lines().count(), 5);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .quoting(false)
+            .from_reader(&tsv[..]);
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), operations.len());
+        assert!(records.iter().all(|record| record.len() == 3));
+        for i in 0..3 {
+            let escaped = operations[i]
+                .dst
+                .text
+                .replace('\\', r"\\")
+                .replace('\t', r"\t")
+                .replace('\r', r"\r")
+                .replace('\n', r"\n");
+            assert_eq!(&records[i][2], escaped);
+        }
+        assert!(records[1][2].ends_with(r"c\td"));
+        Ok(())
+    }
+
+    #[test]
+    fn quote_for_shell() {
+        assert_eq!(shell_quote("1/1.txt"), "1/1.txt");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote(r#"it's "quoted""#), r#"'it'\''s "quoted"'"#);
+        assert_eq!(shell_quote("$HOME`*`"), "'$HOME`*`'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_shell_script() -> Result<()> {
+        let setup = Setup::init("output_shell_script")?;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/it's \"1\".txt"),
+            setup.operation_from("1/11", "3/$HOME 11"),
+            setup.operation_from("2/2.txt", "2/2 2.txt"),
+        ];
+        let script = setup.sandbox.join("script.sh");
+        output_script(&script, &operations)?;
+        let text = std::fs::read_to_string(&script)?;
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[..2], ["#!/bin/sh", "set -e"]);
+        assert!(lines[2].starts_with("mkdir -p -- "));
+        assert_eq!(lines.iter().filter(|l| l.starts_with("mv -- ")).count(), 3);
+        let status = std::process::Command::new("sh").arg(&script).status()?;
+        assert!(status.success());
+        for o in operations.iter() {
+            assert!(!o.src.path.exists());
+            assert!(o.dst.path.exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn collect_stats() -> Result<()> {
+        let setup = Setup::init("collect_stats")?;
+        std::fs::write(setup.sandbox.join("1/1.txt"), "1")?;
+        std::fs::write(setup.sandbox.join("2/21/211/211.txt"), "211")?;
+        let operations = [
+            setup.operation_from("1/1.txt", "3/4/1.txt"),
+            Operation {
+                kind: OperationKind::Copy,
+                ..setup.operation_from("2/21", "3/21")
+            },
+        ];
+        let report = execute_operations(&operations, &setup.args, None)?;
+        assert_eq!(report.files, 1);
+        assert_eq!(report.dirs, 1);
+        assert_eq!(report.bytes, 4);
+        assert_eq!(report.created_dirs, 2);
+        assert_eq!(report.processed(), 2);
+        assert!(report
+            .to_string()
+            .starts_with("Processed 1 file and 1 directory (4 B) in "));
+        assert_eq!(report.operations, operations);
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(
+            (&json["files"], &json["dry_run"]),
+            (&1.into(), &false.into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn confirm_operations() -> Result<()> {
+        let mut setup = Setup::init("confirm_operations")?;
+        setup.args.interactive = true;
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/11/11.txt", "1/11/b.txt"),
+            setup.operation_from("1/12/12.txt", "1/12/c.txt"),
+            setup.operation_from("2/2.txt", "2/d.txt"),
+        ];
+        let report = execute_operations_with(
+            &operations,
+            &setup.args,
+            None,
+            &mut "n\nwhat\ny\nq\n".as_bytes(),
+        )?;
+        assert_eq!(report.files, 1);
+        assert_eq!(report.declined, 3);
+        assert!(report
+            .to_string()
+            .ends_with(", skipped 3 declined operations"));
+        assert!(setup.sandbox.join("1/1.txt").is_file());
+        assert!(setup.sandbox.join("1/11/b.txt").is_file());
+        assert!(setup.sandbox.join("1/12/12.txt").is_file());
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/12/12.txt", "1/12/c.txt"),
+        ];
+        let report =
+            execute_operations_with(&operations, &setup.args, None, &mut "a\n".as_bytes())?;
+        assert_eq!(report.files, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn report_statistics() -> Result<()> {
+        let setup = Setup::init("report_statistics")?;
+        std::fs::write(setup.sandbox.join("1/1.txt"), "123")?;
+        std::fs::write(setup.sandbox.join("1/11/11.txt"), "12345")?;
+        let mut copy = setup.operation_from("1/1.txt", "3/copied-1.txt");
+        copy.kind = OperationKind::Copy;
+        let operations = [
+            copy,
+            setup.operation_from("1/11/11.txt", "1/11/a.txt"),
+            setup.operation_from("2/21", "3/21"),
+        ];
+        let report = execute_operations(&operations, &setup.args, None)?;
+        let stats = report.stats;
+        assert_eq!(stats.count, 3);
+        // Only the copied file is transferred, not the moved file nor directory.
+        assert_eq!(stats.bytes, 3);
+        assert!(stats.duration > std::time::Duration::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn prompt_to_confirm() -> Result<()> {
+        let setup = Setup::init("prompt_to_confirm")?;
+        let args = CommandLine::default();
+        let o = setup.operation_from("1/1.txt", "1/a.txt");
+        for (input, confirmation) in [
+            ("y\n", Confirmation::Yes),
+            ("What\nNo\n", Confirmation::No),
+            ("A\n", Confirmation::All),
+            ("quit\n", Confirmation::Quit),
+            // Not to ask again and again without input.
+            ("", Confirmation::Quit),
+        ] {
+            assert_eq!(
+                prompt_confirmation(&args, &o, &mut input.as_bytes())?,
+                confirmation
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn report_to_memory() -> Result<()> {
+        let mut setup = Setup::init("report_to_memory")?;
+        let reporter = std::sync::Arc::new(MemoryReporter::default());
+        setup.args.reporter = Some(reporter.clone());
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/11/11.txt", "3/b.txt"),
+        ];
+        execute_operations(&operations, &setup.args, None)?;
+        let messages = reporter.messages();
+        let done: Vec<&String> = messages
+            .iter()
+            .filter(|(level, _)| *level == Level::Done)
+            .map(|(_, message)| message)
+            .collect();
+        assert_eq!(done.len(), 2);
+        assert!(done[1].contains("b.txt"));
+        assert!(messages
+            .iter()
+            .any(|(level, message)| *level == Level::Verbose
+                && message.contains("Creating directory")));
+        Ok(())
+    }
+
+    #[test]
+    fn call_hooks() -> Result<()> {
+        #[derive(Debug, Default)]
+        struct Recorder {
+            planned: std::sync::Mutex<usize>,
+            done: std::sync::Mutex<Vec<(PathBuf, bool)>>,
+        }
+        impl Hooks for Recorder {
+            fn on_plan(&self, operations: &[Operation]) {
+                *self.planned.lock().unwrap() = operations.len();
+            }
+            fn on_before(&self, o: &Operation) -> HookDecision {
+                match o.src.path.file_name().unwrap().to_str() {
+                    Some("11.txt") => HookDecision::Skip,
+                    Some("2.txt") => HookDecision::Abort,
+                    _ => HookDecision::Proceed,
+                }
+            }
+            fn on_after(&self, o: &Operation, result: &Result<()>) {
+                let done = &mut self.done.lock().unwrap();
+                done.push((o.dst.path.to_owned(), result.is_ok()));
+            }
+        }
+        let mut setup = Setup::init("call_hooks")?;
+        let recorder = std::sync::Arc::new(Recorder::default());
+        setup.args.hooks = Some(recorder.clone());
+        let operations = [
+            setup.operation_from("1/1.txt", "1/a.txt"),
+            setup.operation_from("1/11/11.txt", "1/11/b.txt"),
+            setup.operation_from("2/2.txt", "2/c.txt"),
+            setup.operation_from("2/22/22.txt", "2/22/d.txt"),
+        ];
+        let report = execute_operations(&operations, &setup.args, None)?;
+        assert_eq!(*recorder.planned.lock().unwrap(), 4);
+        assert_eq!(
+            *recorder.done.lock().unwrap(),
+            vec![(operations[0].dst.path.to_owned(), true)]
+        );
+        assert_eq!((report.files, report.declined), (1, 3));
+        assert!(setup.sandbox.join("1/11/11.txt").is_file());
+        assert!(setup.sandbox.join("2/22/22.txt").is_file());
+        Ok(())
+    }
+
     #[test]
     fn dry_run() -> Result<()> {
         let mut setup = Setup::init("dry_run")?;
@@ -9,54 +9,103 @@ use std::io::IsTerminal;
 #[doc(hidden)]
 fn main() {
     let mut args = CommandLine::parse();
-    if let Ok(env) = std::env::var("MOOVE_OPTIONS") {
-        let env_args = CommandLine::parse_from(
-            std::env::args()
-                .take(1)
-                .chain(env.split_ascii_whitespace().map(|o| o.to_string())),
-        );
-        args.dry_run = args.dry_run || env_args.dry_run;
-        args.verbose = args.verbose || env_args.verbose;
-        args.quiet = args.quiet || env_args.quiet;
-        args.absolute = args.absolute || env_args.absolute;
-        args.directory = args.directory || env_args.directory;
-        args.with_hidden = args.with_hidden || env_args.with_hidden;
+    let env = std::env::var("MOOVE_OPTIONS").ok().filter(|_| !args.no_env);
+    if let Some(env) = env {
+        let cli: Vec<std::ffi::OsString> = std::env::args_os().collect();
+        match merge_env_options(&shell_split(&env), &cli) {
+            Ok((merged, dropped)) => {
+                for option in dropped {
+                    args.reporter().warn(&format!(
+                        "Ignoring {} in MOOVE_OPTIONS, not to conflict with the command line.",
+                        option.yellow()
+                    ));
+                }
+                args = merged;
+            }
+            Err(err) => {
+                args.reporter().error(&format!(
+                    "Invalid MOOVE_OPTIONS. {}\n{}",
+                    env.yellow().underline(),
+                    err.render()
+                ));
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = &args.from_file {
+        match read_paths_from_file(path) {
+            Ok(mut paths) => args.paths.append(&mut paths),
+            Err(err) => {
+                args.reporter().error(&format!("{:?}", err));
+                std::process::exit(2);
+            }
+        }
     }
     let stdin = std::io::stdin();
-    if !stdin.is_terminal() {
+    if args.from_file.as_deref() == Some(std::path::Path::new("-")) {
+        // Stdin is consumed for paths, hence cannot be asked.
         args.oops = true;
-        let mut line = String::new();
-        while let Ok(size) = stdin.read_line(&mut line) {
-            if size == 0 {
-                break;
-            }
-            args.paths
-                .push(line.trim_end_matches(['\r', '\n']).to_owned());
-            line.clear();
+    } else if !stdin.is_terminal() {
+        args.oops = true;
+        let read = if args.null_separated {
+            read_null_separated_paths(stdin.lock())
+                .map(|mut paths| args.literal_paths.append(&mut paths))
+        } else {
+            read_paths(stdin.lock()).map(|mut paths| args.paths.append(&mut paths))
+        };
+        if let Err(err) = read {
+            args.reporter().error(&format!("{:?}", err));
+            std::process::exit(2);
         }
     }
-    if args.paths.is_empty() {
+    if args.paths.is_empty() && args.literal_paths.is_empty() {
         args.paths.push(".".to_owned());
     }
+    args.reporter = Some(args.terminal_reporter());
+    let reporter = args.reporter();
+    // Stop after the current operation, or exit at once otherwise, e.g. at prompts.
+    let interrupted = ctrlc::set_handler(|| {
+        if OPERATING.load(std::sync::atomic::Ordering::SeqCst) {
+            INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+        } else {
+            // Exiting skips dropping the lock guard.
+            remove_held_lock();
+            std::process::exit(130);
+        }
+    });
+    if let Err(err) = interrupted {
+        reporter.warn(&format!("Failed to install the signal handler. {}", err));
+    }
     match try_main(&args) {
         Err(err) => {
-            if !args.quiet {
-                eprintln!("{} {:?}", "Error:".bright_red().bold(), err);
-            }
-            std::process::exit(2);
+            reporter.error(&format!("{:?}", err));
+            std::process::exit(exit_code(&err));
         }
-        Ok(processed) => {
-            if !args.quiet {
-                if processed == 0 {
-                    println!("{} {}", "Info:".bright_cyan(), "Nothing to do".dimmed());
-                } else {
-                    println!(
-                        "{} Processed total {}",
-                        "Success:".green().bold(),
-                        processed.to_string().cyan()
-                    );
+        Ok(_) if args.check => {}
+        Ok(report) => {
+            if report.processed() == 0 {
+                reporter.info(&format!(
+                    "{} {}",
+                    "Info:".bright_cyan(),
+                    "Nothing to do".dimmed()
+                ));
+            } else {
+                reporter.info(&format!("{} {}", "Success:".green().bold(), report));
+                if args.stat {
+                    reporter.info(&report.stats.display());
                 }
             }
+            for o in report.skipped_operations.iter() {
+                reporter.info(&format!(
+                    "{} {} → {}",
+                    "Skipped:".yellow(),
+                    o.src.text.underline(),
+                    o.dst.text.underline()
+                ));
+            }
+            if report.processed() == 0 && args.fail_on_nothing {
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,130 @@
+//! Sinks of messages for humans, injected by `CommandLine::reporter`.
+
+use std::sync::Mutex;
+
+use colored::*;
+
+use crate::Operation;
+
+/// Receiver of messages about sources and operations.
+///
+/// Messages may be colored, while dialogs with the user like prompts do not go through it.
+pub trait Reporter: std::fmt::Debug + Send + Sync {
+    /// Message always shown unless quiet, e.g. a summary or dry-run output.
+    fn info(&self, message: &str);
+    /// Detail of progress, shown with `--verbose`.
+    fn verbose(&self, message: &str);
+    /// Problem which does not stop operations.
+    fn warn(&self, message: &str);
+    /// Failure reported by the application.
+    fn error(&self, message: &str);
+    /// Operation completed successfully, described by the message.
+    fn operation_done(&self, o: &Operation, message: &str);
+}
+
+/// Reporter printing to the terminal, to stderr if stdout is occupied by machine-readable output.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalReporter {
+    pub verbose: bool,
+    pub stdout_occupied: bool,
+}
+
+impl TerminalReporter {
+    fn print(&self, message: &str) {
+        if self.stdout_occupied {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+impl Reporter for TerminalReporter {
+    fn info(&self, message: &str) {
+        self.print(message);
+    }
+
+    fn verbose(&self, message: &str) {
+        if self.verbose {
+            self.print(message);
+        }
+    }
+
+    fn warn(&self, message: &str) {
+        self.print(&format!("{} {}", "Warning:".yellow().bold(), message));
+    }
+
+    fn error(&self, message: &str) {
+        eprintln!("{} {}", "Error:".bright_red().bold(), message);
+    }
+
+    fn operation_done(&self, _o: &Operation, message: &str) {
+        self.print(message);
+    }
+}
+
+/// Reporter discarding all messages, for `--quiet`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuietReporter;
+
+impl Reporter for QuietReporter {
+    fn info(&self, _message: &str) {}
+    fn verbose(&self, _message: &str) {}
+    fn warn(&self, _message: &str) {}
+    fn error(&self, _message: &str) {}
+    fn operation_done(&self, _o: &Operation, _message: &str) {}
+}
+
+/// Kind of a message recorded by `MemoryReporter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Verbose,
+    Warn,
+    Error,
+    Done,
+}
+
+/// Reporter recording all messages in memory, e.g. for tests and library users.
+#[derive(Debug, Default)]
+pub struct MemoryReporter {
+    messages: Mutex<Vec<(Level, String)>>,
+}
+
+impl MemoryReporter {
+    /// Recorded messages in the order of reports.
+    pub fn messages(&self) -> Vec<(Level, String)> {
+        self.messages
+            .lock()
+            .map(|messages| messages.clone())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, level: Level, message: &str) {
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push((level, message.to_owned()));
+        }
+    }
+}
+
+impl Reporter for MemoryReporter {
+    fn info(&self, message: &str) {
+        self.record(Level::Info, message);
+    }
+
+    fn verbose(&self, message: &str) {
+        self.record(Level::Verbose, message);
+    }
+
+    fn warn(&self, message: &str) {
+        self.record(Level::Warn, message);
+    }
+
+    fn error(&self, message: &str) {
+        self.record(Level::Error, message);
+    }
+
+    fn operation_done(&self, _o: &Operation, message: &str) {
+        self.record(Level::Done, message);
+    }
+}
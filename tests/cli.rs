@@ -0,0 +1,299 @@
+//! This test runs the executable to check its exit codes.
+//! - Paths are given from stdin, which also makes it abort in case of collision.
+//! - The editor is replaced with a shell script, which is given the file to edit as `$1`.
+
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use assert_cmd::Command;
+
+/// Create `a.txt` and `b.txt` in an empty sandbox directory.
+fn sandbox(key: &str) -> Result<PathBuf> {
+    let sandbox = std::env::temp_dir().join("moove").join("cli").join(key);
+    if sandbox.exists() {
+        std::fs::remove_dir_all(&sandbox)?;
+    }
+    std::fs::create_dir_all(&sandbox)?;
+    std::fs::write(sandbox.join("a.txt"), "a")?;
+    std::fs::write(sandbox.join("b.txt"), "b")?;
+    Ok(sandbox)
+}
+
+/// Run moove for `a.txt` and `b.txt` with the editor running the script.
+fn moove(sandbox: &Path, script: &str, args: &[&str]) -> Result<Command> {
+    use std::os::unix::fs::PermissionsExt;
+    let editor = sandbox.join("editor.sh");
+    std::fs::write(&editor, format!("#!/bin/sh\n{}\n", script))?;
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755))?;
+    let mut cmd = Command::cargo_bin("moove")?;
+    cmd.current_dir(sandbox)
+        .args(args)
+        .env("VISUAL", &editor)
+        .env("EDITOR", &editor)
+        .env_remove("MOOVE_OPTIONS")
+        .env_remove("MOOVE_STATE_FILE")
+        .env_remove("MOOVE_LOG")
+        .env_remove("MOOVE_EDITOR")
+        .env_remove("MOOVE_EDITOR_ARGS")
+        .write_stdin("a.txt\nb.txt\n");
+    Ok(cmd)
+}
+
+#[test]
+fn exit_with_success() -> Result<()> {
+    let sandbox = sandbox("exit_with_success")?;
+    moove(&sandbox, r#"printf "c.txt\nd.txt\n" > "$1""#, &[])?
+        .assert()
+        .code(0);
+    assert!(sandbox.join("c.txt").is_file());
+    assert!(sandbox.join("d.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn exit_with_nothing_to_do() -> Result<()> {
+    let sandbox = sandbox("exit_with_nothing_to_do")?;
+    moove(&sandbox, "true", &[])?.assert().code(0);
+    moove(&sandbox, "true", &["--fail-on-nothing"])?
+        .assert()
+        .code(1);
+    Ok(())
+}
+
+#[test]
+fn exit_with_collision() -> Result<()> {
+    let sandbox = sandbox("exit_with_collision")?;
+    moove(&sandbox, r#"printf "c.txt\nc.txt\n" > "$1""#, &[])?
+        .assert()
+        .code(2);
+    assert!(sandbox.join("a.txt").is_file());
+    assert!(sandbox.join("b.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn exit_with_filesystem_error() -> Result<()> {
+    let sandbox = sandbox("exit_with_filesystem_error")?;
+    // Source disappears while editing.
+    moove(&sandbox, r#"printf "c.txt\nd.txt\n" > "$1"; rm b.txt"#, &[])?
+        .assert()
+        .code(3);
+    Ok(())
+}
+
+#[test]
+fn exit_with_abort() -> Result<()> {
+    let sandbox = sandbox("exit_with_abort")?;
+    moove(&sandbox, "exit 1", &[])?.assert().code(4);
+    assert!(sandbox.join("a.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn skip_sources_of_deleted_lines() -> Result<()> {
+    let sandbox = sandbox("skip_sources_of_deleted_lines")?;
+    let script = r#"sed -i -e '/^b\.txt/d' -e 's/\ta\.txt$/\tc.txt/' "$1""#;
+    let assert = moove(&sandbox, script, &["--allow-removal"])?
+        .assert()
+        .code(0);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    assert!(stdout.contains("skipped 1 source of deleted lines"));
+    assert!(sandbox.join("b.txt").is_file());
+    assert!(sandbox.join("c.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn ignore_env_options() -> Result<()> {
+    let sandbox = sandbox("ignore_env_options")?;
+    let script = r#"printf "c.txt\nd.txt\n" > "$1""#;
+    moove(&sandbox, script, &[])?
+        .env("MOOVE_OPTIONS", "--dry-run")
+        .assert()
+        .code(0);
+    assert!(sandbox.join("a.txt").is_file());
+    moove(&sandbox, script, &["--no-env"])?
+        .env("MOOVE_OPTIONS", "--dry-run")
+        .assert()
+        .code(0);
+    assert!(sandbox.join("c.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn check_operations() -> Result<()> {
+    let sandbox = sandbox("check_operations")?;
+    moove(&sandbox, r#"printf "c.txt\nc.txt\n" > "$1""#, &["--check"])?
+        .assert()
+        .code(1);
+    moove(&sandbox, r#"printf "c.txt\nd.txt\n" > "$1""#, &["--check"])?
+        .assert()
+        .code(0);
+    assert!(sandbox.join("a.txt").is_file());
+    assert!(sandbox.join("b.txt").is_file());
+    assert!(!sandbox.join("c.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn merge_env_options() -> Result<()> {
+    let sandbox = sandbox("merge_env_options")?;
+    moove(&sandbox, r#"printf "c.txt\n" > "$1""#, &["--oops"])?
+        .env("MOOVE_OPTIONS", r#"--exclude-pattern 'b\.txt$' --copy"#)
+        .assert()
+        .code(0);
+    assert!(sandbox.join("a.txt").is_file());
+    assert!(sandbox.join("b.txt").is_file());
+    assert!(sandbox.join("c.txt").is_file());
+    let assert = moove(&sandbox, "true", &[])?
+        .env("MOOVE_OPTIONS", "--no-such-option")
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).to_string();
+    assert!(stderr.contains("MOOVE_OPTIONS"));
+    Ok(())
+}
+
+#[test]
+fn drop_env_options_conflicting_with_command_line() -> Result<()> {
+    let sandbox = sandbox("drop_env_options_conflicting_with_command_line")?;
+    // Conflicting for clap, and for validation of the command line.
+    let assert = moove(&sandbox, "exit 1", &["--flatten=x", "--shuffle"])?
+        .env("MOOVE_OPTIONS", "--organize --sort --copy a.txt")
+        .assert()
+        .code(0);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).to_string();
+    for option in ["--organize", "--sort", "paths"] {
+        assert!(stdout.contains(&format!("Ignoring {} in MOOVE_OPTIONS", option)));
+    }
+    assert!(!stdout.contains("--copy"));
+    for name in ["a.txt", "b.txt"] {
+        assert!(sandbox.join(name).is_file());
+        assert!(sandbox.join("x").join(name).is_file());
+    }
+    Ok(())
+}
+
+#[test]
+fn leave_no_lock_file() -> Result<()> {
+    let sandbox = sandbox("leave_no_lock_file")?;
+    moove(&sandbox, "true", &["--dry-run"])?.assert().code(0);
+    assert!(!sandbox.join(".moove.lock").exists());
+    moove(&sandbox, r#"printf "c.txt\nd.txt\n" > "$1""#, &[])?
+        .assert()
+        .code(0);
+    assert!(!sandbox.join(".moove.lock").exists());
+    Ok(())
+}
+
+#[test]
+fn skip_lock_file_in_sources() -> Result<()> {
+    let sandbox = sandbox("skip_lock_file_in_sources")?;
+    // The editor fails if the lock file is listed.
+    moove(&sandbox, r#"! grep -q moove.lock "$1""#, &["--with-hidden"])?
+        .write_stdin(".\n")
+        .assert()
+        .code(0);
+    Ok(())
+}
+
+#[test]
+fn swap_with_cycle_stage() -> Result<()> {
+    let sandbox = sandbox("swap_with_cycle_stage")?;
+    let script = r#"printf "b.txt\na.txt\n" > "$1""#;
+    moove(&sandbox, script, &[])?.assert().code(2);
+    moove(&sandbox, script, &["--cycle-stage"])?
+        .assert()
+        .code(0);
+    assert_eq!(std::fs::read_to_string(sandbox.join("a.txt"))?, "b");
+    assert_eq!(std::fs::read_to_string(sandbox.join("b.txt"))?, "a");
+    assert!(!sandbox.join(".a.txt.moove-stage").exists());
+    Ok(())
+}
+
+#[test]
+fn keep_percent_in_typed_names() -> Result<()> {
+    let sandbox = sandbox("keep_percent_in_typed_names")?;
+    std::fs::write(sandbox.join("100%E9.txt"), "")?;
+    // `%E9%41` typed in the line of a valid name is not decoded.
+    moove(&sandbox, r#"printf "100%%E9%%41.txt\n" > "$1""#, &[])?
+        .write_stdin("100%E9.txt\n")
+        .assert()
+        .code(0);
+    assert!(sandbox.join("100%E9%41.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn reject_timeout_with_atomic() -> Result<()> {
+    let sandbox = sandbox("reject_timeout_with_atomic")?;
+    // Timed out operations keep running, which might complete after rolling back.
+    moove(
+        &sandbox,
+        r#"printf "c.txt\nd.txt\n" > "$1""#,
+        &["--timeout=1s", "--atomic"],
+    )?
+    .assert()
+    .code(2);
+    Ok(())
+}
+
+#[test]
+fn copy_into_dest_dir_without_editing() -> Result<()> {
+    let sandbox = sandbox("copy_into_dest_dir_without_editing")?;
+    moove(&sandbox, "exit 1", &["--dest-dir=x/y", "--copy"])?
+        .assert()
+        .code(0);
+    assert_eq!(std::fs::read_to_string(sandbox.join("x/y/a.txt"))?, "a");
+    assert_eq!(std::fs::read_to_string(sandbox.join("x/y/b.txt"))?, "b");
+    assert!(sandbox.join("a.txt").is_file());
+    moove(&sandbox, "exit 1", &["--dest-dir=z", "--no-create-dir"])?
+        .assert()
+        .code(3);
+    moove(&sandbox, "exit 1", &["--dest-dir=z", "--add-prefix=_"])?
+        .assert()
+        .code(2);
+    assert!(sandbox.join("a.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn exit_on_sigint_at_editor() -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    let sandbox = sandbox("exit_on_sigint_at_editor")?;
+    let editor = sandbox.join("editor.sh");
+    std::fs::write(&editor, "#!/bin/sh\nsleep 3\n")?;
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755))?;
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("moove"))
+        .current_dir(&sandbox)
+        .env("EDITOR", &editor)
+        .env("VISUAL", &editor)
+        .env_remove("MOOVE_OPTIONS")
+        .env_remove("MOOVE_EDITOR")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    // NOTE Can be unwrapped safely, stdin is piped.
+    child.stdin.take().unwrap().write_all(b"a.txt\nb.txt\n")?;
+    // The signal handler is installed before locking.
+    let lock = sandbox.join(".moove.lock");
+    for _ in 0..100 {
+        if lock.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(lock.exists());
+    assert_eq!(
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) },
+        0
+    );
+    assert_eq!(child.wait()?.code(), Some(130));
+    assert!(!lock.exists());
+    assert!(sandbox.join("a.txt").is_file());
+    Ok(())
+}
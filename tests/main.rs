@@ -42,8 +42,8 @@ impl Setup {
     fn init(key: &str) -> Result<Setup> {
         let sandbox = &std::env::temp_dir().join("moove").join("tests").join(key);
         std::fs::create_dir_all(sandbox)?;
-        std::env::set_current_dir(&sandbox)?;
-        let dirs: Vec<PathBuf> = vec!["1", "1/11", "1/12", "2", "2/21", "2/21/211", "2/22"]
+        std::env::set_current_dir(sandbox)?;
+        let dirs: Vec<PathBuf> = ["1", "1/11", "1/12", "2", "2/21", "2/21/211", "2/22"]
             .iter()
             .map(|d| {
                 sandbox.join(PathBuf::from(if cfg!(target_family = "windows") {
@@ -93,10 +93,13 @@ impl Setup {
                 .context(format!("Failed to normalize {:?}", path))
                 .unwrap()
                 .into_path_buf(),
-            meta: path
-                .metadata()
-                .context(format!("Failed to get metadata {:?}", path))
-                .unwrap(),
+            meta: SourceMeta::from(
+                &path
+                    .metadata()
+                    .context(format!("Failed to get metadata {:?}", path))
+                    .unwrap(),
+            ),
+            inode: None,
         }
     }
 
@@ -106,10 +109,7 @@ impl Setup {
         } else {
             s.to_string()
         });
-        Destination {
-            text: path.to_string_lossy().to_string(),
-            path: path.to_owned(),
-        }
+        Destination::new(path.to_string_lossy().to_string(), path.to_owned()).unwrap()
     }
 
     fn operation_from(&self, src: &str, dst: &str) -> Operation {
@@ -123,7 +123,7 @@ impl Setup {
 
 impl Drop for Setup {
     fn drop(&mut self) {
-        if let Err(_) = std::env::set_current_dir("..") {
+        if std::env::set_current_dir("..").is_err() {
             return println!(
                 "Failed to change current directory to the parent {}",
                 "..".yellow().underline()
@@ -230,3 +230,149 @@ fn rel_move_and_rename_directory() -> Result<()> {
     assert!(!operation.src.path.is_dir());
     Ok(())
 }
+
+#[test]
+#[serial]
+fn rel_move_file_in_current_dir_with_force_copy() -> Result<()> {
+    let setup = &Setup::init("move_file_in_current_dir_with_force_copy")?;
+    std::fs::write("0.txt", "0")?;
+    let operation = &setup.operation_from("0.txt", "1/moved-0.txt");
+    let args = &CommandLine {
+        force_copy: true,
+        ..setup.args.clone()
+    };
+    execute_move_or_copy(operation, args)?;
+    assert_eq!(std::fs::read_to_string(&operation.dst.path)?, "0");
+    assert!(!operation.src.path.exists());
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rel_stop_on_interrupt() -> Result<()> {
+    let mut setup = Setup::init("stop_on_interrupt")?;
+    setup.args.paths.push("2".to_owned());
+    setup.args.flatten = Some(PathBuf::from("1"));
+    setup.args.no_lock = true;
+    // As set by the signal handler of the application during the first operation.
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+    assert!(!OPERATING.load(std::sync::atomic::Ordering::SeqCst));
+    let args = std::mem::take(&mut setup.args);
+    let err = std::thread::spawn(move || try_main(&args))
+        .join()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(exit_code(&err), 130);
+    let interrupted = err.downcast_ref::<InterruptedError>().unwrap();
+    assert_eq!((interrupted.completed, interrupted.pending), (1, 2));
+    assert!(!INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst));
+    assert!(!OPERATING.load(std::sync::atomic::Ordering::SeqCst));
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rel_edit_with_injected_editor() -> Result<()> {
+    let mut setup = Setup::init("edit_with_injected_editor")?;
+    setup.args.paths.push("1".to_owned());
+    setup.args.no_lock = true;
+    let edited = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let texts = edited.clone();
+    let editor = move |text: &str| -> Result<String> {
+        texts.lock().unwrap().push(text.to_owned());
+        Ok(text.replace("1.txt", "renamed.txt"))
+    };
+    assert_eq!(
+        try_main_with(&setup.args, std::sync::Arc::new(editor))?.processed(),
+        1
+    );
+    assert_eq!(edited.lock().unwrap().len(), 1);
+    assert!(PathBuf::from("1/renamed.txt").is_file());
+    let abort =
+        |_: &str| -> Result<String> { Err(anyhow::Error::msg("quit").context(Failure::Aborted)) };
+    let err = try_main_with(&setup.args, std::sync::Arc::new(abort)).unwrap_err();
+    assert_eq!(exit_code(&err), 4);
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rel_lock_concurrent_invocations() -> Result<()> {
+    use std::sync::{Arc, Barrier, Mutex};
+    let mut setup = Setup::init("lock_concurrent_invocations")?;
+    setup.args.quiet = true;
+    let edited = Arc::new(Mutex::new(Vec::new()));
+    // The first invocation holds the lock while editing until released.
+    let (entered, release) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+    let first = {
+        let args = CommandLine {
+            paths: vec!["1".to_owned()],
+            ..setup.args.clone()
+        };
+        let (entered, release, edited) = (entered.clone(), release.clone(), edited.clone());
+        let editor = move |text: &str| -> Result<String> {
+            entered.wait();
+            release.wait();
+            edited.lock().unwrap().push("first");
+            Ok(text.replace("1/1.txt", "1/a.txt"))
+        };
+        std::thread::spawn(move || try_main_with(&args, Arc::new(editor)))
+    };
+    entered.wait();
+    let args = CommandLine {
+        paths: vec!["2".to_owned()],
+        ..setup.args.clone()
+    };
+    let err = try_main_with(&args, Arc::new(|text: &str| Ok(text.to_owned()))).unwrap_err();
+    assert_eq!(exit_code(&err), 5);
+    let second = {
+        let args = CommandLine {
+            wait_lock: true,
+            ..args
+        };
+        let edited = edited.clone();
+        let editor = move |text: &str| -> Result<String> {
+            edited.lock().unwrap().push("second");
+            Ok(text.replace("2/2.txt", "2/b.txt"))
+        };
+        std::thread::spawn(move || try_main_with(&args, Arc::new(editor)))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(edited.lock().unwrap().is_empty());
+    release.wait();
+    assert_eq!(first.join().unwrap()?.processed(), 1);
+    assert_eq!(second.join().unwrap()?.processed(), 1);
+    assert_eq!(*edited.lock().unwrap(), ["first", "second"]);
+    assert!(PathBuf::from("1/a.txt").is_file() && !PathBuf::from("1/1.txt").exists());
+    assert!(PathBuf::from("2/b.txt").is_file() && !PathBuf::from("2/2.txt").exists());
+    assert!(!PathBuf::from(LOCK_FILE_NAME).exists());
+    Ok(())
+}
+
+#[test]
+#[serial]
+fn rel_redo_with_injected_editor() -> Result<()> {
+    let mut setup = Setup::init("redo_with_injected_editor")?;
+    setup.args.paths.push("1".to_owned());
+    setup.args.no_lock = true;
+    let edited = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let texts = edited.clone();
+    let editor = move |text: &str| -> Result<String> {
+        let mut texts = texts.lock().unwrap();
+        texts.push(text.to_owned());
+        Ok(match texts.len() {
+            // Colliding with the directory left alone.
+            1 => text.replace("1/1.txt", "1/11"),
+            _ => texts[0].replace("1.txt", "renamed.txt"),
+        })
+    };
+    assert_eq!(
+        try_main_with(&setup.args, std::sync::Arc::new(editor))?.processed(),
+        1
+    );
+    let edited = edited.lock().unwrap();
+    assert_eq!(edited.len(), 2);
+    assert!(edited[1].starts_with("# ERROR"));
+    assert!(PathBuf::from("1/renamed.txt").is_file());
+    Ok(())
+}